@@ -0,0 +1,304 @@
+//! Access to Cargo's local registry cache.
+
+use crate::config;
+use crate::index;
+use crate::prefetch_lock::LockedPackage;
+use failure::{bail, format_err, Fallible, ResultExt};
+use serde_derive::Serialize;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::info;
+
+/// Returns the path to Cargo's home directory.
+pub fn cargo_home() -> Fallible<PathBuf> {
+    home::cargo_home().map_err(|e| format_err!("Failed to determine Cargo home: {}", e))
+}
+
+/// A lock on Cargo's package cache, which is released when dropped.
+pub struct CacheLock {
+    _files: Vec<File>,
+}
+
+/// Take the same lock that Cargo uses while it changes the package cache.
+///
+/// Cargo takes `.package-cache` while downloading, and newer versions of
+/// Cargo take `.package-cache-mutate` while reading or deleting files. Set
+/// `mutate` to take both, such as before deleting files.
+///
+/// This blocks while Cargo is running, so it must not be held while running
+/// Cargo.
+pub fn lock_package_cache(mutate: bool) -> Fallible<CacheLock> {
+    lock_package_cache_in(&cargo_home()?, mutate)
+}
+
+/// Take the package cache lock of another Cargo home, like
+/// [`lock_package_cache`].
+pub fn lock_package_cache_in(home: &Path, mutate: bool) -> Fallible<CacheLock> {
+    fs::create_dir_all(home)?;
+    let mut names = vec![".package-cache"];
+    if mutate {
+        names.push(".package-cache-mutate");
+    }
+    let mut files = Vec::new();
+    for name in names {
+        let path = home.join(name);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|_| format!("Failed to open `{}`.", path.display()))?;
+        if file.try_lock().is_err() {
+            info!("Blocking waiting for file lock on package cache");
+            file.lock()
+                .with_context(|_| format!("Failed to lock `{}`.", path.display()))?;
+        }
+        files.push(file);
+    }
+    Ok(CacheLock { _files: files })
+}
+
+/// Returns the directories within `registry/cache` (one per registry).
+pub fn registry_cache_dirs() -> Fallible<Vec<PathBuf>> {
+    let cache = cargo_home()?.join("registry").join("cache");
+    let mut dirs = Vec::new();
+    if !cache.exists() {
+        return Ok(dirs);
+    }
+    for entry in fs::read_dir(&cache)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            dirs.push(path);
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// A `.crate` file in the cache.
+pub struct CachedCrate {
+    pub name: String,
+    pub version: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// Returns the `.crate` files in a directory within `registry/cache`.
+pub fn cached_crates(dir: &Path) -> Fallible<Vec<CachedCrate>> {
+    let mut crates = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let (name, version) = match file_name.to_str().and_then(parse_crate_file_name) {
+            Some(parsed) => parsed,
+            None => continue,
+        };
+        let metadata = entry.metadata()?;
+        crates.push(CachedCrate {
+            name: name.to_string(),
+            version: version.to_string(),
+            path: entry.path(),
+            size: metadata.len(),
+            modified: metadata.modified()?,
+        });
+    }
+    crates.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.version.cmp(&b.version)));
+    Ok(crates)
+}
+
+/// Split a file name such as `serde-1.0.0.crate` into the name and version.
+///
+/// Both names and versions may contain `-`, so the version starts at the
+/// first `-` where the rest is a valid version.
+pub fn parse_crate_file_name(file_name: &str) -> Option<(&str, &str)> {
+    let stem = file_name.strip_suffix(".crate")?;
+    stem.match_indices('-')
+        .map(|(i, _)| (&stem[..i], &stem[i + 1..]))
+        .find(|(name, version)| !name.is_empty() && semver::Version::parse(version).is_ok())
+}
+
+/// Returns the directory with the extracted source of a cached crate, in
+/// `registry/src`.
+pub fn src_dir(krate: &CachedCrate) -> Fallible<PathBuf> {
+    let registry = krate.path.parent().unwrap().file_name().unwrap();
+    Ok(cargo_home()?
+        .join("registry")
+        .join("src")
+        .join(registry)
+        .join(format!("{}-{}", krate.name, krate.version)))
+}
+
+/// Format a number of bytes, such as `1.5 GiB`.
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Find the `.crate` file for the given package in the cache.
+pub fn find_crate_file(name: &str, version: &str) -> Fallible<Option<PathBuf>> {
+    let file_name = format!("{}-{}.crate", name, version);
+    for dir in registry_cache_dirs()? {
+        let path = dir.join(&file_name);
+        if path.exists() {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+/// The names current versions of Cargo use for the crates.io registry
+/// directories, for each protocol. The part after the host is a hash of the
+/// index URL, which older versions of Cargo computed differently.
+const CRATES_IO_DIRS: [(&str, &str); 2] = [
+    ("sparse", "index.crates.io-1949cf8c6b5b557f"),
+    ("git", "github.com-25cdd57fae9f0462"),
+];
+
+/// Returns the directory in `registry/cache` for crates.io.
+///
+/// The protocol is `registries.crates-io.protocol` from Cargo's config. If
+/// it isn't set, the sparse index is preferred, but the git index's
+/// directory is used if it is the only one that exists. A directory from an
+/// older version of Cargo is used if the current name doesn't exist.
+pub fn crates_io_cache_dir() -> Fallible<PathBuf> {
+    let cache = cargo_home()?.join("registry").join("cache");
+    let protocol = env::var(config::CRATES_IO_PROTOCOL_ENV).ok();
+    let names: Vec<&str> = CRATES_IO_DIRS
+        .iter()
+        .filter(|(p, _)| protocol.as_deref().is_none_or(|protocol| protocol == *p))
+        .map(|(_, name)| *name)
+        .collect();
+    let existing = registry_cache_dirs()?;
+    for name in &names {
+        if cache.join(name).exists() {
+            return Ok(cache.join(name));
+        }
+        let host = &name[..=name.rfind('-').unwrap()];
+        let older = existing.iter().find(|dir| {
+            dir.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(host))
+        });
+        if let Some(dir) = older {
+            return Ok(dir.clone());
+        }
+    }
+    Ok(cache.join(names.first().unwrap_or(&CRATES_IO_DIRS[0].1)))
+}
+
+/// Copy the cached files of the given crates.io packages into another Cargo
+/// home, along with their index entries so that Cargo can use them offline.
+///
+/// `.crate` files are hard-linked when possible. The index files are copied,
+/// since Cargo rewrites them when it updates the index, and that shouldn't
+/// change the other home.
+pub fn copy_to_home(home: &Path, pkgs: &[LockedPackage]) -> Fallible<()> {
+    let cache_dir = crates_io_cache_dir()?;
+    let registry = cache_dir.file_name().unwrap();
+    let index_dir = cargo_home()?.join("registry").join("index").join(registry);
+    let dest_cache = home.join("registry").join("cache").join(registry);
+    let dest_index = home.join("registry").join("index").join(registry);
+    copy(
+        &index_dir.join("config.json"),
+        &dest_index.join("config.json"),
+    )?;
+    for pkg in pkgs {
+        let file_name = format!("{}-{}.crate", pkg.name, pkg.version);
+        link_or_copy(&cache_dir.join(&file_name), &dest_cache.join(&file_name))?;
+        let index_path = Path::new(".cache").join(index::index_path(&pkg.name));
+        copy(&index_dir.join(&index_path), &dest_index.join(&index_path))?;
+    }
+    Ok(())
+}
+
+/// An entry in the `manifest.json` of an `--output-dir`.
+#[derive(Serialize)]
+struct ManifestEntry<'a> {
+    name: &'a str,
+    version: &'a str,
+    checksum: &'a str,
+    file: String,
+}
+
+/// Copy the `.crate` files of the given crates.io packages into a flat
+/// directory, along with a `manifest.json` listing them.
+pub fn copy_to_dir(dir: &Path, pkgs: &[LockedPackage]) -> Fallible<()> {
+    let cache_dir = crates_io_cache_dir()?;
+    let mut manifest = Vec::new();
+    for pkg in pkgs {
+        let file_name = format!("{}-{}.crate", pkg.name, pkg.version);
+        let src = cache_dir.join(&file_name);
+        if !src.exists() {
+            bail!("`{}` was not found in the cache", file_name);
+        }
+        link_or_copy(&src, &dir.join(&file_name))?;
+        manifest.push(ManifestEntry {
+            name: &pkg.name,
+            version: &pkg.version,
+            checksum: &pkg.checksum,
+            file: file_name,
+        });
+    }
+    manifest.sort_by(|a, b| a.file.cmp(&b.file));
+    fs::write(
+        dir.join("manifest.json"),
+        serde_json::to_string_pretty(&manifest)?,
+    )?;
+    Ok(())
+}
+
+/// Hard-link or copy a file, replacing the destination. Missing sources are
+/// skipped.
+fn link_or_copy(src: &Path, dest: &Path) -> Fallible<()> {
+    replace(src, dest, true)
+}
+
+/// Copy a file, replacing the destination. Missing sources are skipped.
+fn copy(src: &Path, dest: &Path) -> Fallible<()> {
+    replace(src, dest, false)
+}
+
+fn replace(src: &Path, dest: &Path, link: bool) -> Fallible<()> {
+    if !src.exists() {
+        return Ok(());
+    }
+    fs::create_dir_all(dest.parent().unwrap())?;
+    if dest.exists() {
+        fs::remove_file(dest)?;
+    }
+    if !link || fs::hard_link(src, dest).is_err() {
+        fs::copy(src, dest).with_context(|_| format!("Failed to copy `{}`.", src.display()))?;
+    }
+    Ok(())
+}
+
+/// Returns the directory where cargo-prefetch stores its own data.
+pub fn prefetch_dir() -> Fallible<PathBuf> {
+    Ok(cargo_home()?.join("prefetch"))
+}
+
+/// Point `CARGO_HOME` at a temporary directory for the tests, which is
+/// shared since the environment is shared by the threads running them.
+#[cfg(test)]
+pub(crate) fn test_home() -> &'static std::path::Path {
+    static HOME: std::sync::OnceLock<tempfile::TempDir> = std::sync::OnceLock::new();
+    HOME.get_or_init(|| {
+        let home = tempfile::tempdir().unwrap();
+        std::env::set_var("CARGO_HOME", home.path());
+        home
+    })
+    .path()
+}
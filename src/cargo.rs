@@ -0,0 +1,154 @@
+//! Running Cargo.
+//!
+//! Cargo is found with `--cargo`, then the `CARGO` environment variable (which
+//! Cargo sets when it runs a subcommand like this one), and then `cargo` on
+//! the `PATH`. With `--toolchain`, the rustup proxy on the `PATH` is run with
+//! `+toolchain` instead, unless `--cargo` is given.
+//!
+//! The version of Cargo is checked when starting, so that features it
+//! doesn't support give a clear error instead of a confusing failure.
+
+use crate::exit::CargoError;
+use failure::{bail, format_err, Fallible, ResultExt};
+use semver::Version;
+use std::env;
+use std::ffi::OsString;
+use std::process::Command;
+use tracing::debug;
+
+/// The oldest version of Cargo that can fetch the temp project, which uses
+/// the 2018 edition.
+const MIN_VERSION: (u64, u64) = (1, 31);
+
+/// The version where the sparse protocol for crates.io became the default.
+const SPARSE_DEFAULT: (u64, u64) = (1, 70);
+
+/// The version where the sparse protocol became available.
+const SPARSE_AVAILABLE: (u64, u64) = (1, 68);
+
+pub struct Cargo {
+    program: OsString,
+    toolchain: Option<String>,
+    /// Extra arguments for every subcommand, from `--cargo-arg`.
+    args: Vec<String>,
+    version: Version,
+}
+
+impl Cargo {
+    pub fn new(
+        program: Option<&str>,
+        toolchain: Option<&str>,
+        args: Vec<String>,
+    ) -> Fallible<Cargo> {
+        let program = match (program, toolchain) {
+            (Some(program), _) => program.into(),
+            (None, Some(_)) => "cargo".into(),
+            (None, None) => env::var_os("CARGO").unwrap_or_else(|| "cargo".into()),
+        };
+        let mut cargo = Cargo {
+            program,
+            toolchain: toolchain.map(String::from),
+            args,
+            version: Version::new(0, 0, 0),
+        };
+        cargo.version = cargo.probe_version()?;
+        debug!("Using cargo {}", cargo.version);
+        let (major, minor) = MIN_VERSION;
+        cargo.require("cargo-prefetch", major, minor)?;
+        Ok(cargo)
+    }
+
+    /// Describes this Cargo and its arguments, which is different for any
+    /// change that may select different versions.
+    pub fn fingerprint(&self) -> String {
+        format!(
+            "{} {:?} {:?} {}",
+            self.program.to_string_lossy(),
+            self.toolchain,
+            self.args,
+            self.version
+        )
+    }
+
+    /// A placeholder for `--no-cargo`, which is never run.
+    pub fn not_installed() -> Cargo {
+        Cargo {
+            program: "cargo".into(),
+            toolchain: None,
+            args: Vec::new(),
+            version: Version::new(0, 0, 0),
+        }
+    }
+
+    /// Run `cargo --version`, which prints something like
+    /// `cargo 1.70.0 (ec8a8a0ca 2023-04-25)`.
+    fn probe_version(&self) -> Fallible<Version> {
+        let mut cmd = self.base_command();
+        cmd.arg("--version");
+        let output = cmd
+            .output()
+            .with_context(|_| format!("Failed to run {:?}, is cargo installed?", cmd))?;
+        if !output.status.success() {
+            return Err(CargoError(format!(
+                "{:?} failed to run: {}\n{}",
+                cmd,
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            ))
+            .into());
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        stdout
+            .split_whitespace()
+            .nth(1)
+            .and_then(|version| Version::parse(version).ok())
+            .ok_or_else(|| format_err!("unexpected output from {:?}: {}", cmd, stdout.trim()))
+    }
+
+    /// Returns an error if Cargo is older than `major.minor`, which is
+    /// needed for `feature`.
+    pub fn require(&self, feature: &str, major: u64, minor: u64) -> Fallible<()> {
+        if !self.at_least((major, minor)) {
+            bail!(
+                "{} requires cargo {}.{} or newer, but cargo is version {}",
+                feature,
+                major,
+                minor,
+                self.version
+            );
+        }
+        Ok(())
+    }
+
+    /// Whether this is a nightly Cargo, which accepts `-Z` flags.
+    pub fn is_nightly(&self) -> bool {
+        matches!(self.version.pre.as_str(), "nightly" | "dev")
+    }
+
+    fn at_least(&self, (major, minor): (u64, u64)) -> bool {
+        (self.version.major, self.version.minor) >= (major, minor)
+    }
+
+    fn base_command(&self) -> Command {
+        let mut cmd = Command::new(&self.program);
+        if let Some(toolchain) = &self.toolchain {
+            cmd.arg(format!("+{}", toolchain));
+        }
+        cmd
+    }
+
+    /// Returns a command that runs the given Cargo subcommand.
+    pub fn command(&self, subcommand: &str) -> Command {
+        let mut cmd = self.base_command();
+        cmd.arg(subcommand).args(&self.args);
+        // The sparse protocol is much faster than cloning the git index,
+        // so use it where it exists but isn't the default yet.
+        if self.at_least(SPARSE_AVAILABLE)
+            && !self.at_least(SPARSE_DEFAULT)
+            && env::var_os("CARGO_REGISTRIES_CRATES_IO_PROTOCOL").is_none()
+        {
+            cmd.env("CARGO_REGISTRIES_CRATES_IO_PROTOCOL", "sparse");
+        }
+        cmd
+    }
+}
@@ -0,0 +1,72 @@
+//! The `check` subcommand for checking that a lockfile can be built offline.
+//!
+//! Every crates.io package in the lockfile needs its `.crate` file in the
+//! cache with the right checksum, and its entry in Cargo's local copy of the
+//! index, since Cargo reads the index even when the lockfile pins the
+//! version. Packages from git or other registries aren't checked.
+
+use cargo_prefetch::cache;
+use cargo_prefetch::download::{self, Cached};
+use cargo_prefetch::index;
+use cargo_prefetch::{is_crates_io, sources};
+use clap::ArgMatches;
+use failure::{bail, Fallible};
+use std::path::Path;
+use tracing::info;
+
+pub fn check(matches: &ArgMatches<'_>) -> Fallible<()> {
+    let cache_dir = cache::crates_io_cache_dir()?;
+    let registry = cache_dir
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .into_owned();
+    let mut total = 0;
+    let mut missing = 0;
+    for path in matches.values_of("lockfile").unwrap() {
+        let mut pkgs = sources::lockfile_packages(Path::new(path))?;
+        pkgs.retain(|pkg| pkg.source.as_deref().is_some_and(is_crates_io));
+        total += pkgs.len();
+        for pkg in &pkgs {
+            let mut problems = Vec::new();
+            let cached = match &pkg.checksum {
+                Some(cksum) => download::check_cached(&pkg.name, &pkg.version, cksum)?,
+                // Old lockfiles keep checksums elsewhere.
+                None if cache_dir
+                    .join(format!("{}-{}.crate", pkg.name, pkg.version))
+                    .exists() =>
+                {
+                    Cached::Valid
+                }
+                None => Cached::Missing,
+            };
+            match cached {
+                Cached::Valid => {}
+                Cached::Missing => problems.push(".crate file missing"),
+                Cached::Corrupt(_) => problems.push(".crate file corrupt"),
+            }
+            let indexed = index::read_local_cache(&registry, &pkg.name)?
+                .into_iter()
+                .flatten()
+                .any(|entry| entry.vers == pkg.version);
+            if !indexed {
+                problems.push("index entry missing");
+            }
+            if !problems.is_empty() {
+                println!(
+                    "{}@{} ({}): {}",
+                    pkg.name,
+                    pkg.version,
+                    path,
+                    problems.join(", ")
+                );
+                missing += 1;
+            }
+        }
+    }
+    if missing > 0 {
+        bail!("{} of {} packages are not fully cached", missing, total);
+    }
+    info!("All {} packages are cached", total);
+    Ok(())
+}
@@ -0,0 +1,163 @@
+//! The `classroom` subcommand for building an offline workshop bundle.
+//!
+//! The bundle is a directory containing:
+//!
+//! - `project/`: A copy of the template project.
+//! - `vendor/`: The sources of every crate needed by the template project
+//!   and the beginner crates, as created by `cargo vendor`.
+//! - `config.toml`: The source replacement config to use the vendored crates.
+//! - `README.md`: Instructions for participants.
+
+use cargo_prefetch::exit::CargoError;
+use cargo_prefetch::{make_project, mktemp, parse_specs, CrateSet, Options};
+use clap::ArgMatches;
+use failure::{bail, Fallible, ResultExt};
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use tracing::{debug, info};
+
+/// Crates commonly used in introductory Rust material.
+const BEGINNER_CRATES: &[&str] = &[
+    "anyhow",
+    "chrono",
+    "clap",
+    "itertools",
+    "rand",
+    "rayon",
+    "regex",
+    "serde",
+    "serde_json",
+    "thiserror",
+];
+
+pub fn classroom(options: &Options, matches: &ArgMatches<'_>) -> Fallible<()> {
+    let project = matches.value_of("project").unwrap();
+    let out = Path::new(matches.value_of("out").unwrap());
+    if out.exists() && fs::read_dir(out)?.next().is_some() {
+        bail!("output directory `{}` is not empty", out.display());
+    }
+
+    let mut crates: CrateSet = BEGINNER_CRATES
+        .iter()
+        .map(|name| (name.to_string(), None))
+        .collect();
+    if let Some(extra) = matches.values_of("crates") {
+        for krate in extra {
+            crates.extend(parse_specs(krate)?);
+        }
+    }
+
+    options.cargo.require("classroom", 1, 37)?;
+    let dir = mktemp(options)?;
+    let tmp_path = dir.path();
+    let template = tmp_path.join("template");
+    if is_remote(project) {
+        run(Command::new("git")
+            .args(["clone", "--depth", "1", project])
+            .arg(&template))?;
+    } else {
+        copy_project(Path::new(project), &template)?;
+    }
+    let template_manifest = template.join("Cargo.toml");
+    if !template_manifest.exists() {
+        bail!("template project `{}` does not have a Cargo.toml", project);
+    }
+
+    let resolve = tmp_path.join("resolve");
+    fs::create_dir(&resolve)?;
+    // The template's own manifest decides which resolver to use.
+    make_project(&resolve, &crates, None, options.rust_version.as_deref())?;
+
+    fs::create_dir_all(out)?;
+    let vendor = out.join("vendor");
+    run(options
+        .cargo
+        .command("vendor")
+        .arg("--sync")
+        .arg(&template_manifest)
+        .arg(&vendor)
+        .current_dir(&resolve)
+        // The printed config is replaced with `config.toml`.
+        .stdout(Stdio::null()))?;
+    copy_project(&template, &out.join("project"))?;
+    fs::write(out.join("config.toml"), CONFIG)?;
+    let mut names: Vec<_> = crates.iter().map(|(name, _)| name.as_str()).collect();
+    names.sort_unstable();
+    fs::write(
+        out.join("README.md"),
+        README.replace("{crates}", &names.join(", ")),
+    )?;
+    info!("Created classroom bundle in `{}`.", out.display());
+    Ok(())
+}
+
+/// Whether the template project argument is a git URL.
+fn is_remote(project: &str) -> bool {
+    project.contains("://") || project.starts_with("git@")
+}
+
+/// Run a command, failing if it does not succeed.
+fn run(cmd: &mut Command) -> Fallible<()> {
+    debug!("Running: {:?}", cmd);
+    let status = cmd
+        .status()
+        .with_context(|_| format!("Failed to launch {:?}.", cmd))?;
+    if !status.success() {
+        return Err(CargoError(format!("{:?} failed to run: {}", cmd, status)).into());
+    }
+    Ok(())
+}
+
+/// Copy a project directory, skipping `target` and `.git`.
+fn copy_project(src: &Path, dst: &Path) -> Fallible<()> {
+    fs::create_dir_all(dst)?;
+    for entry in fs::read_dir(src)
+        .with_context(|_| format!("Failed to read template project `{}`.", src.display()))?
+    {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name == "target" || name == ".git" {
+            continue;
+        }
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            copy_project(&path, &dst.join(&name))?;
+        } else {
+            fs::copy(&path, dst.join(&name))?;
+        }
+    }
+    Ok(())
+}
+
+const CONFIG: &str = "\
+# Replace `/path/to/bundle` with the location of this bundle, and add this to
+# `~/.cargo/config.toml` (or the project's `.cargo/config.toml`).
+[source.crates-io]
+replace-with = \"classroom-bundle\"
+
+[source.classroom-bundle]
+directory = \"/path/to/bundle/vendor\"
+";
+
+const README: &str = "\
+# Offline workshop bundle
+
+This bundle contains everything needed to build the workshop project without
+an internet connection.
+
+## Setup
+
+1. Copy this directory to your computer.
+2. Copy the contents of `config.toml` into `~/.cargo/config.toml`, replacing
+   `/path/to/bundle` with the location of this directory.
+3. Build the project in `project/` with `cargo build`.
+
+Remove the `config.toml` settings from `~/.cargo/config.toml` after the
+workshop to go back to downloading from crates.io.
+
+## Included crates
+
+In addition to the dependencies of the project, the following crates are
+available to use: {crates}.
+";
@@ -0,0 +1,198 @@
+//! The `clean` and `prune` subcommands for removing crates from the cache.
+//!
+//! These remove `.crate` files from `registry/cache`, along with their
+//! extracted sources in `registry/src`. The age of a crate is the time its
+//! `.crate` file was last modified, which is when it was downloaded.
+
+use cargo_prefetch::cache::{self, CachedCrate};
+use clap::ArgMatches;
+use failure::{bail, format_err, Fallible};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::time::{Duration, SystemTime};
+use tracing::info;
+
+pub fn clean(matches: &ArgMatches<'_>) -> Fallible<()> {
+    let older_than = matches.value_of("older-than").map(parse_age).transpose()?;
+    let max_size = matches.value_of("max-size").map(parse_size).transpose()?;
+    if older_than.is_none() && max_size.is_none() {
+        bail!("either --older-than or --max-size is required");
+    }
+    let dry_run = matches.is_present("dry-run");
+
+    let mut crates = Vec::new();
+    for dir in cache::registry_cache_dirs()? {
+        crates.extend(cache::cached_crates(&dir)?);
+    }
+    // Oldest first.
+    crates.sort_by_key(|c| c.modified);
+    let now = SystemTime::now();
+    let mut total: u64 = crates.iter().map(|c| c.size).sum();
+    let mut remove = Vec::new();
+    for krate in crates {
+        let too_old = older_than.is_some_and(|age| {
+            now.duration_since(krate.modified)
+                .is_ok_and(|actual| actual > age)
+        });
+        let too_big = max_size.is_some_and(|max| total > max);
+        if too_old || too_big {
+            total -= krate.size;
+            remove.push(krate);
+        }
+    }
+
+    let size: u64 = remove.iter().map(|c| c.size).sum();
+    let _lock = if dry_run {
+        None
+    } else {
+        Some(cache::lock_package_cache(true)?)
+    };
+    for krate in &remove {
+        if dry_run {
+            println!("Would remove {}-{}", krate.name, krate.version);
+        } else {
+            remove_crate(krate)?;
+        }
+    }
+    info!(
+        "{} {} crates ({}), {} remaining",
+        if dry_run { "Would remove" } else { "Removed" },
+        remove.len(),
+        cache::human_size(size),
+        cache::human_size(total)
+    );
+    Ok(())
+}
+
+pub fn prune(matches: &ArgMatches<'_>) -> Fallible<()> {
+    let keep = matches
+        .value_of("keep")
+        .unwrap()
+        .parse::<usize>()
+        .map_err(|e| format_err!("keep must be an integer: {}", e))?;
+    let dry_run = matches.is_present("dry-run");
+
+    let mut remove = Vec::new();
+    for dir in cache::registry_cache_dirs()? {
+        let mut by_name: HashMap<String, Vec<CachedCrate>> = HashMap::new();
+        for krate in cache::cached_crates(&dir)? {
+            by_name.entry(krate.name.clone()).or_default().push(krate);
+        }
+        for (_, mut versions) in by_name {
+            // Newest first. File names always have a valid version.
+            versions.sort_by_key(|c| Reverse(semver::Version::parse(&c.version).ok()));
+            remove.extend(versions.into_iter().skip(keep));
+        }
+    }
+    remove.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let size: u64 = remove.iter().map(|c| c.size).sum();
+    let _lock = if dry_run {
+        None
+    } else {
+        Some(cache::lock_package_cache(true)?)
+    };
+    for krate in &remove {
+        if dry_run {
+            println!("Would remove {}-{}", krate.name, krate.version);
+        } else {
+            remove_crate(krate)?;
+        }
+    }
+    info!(
+        "{} {} old versions ({})",
+        if dry_run { "Would remove" } else { "Removed" },
+        remove.len(),
+        cache::human_size(size)
+    );
+    Ok(())
+}
+
+/// Remove a `.crate` file and its extracted source.
+///
+/// The caller should hold the package cache lock.
+pub fn remove_crate(krate: &CachedCrate) -> Fallible<()> {
+    fs::remove_file(&krate.path)?;
+    let src = cache::src_dir(krate)?;
+    if src.exists() {
+        fs::remove_dir_all(&src)?;
+    }
+    Ok(())
+}
+
+/// Parse an age such as `90d`, `12h`, or `4w`.
+pub fn parse_age(value: &str) -> Fallible<Duration> {
+    let err = || format_err!("invalid age `{}`, expected a value such as `90d`", value);
+    let split = value.find(|c: char| !c.is_ascii_digit()).ok_or_else(err)?;
+    let n: u64 = value[..split].parse().map_err(|_| err())?;
+    let unit = match &value[split..] {
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(err()),
+    };
+    let secs = n.checked_mul(unit).ok_or_else(err)?;
+    Ok(Duration::from_secs(secs))
+}
+
+/// Parse a size such as `5GiB`, `500MB`, or `1G`.
+fn parse_size(value: &str) -> Fallible<u64> {
+    let err = || format_err!("invalid size `{}`, expected a value such as `5GiB`", value);
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let n: f64 = value[..split].parse().map_err(|_| err())?;
+    let unit: u64 = match value[split..].trim() {
+        "" | "B" => 1,
+        "K" | "KiB" => 1 << 10,
+        "M" | "MiB" => 1 << 20,
+        "G" | "GiB" => 1 << 30,
+        "T" | "TiB" => 1 << 40,
+        "KB" => 1_000,
+        "MB" => 1_000_000,
+        "GB" => 1_000_000_000,
+        "TB" => 1_000_000_000_000,
+        _ => return Err(err()),
+    };
+    Ok((n * unit as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("100").unwrap(), 100);
+        assert_eq!(parse_size("100B").unwrap(), 100);
+        assert_eq!(parse_size("5GiB").unwrap(), 5 << 30);
+        assert_eq!(parse_size("1G").unwrap(), 1 << 30);
+        assert_eq!(parse_size("500MB").unwrap(), 500_000_000);
+        assert_eq!(parse_size("1.5KiB").unwrap(), 1536);
+        assert_eq!(parse_size("2 TB").unwrap(), 2_000_000_000_000);
+        assert!(parse_size("").is_err());
+        assert!(parse_size("GiB").is_err());
+        assert!(parse_size("5XB").is_err());
+    }
+
+    #[test]
+    fn ages() {
+        assert_eq!(parse_age("30m").unwrap(), Duration::from_secs(30 * 60));
+        assert_eq!(parse_age("12h").unwrap(), Duration::from_secs(12 * 60 * 60));
+        assert_eq!(
+            parse_age("90d").unwrap(),
+            Duration::from_secs(90 * 24 * 60 * 60)
+        );
+        assert_eq!(
+            parse_age("2w").unwrap(),
+            Duration::from_secs(14 * 24 * 60 * 60)
+        );
+        assert!(parse_age("90").is_err());
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("90y").is_err());
+        assert!(parse_age("99999999999999999999d").is_err());
+        assert!(parse_age("999999999999999999w").is_err());
+    }
+}
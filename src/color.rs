@@ -0,0 +1,86 @@
+//! Colored output, for `--color`.
+//!
+//! Like Cargo, colors are used when stderr is a terminal. `NO_COLOR` turns
+//! them off unless `--color always` is given. The choice is passed on to
+//! Cargo with `CARGO_TERM_COLOR`, so that its output matches.
+//!
+//! `tracing` escapes control characters in messages, so colored lines are
+//! printed directly to stderr, and only logged when colors are off.
+
+use failure::{bail, Fallible};
+use std::env;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::info;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy)]
+pub enum Color {
+    Green,
+    Yellow,
+    Red,
+}
+
+/// Set up colors from a choice of `auto`, `always`, or `never`.
+pub fn init(choice: &str) -> Fallible<()> {
+    let no_color = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    let enabled = match choice {
+        "auto" => !no_color && io::stderr().is_terminal(),
+        "always" => true,
+        "never" => false,
+        _ => bail!(
+            "unknown color choice `{}`, expected auto, always, or never",
+            choice
+        ),
+    };
+    ENABLED.store(enabled, Ordering::Relaxed);
+    if choice != "auto" {
+        env::set_var("CARGO_TERM_COLOR", choice);
+    } else if no_color && env::var_os("CARGO_TERM_COLOR").is_none() {
+        // Cargo doesn't look at `NO_COLOR`.
+        env::set_var("CARGO_TERM_COLOR", "never");
+    }
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Returns `text` in bold and the given color, if colors are enabled.
+pub fn paint(color: Color, text: &str) -> String {
+    if !enabled() {
+        return text.to_string();
+    }
+    let code = match color {
+        Color::Green => 32,
+        Color::Yellow => 33,
+        Color::Red => 31,
+    };
+    format!("\x1b[1;{}m{}\x1b[0m", code, text)
+}
+
+/// Print a line made of parts which may be colored.
+pub fn print_line(parts: &[(Option<Color>, &str)]) {
+    let line: String = parts
+        .iter()
+        .map(|(color, text)| match color {
+            Some(color) => paint(*color, text),
+            None => text.to_string(),
+        })
+        .collect();
+    if enabled() {
+        eprintln!("{}", line);
+    } else {
+        info!("{}", line);
+    }
+}
+
+/// Print a status line like Cargo's, such as `  Downloaded foo v1.0.0`.
+pub fn status(color: Color, label: &str, message: &str) {
+    print_line(&[
+        (Some(color), &format!("{:>12}", label)),
+        (None, &format!(" {}", message)),
+    ]);
+}
@@ -0,0 +1,113 @@
+//! Adaptive concurrency for direct downloads with `--jobs`.
+//!
+//! `--jobs` is the most downloads that run at once. The limit starts at half
+//! of it, and is adjusted like TCP's AIMD congestion control: after each
+//! window of downloads, one more is allowed if the throughput improved, and
+//! the limit is halved whenever the server rate limits a request or one is
+//! retried after failing.
+
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// The shortest window that the throughput is measured over.
+const MIN_WINDOW: Duration = Duration::from_millis(500);
+
+/// How much the throughput has to improve for the limit to go up, since
+/// it is noisy.
+const IMPROVEMENT: f64 = 1.05;
+
+pub struct Concurrency {
+    max: usize,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+struct State {
+    /// How many downloads may run at once.
+    limit: usize,
+    /// How many downloads are running.
+    active: usize,
+    window_start: Instant,
+    /// Bytes and downloads in the current window.
+    window_bytes: u64,
+    window_done: usize,
+    /// Bytes per second in the last window.
+    last_rate: f64,
+    /// The number of throttled requests last seen.
+    throttled: u64,
+}
+
+impl Concurrency {
+    /// A controller for up to `max` downloads at once, where `throttled` is
+    /// the current count from [`Http::throttled`](crate::http::Http::throttled).
+    pub fn new(max: usize, throttled: u64) -> Concurrency {
+        Concurrency {
+            max,
+            state: Mutex::new(State {
+                limit: max.div_ceil(2),
+                active: 0,
+                window_start: Instant::now(),
+                window_bytes: 0,
+                window_done: 0,
+                last_rate: 0.0,
+                throttled,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Wait until another download may start, or until `stop` returns true,
+    /// and return whether it may start.
+    pub fn acquire(&self, stop: impl Fn() -> bool) -> bool {
+        let mut state = self.state.lock().unwrap();
+        while state.active >= state.limit {
+            if stop() {
+                return false;
+            }
+            state = self
+                .changed
+                .wait_timeout(state, Duration::from_millis(100))
+                .unwrap()
+                .0;
+        }
+        state.active += 1;
+        true
+    }
+
+    /// Record that a download finished after transferring `bytes`, where
+    /// `throttled` is the current count of throttled requests.
+    pub fn release(&self, bytes: u64, throttled: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.active -= 1;
+        state.window_bytes += bytes;
+        state.window_done += 1;
+        if throttled > state.throttled {
+            state.throttled = throttled;
+            state.limit = (state.limit / 2).max(1);
+            state.last_rate = 0.0;
+            state.start_window();
+            debug!("Throttled, downloading {} at once", state.limit);
+        } else {
+            let elapsed = state.window_start.elapsed();
+            if state.window_done >= state.limit && elapsed >= MIN_WINDOW {
+                let rate = state.window_bytes as f64 / elapsed.as_secs_f64();
+                if rate > state.last_rate * IMPROVEMENT && state.limit < self.max {
+                    state.limit += 1;
+                    debug!("{:.0} bytes/s, downloading {} at once", rate, state.limit);
+                }
+                state.last_rate = rate;
+                state.start_window();
+            }
+        }
+        self.changed.notify_all();
+    }
+}
+
+impl State {
+    fn start_window(&mut self) {
+        self.window_start = Instant::now();
+        self.window_bytes = 0;
+        self.window_done = 0;
+    }
+}
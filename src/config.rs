@@ -0,0 +1,223 @@
+//! Settings from Cargo's config files.
+//!
+//! Settings are read from a `[prefetch]` table in the same config files that
+//! Cargo uses (`.cargo/config.toml` in the current directory and its parents,
+//! and then `$CARGO_HOME/config.toml`). Files closer to the current directory
+//! take precedence, and command-line flags take precedence over all of them.
+//!
+//! The protocol used for crates.io is read from Cargo's `[registries]` table
+//! too, since it decides the name of its directories in Cargo's cache.
+//!
+//! Cargo's own `[net]` table is read from the same files, so that the
+//! requests made here behave like Cargo's. The `CARGO_NET_*` environment
+//! variables take precedence over it, like they do for Cargo. The index URLs
+//! of the `[registries]` table are read too, for packages in lockfiles that
+//! come from other registries.
+
+use crate::cache;
+use failure::{bail, format_err, Fallible, ResultExt};
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    /// The `User-Agent` header to send with HTTP requests.
+    pub user_agent: Option<String>,
+    /// The base URL of the crates.io API.
+    pub api_url: Option<String>,
+    /// The protocol Cargo uses for crates.io, `sparse` or `git`.
+    #[serde(skip)]
+    pub crates_io_protocol: Option<String>,
+    /// A command to run after a successful fetch.
+    pub post_hook: Option<String>,
+    #[serde(skip)]
+    pub net: Net,
+    /// The index URLs of other registries, by name.
+    #[serde(skip)]
+    pub registries: BTreeMap<String, String>,
+}
+
+/// Settings from Cargo's `[net]` table.
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Net {
+    /// How many times to retry requests that fail with a network error.
+    pub retry: Option<u32>,
+    /// Don't access the network.
+    pub offline: Option<bool>,
+    /// Whether Cargo fetches git repositories with the `git` command.
+    pub git_fetch_with_cli: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct ConfigFile {
+    #[serde(default)]
+    prefetch: Config,
+    #[serde(default)]
+    registries: BTreeMap<String, Registry>,
+    #[serde(default)]
+    net: Net,
+}
+
+/// A registry in Cargo's `[registries]` table.
+#[derive(Deserialize)]
+struct Registry {
+    index: Option<String>,
+    protocol: Option<String>,
+}
+
+impl Config {
+    /// Fill in any settings that are not set from `other`.
+    fn merge(&mut self, other: Config) {
+        self.user_agent = self.user_agent.take().or(other.user_agent);
+        self.api_url = self.api_url.take().or(other.api_url);
+        self.post_hook = self.post_hook.take().or(other.post_hook);
+    }
+
+    /// Returns the name of the registry for a lockfile `source`, such as
+    /// `sparse+https://example.com/index/`.
+    pub fn registry_name(&self, source: &str) -> Option<&str> {
+        let source = source.trim_end_matches('/');
+        self.registries
+            .iter()
+            .find(|(_, index)| {
+                // Lockfiles mark git indexes with `registry+`, and sparse
+                // indexes already have their `sparse+` prefix.
+                let index = index.trim_end_matches('/');
+                source == index || source.strip_prefix("registry+") == Some(index)
+            })
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Set the registries in the environment, so that the temp projects use
+    /// them too: `CARGO_REGISTRIES_<NAME>_INDEX` for each registry, so they
+    /// can be used by name, and the crates.io protocol.
+    pub fn export_registries(&self) {
+        for (name, index) in &self.registries {
+            env::set_var(registry_env(name), index);
+        }
+        if let Some(protocol) = &self.crates_io_protocol {
+            env::set_var(CRATES_IO_PROTOCOL_ENV, protocol);
+        }
+    }
+}
+
+impl Net {
+    fn merge(&mut self, other: Net) {
+        self.retry = self.retry.take().or(other.retry);
+        self.offline = self.offline.take().or(other.offline);
+        self.git_fetch_with_cli = self.git_fetch_with_cli.take().or(other.git_fetch_with_cli);
+    }
+
+    /// Override the settings with the `CARGO_NET_*` environment variables.
+    fn apply_env(&mut self) -> Fallible<()> {
+        if let Ok(retry) = env::var("CARGO_NET_RETRY") {
+            self.retry = Some(
+                retry
+                    .parse()
+                    .map_err(|e| format_err!("invalid CARGO_NET_RETRY `{}`: {}", retry, e))?,
+            );
+        }
+        let flag = |name: &str| -> Fallible<Option<bool>> {
+            match env::var(name) {
+                Ok(value) => match &value[..] {
+                    "true" => Ok(Some(true)),
+                    "false" => Ok(Some(false)),
+                    _ => bail!("invalid {} `{}`, expected `true` or `false`", name, value),
+                },
+                Err(_) => Ok(None),
+            }
+        };
+        if let Some(offline) = flag("CARGO_NET_OFFLINE")? {
+            self.offline = Some(offline);
+        }
+        if let Some(cli) = flag("CARGO_NET_GIT_FETCH_WITH_CLI")? {
+            self.git_fetch_with_cli = Some(cli);
+        }
+        Ok(())
+    }
+
+    /// Set the environment variables for these settings, so that the Cargo
+    /// processes run for the temp projects use them. Cargo wouldn't read a
+    /// `.cargo/config.toml` near the current directory itself, since the temp
+    /// projects are somewhere else.
+    pub fn export(&self) {
+        if let Some(retry) = self.retry {
+            env::set_var("CARGO_NET_RETRY", retry.to_string());
+        }
+        if let Some(offline) = self.offline {
+            env::set_var("CARGO_NET_OFFLINE", offline.to_string());
+        }
+        if let Some(cli) = self.git_fetch_with_cli {
+            env::set_var("CARGO_NET_GIT_FETCH_WITH_CLI", cli.to_string());
+        }
+    }
+}
+
+/// Load the settings from all config files.
+pub fn load() -> Fallible<Config> {
+    let mut config = Config::default();
+    for path in config_paths()? {
+        let contents = fs::read_to_string(&path)
+            .with_context(|_| format!("Failed to read `{}`.", path.display()))?;
+        let file: ConfigFile = toml::from_str(&contents)
+            .with_context(|_| format!("Failed to parse `{}`.", path.display()))?;
+        config.merge(file.prefetch);
+        config.net.merge(file.net);
+        for (name, registry) in file.registries {
+            if name == "crates-io" {
+                config.crates_io_protocol = config.crates_io_protocol.take().or(registry.protocol);
+            }
+            if let Some(index) = registry.index {
+                config.registries.entry(name).or_insert(index);
+            }
+        }
+    }
+    if let Ok(protocol) = env::var(CRATES_IO_PROTOCOL_ENV) {
+        config.crates_io_protocol = Some(protocol);
+    }
+    config.net.apply_env()?;
+    for (name, index) in &mut config.registries {
+        if let Ok(value) = env::var(registry_env(name)) {
+            *index = value;
+        }
+    }
+    Ok(config)
+}
+
+/// The environment variable for the protocol Cargo uses for crates.io.
+pub const CRATES_IO_PROTOCOL_ENV: &str = "CARGO_REGISTRIES_CRATES_IO_PROTOCOL";
+
+/// The environment variable for the index of a registry.
+fn registry_env(name: &str) -> String {
+    format!(
+        "CARGO_REGISTRIES_{}_INDEX",
+        name.to_uppercase().replace('-', "_")
+    )
+}
+
+/// Returns the config files that exist, in order of precedence.
+fn config_paths() -> Fallible<Vec<PathBuf>> {
+    let mut dirs: Vec<PathBuf> = env::current_dir()?
+        .ancestors()
+        .map(|dir| dir.join(".cargo"))
+        .collect();
+    let home = cache::cargo_home()?;
+    if !dirs.contains(&home) {
+        dirs.push(home);
+    }
+    Ok(dirs.iter().filter_map(|dir| config_file(dir)).collect())
+}
+
+/// Returns the config file in a directory, preferring `config.toml` like
+/// Cargo does.
+fn config_file(dir: &Path) -> Option<PathBuf> {
+    ["config.toml", "config"]
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
@@ -0,0 +1,449 @@
+//! Queries against the crates.io API.
+//!
+//! The API host can be changed with `--api-url`, for a mirror of the API.
+
+use crate::http::Http;
+use crate::timings;
+use failure::{bail, Fallible, ResultExt};
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use tracing::{info_span, warn};
+
+#[derive(Deserialize)]
+struct CratesQuery {
+    crates: Vec<CrateInfo>,
+    #[serde(default)]
+    meta: QueryMeta,
+}
+
+#[derive(Default, Deserialize)]
+struct QueryMeta {
+    /// Query string for the next page, when using seek-based pagination.
+    next_page: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CrateInfo {
+    name: String,
+}
+
+/// Information about a crate.
+#[derive(Deserialize)]
+pub struct CrateDetails {
+    pub name: String,
+    pub downloads: u64,
+    pub description: Option<String>,
+    pub updated_at: String,
+    #[serde(default)]
+    pub max_version: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DetailsQuery {
+    crates: Vec<CrateDetails>,
+}
+
+#[derive(Deserialize)]
+struct ReverseDepsQuery {
+    dependencies: Vec<ReverseDep>,
+    versions: Vec<ReverseDepVersion>,
+}
+
+#[derive(Deserialize)]
+struct ReverseDep {
+    version_id: u64,
+}
+
+#[derive(Deserialize)]
+struct ReverseDepVersion {
+    id: u64,
+    #[serde(rename = "crate")]
+    krate: String,
+}
+
+#[derive(Deserialize)]
+struct UserQuery {
+    user: Owner,
+}
+
+#[derive(Deserialize)]
+struct TeamQuery {
+    team: Owner,
+}
+
+#[derive(Deserialize)]
+struct Owner {
+    id: u64,
+}
+
+/// The ranking used for crates.io queries.
+#[derive(Clone, Copy)]
+pub enum Sort {
+    /// All-time downloads.
+    Downloads,
+    /// Downloads in the last 90 days.
+    RecentDownloads,
+    /// Most recently updated.
+    RecentUpdates,
+}
+
+impl Sort {
+    fn as_query(self) -> &'static str {
+        match self {
+            Sort::Downloads => "downloads",
+            Sort::RecentDownloads => "recent-downloads",
+            Sort::RecentUpdates => "recent-updates",
+        }
+    }
+}
+
+/// Return the top downloaded crates by querying crates.io.
+pub fn top_downloads(http: &Http, sort: Sort, count: usize) -> Fallible<Vec<String>> {
+    top_crates(http, sort, "", count)
+}
+
+/// Return the most recently updated crates.
+pub fn recently_updated(http: &Http, count: usize) -> Fallible<Vec<String>> {
+    top_crates(http, Sort::RecentUpdates, "", count)
+}
+
+/// Return all crates owned by the given user.
+pub fn user_crates(http: &Http, login: &str) -> Fallible<Vec<String>> {
+    let q = format!("/users/{}", login);
+    let user: UserQuery = get_json(http, &q)?;
+    let filter = format!("&user_id={}", user.user.id);
+    top_crates(http, Sort::Downloads, &filter, usize::MAX)
+}
+
+/// Return all crates owned by the given team (such as `github:org:team`).
+pub fn team_crates(http: &Http, team: &str) -> Fallible<Vec<String>> {
+    let q = format!("/teams/{}", team);
+    let team: TeamQuery = get_json(http, &q)?;
+    let filter = format!("&team_id={}", team.team.id);
+    top_crates(http, Sort::Downloads, &filter, usize::MAX)
+}
+
+/// Return the most downloaded crates that depend on the given crate.
+pub fn reverse_dependencies(http: &Http, name: &str, count: usize) -> Fallible<Vec<String>> {
+    const CRATES_IO_MAX: usize = 100;
+    let mut result = Vec::new();
+    let mut page = 1;
+    while result.len() < count {
+        let q = format!(
+            "/crates/{}/reverse_dependencies?page={}&per_page={}",
+            name, page, CRATES_IO_MAX
+        );
+        let json: ReverseDepsQuery = get_json(http, &q)?;
+        let done = json.dependencies.len() < CRATES_IO_MAX;
+        for dep in json.dependencies {
+            if let Some(version) = json.versions.iter().find(|v| v.id == dep.version_id) {
+                if !result.contains(&version.krate) {
+                    result.push(version.krate.clone());
+                }
+            }
+        }
+        if done {
+            break;
+        }
+        page += 1;
+    }
+    result.truncate(count);
+    Ok(result)
+}
+
+/// Return information about the given crates.
+pub fn crate_details(http: &Http, names: &[&str]) -> Fallible<HashMap<String, CrateDetails>> {
+    // Keep the URL at a reasonable length.
+    const CHUNK: usize = 50;
+    let mut result = HashMap::new();
+    for chunk in names.chunks(CHUNK) {
+        let ids: Vec<String> = chunk.iter().map(|name| format!("ids[]={}", name)).collect();
+        let q = format!("/crates?per_page={}&{}", CHUNK, ids.join("&"));
+        let json: DetailsQuery = get_json(http, &q)?;
+        for details in json.crates {
+            result.insert(details.name.clone(), details);
+        }
+    }
+    Ok(result)
+}
+
+#[derive(Deserialize)]
+struct VersionQuery {
+    version: VersionDetails,
+}
+
+/// Information about a version of a crate.
+#[derive(Deserialize)]
+pub struct VersionDetails {
+    /// The size of the `.crate` file, if it is known.
+    pub crate_size: Option<u64>,
+    /// The SPDX license expression, if it has one.
+    pub license: Option<String>,
+}
+
+/// Return information about a version of a crate.
+pub fn version_details(http: &Http, name: &str, version: &str) -> Fallible<VersionDetails> {
+    let json: VersionQuery = get_json(http, &format!("/crates/{}/{}", name, version))?;
+    Ok(json.version)
+}
+
+/// Return the size of the `.crate` file of a version, if it is known.
+pub fn crate_size(http: &Http, name: &str, version: &str) -> Fallible<Option<u64>> {
+    Ok(version_details(http, name, version)?.crate_size)
+}
+
+#[derive(Deserialize)]
+struct VersionsQuery {
+    versions: Vec<PublishedVersion>,
+}
+
+#[derive(Deserialize)]
+struct PublishedVersion {
+    num: String,
+    created_at: String,
+    #[serde(flatten)]
+    details: VersionDetails,
+}
+
+/// Return when each version of a crate was published, as a timestamp such
+/// as `2024-01-31T12:00:00.000000+00:00`.
+pub fn publish_times(http: &Http, name: &str) -> Fallible<HashMap<String, String>> {
+    let json: VersionsQuery = get_json(http, &format!("/crates/{}/versions", name))?;
+    Ok(json
+        .versions
+        .into_iter()
+        .map(|v| (v.num, v.created_at))
+        .collect())
+}
+
+/// Return information about every version of the given crates, by name and
+/// then version.
+///
+/// This takes one request for each crate, instead of one for each version
+/// like [`version_details`].
+pub fn all_version_details(
+    http: &Http,
+    names: &[&str],
+) -> Fallible<HashMap<String, HashMap<String, VersionDetails>>> {
+    let mut result = HashMap::new();
+    for name in names {
+        let json: VersionsQuery = get_json(http, &format!("/crates/{}/versions", name))?;
+        let versions = json
+            .versions
+            .into_iter()
+            .map(|v| (v.num, v.details))
+            .collect();
+        result.insert(name.to_string(), versions);
+    }
+    Ok(result)
+}
+
+#[derive(Deserialize)]
+struct CrateQuery {
+    #[serde(rename = "crate")]
+    krate: CrateTags,
+}
+
+/// The keywords and categories of a crate.
+#[derive(Deserialize)]
+pub struct CrateTags {
+    #[serde(default)]
+    pub keywords: Option<Vec<String>>,
+    /// Category slugs, such as `no-std`.
+    #[serde(default)]
+    pub categories: Option<Vec<String>>,
+}
+
+/// Return the keywords and categories of a crate.
+pub fn crate_tags(http: &Http, name: &str) -> Fallible<CrateTags> {
+    let json: CrateQuery = get_json(http, &format!("/crates/{}", name))?;
+    Ok(json.krate)
+}
+
+#[derive(Deserialize)]
+struct CategoriesQuery {
+    categories: Vec<Category>,
+}
+
+/// A category on crates.io.
+#[derive(Deserialize)]
+pub struct Category {
+    /// The name used in queries, such as `embedded`.
+    pub slug: String,
+    pub description: String,
+    /// The number of crates in the category, including its subcategories.
+    pub crates_cnt: u64,
+}
+
+/// Return the top-level categories on crates.io, sorted by slug.
+pub fn categories(http: &Http) -> Fallible<Vec<Category>> {
+    const CRATES_IO_MAX: usize = 100;
+    let mut result = Vec::new();
+    let mut page = 1;
+    loop {
+        let q = format!(
+            "/categories?page={}&per_page={}&sort=alpha",
+            page, CRATES_IO_MAX
+        );
+        let json: CategoriesQuery = get_json(http, &q)?;
+        let done = json.categories.len() < CRATES_IO_MAX;
+        result.extend(json.categories);
+        if done {
+            break;
+        }
+        page += 1;
+    }
+    Ok(result)
+}
+
+/// Search crates.io, returning the best matches first.
+pub fn search(http: &Http, query: &str, count: usize) -> Fallible<Vec<CrateDetails>> {
+    let q = format!("/crates?per_page={}&q={}", count, encode(query));
+    let json: DetailsQuery = get_json(http, &q)?;
+    Ok(json.crates)
+}
+
+/// Return the names of every crate on crates.io.
+pub fn all_crate_names(http: &Http) -> Fallible<Vec<String>> {
+    let mut result = Vec::new();
+    let mut query = "?per_page=100&sort=alpha".to_string();
+    loop {
+        let q = format!("/crates{}", query);
+        let json: CratesQuery = get_json(http, &q)?;
+        result.extend(json.crates.into_iter().map(|c| c.name));
+        match json.meta.next_page {
+            Some(next) if !next.is_empty() => query = next,
+            _ => break,
+        }
+    }
+    Ok(result)
+}
+
+/// How many pages of search results a pattern is matched against.
+const MAX_GLOB_PAGES: usize = 10;
+
+/// Return the names of the crates matching a glob pattern such as `tokio-*`.
+///
+/// The crates.io search matches names containing its query, so the longest
+/// literal part of the pattern is searched for and the results are filtered
+/// by the pattern. A short literal can match much of crates.io, so only the
+/// first `MAX_GLOB_PAGES` pages of results are used.
+pub fn glob_names(http: &Http, pattern: &str) -> Fallible<Vec<String>> {
+    let literal = pattern
+        .split(['*', '?'])
+        .max_by_key(|part| part.len())
+        .unwrap_or("");
+    if literal.len() < 2 {
+        bail!(
+            "pattern `{}` needs at least two characters in a row that aren't `*` or `?`",
+            pattern
+        );
+    }
+    let mut result = Vec::new();
+    let mut query = format!("?per_page=100&sort=alpha&q={}", encode(literal));
+    for page in 1.. {
+        let q = format!("/crates{}", query);
+        let json: CratesQuery = get_json(http, &q)?;
+        result.extend(
+            json.crates
+                .into_iter()
+                .map(|c| c.name)
+                .filter(|name| crate::glob_match(pattern, name)),
+        );
+        match json.meta.next_page {
+            Some(next) if !next.is_empty() => {
+                if page == MAX_GLOB_PAGES {
+                    warn!(
+                        "only the first {} crates.io results for `{}` were matched \
+                         against `{}`, use a longer pattern to match the rest",
+                        page * 100,
+                        literal,
+                        pattern
+                    );
+                    break;
+                }
+                query = next;
+            }
+            _ => break,
+        }
+    }
+    Ok(result)
+}
+
+/// Return the top downloaded crates with the given keyword.
+pub fn top_keyword(http: &Http, sort: Sort, keyword: &str, count: usize) -> Fallible<Vec<String>> {
+    top_crates(http, sort, &format!("&keyword={}", encode(keyword)), count)
+}
+
+/// Percent-encode a query string value.
+fn encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Return the top downloaded crates in the given category, such as
+/// `embedded`.
+pub fn top_category(
+    http: &Http,
+    sort: Sort,
+    category: &str,
+    count: usize,
+) -> Fallible<Vec<String>> {
+    top_crates(
+        http,
+        sort,
+        &format!("&category={}", encode(category)),
+        count,
+    )
+}
+
+/// Return the top crates from crates.io.
+///
+/// `filter` is an extra query string (such as `&keyword=foo`) to narrow the
+/// search.
+fn top_crates(http: &Http, sort: Sort, filter: &str, count: usize) -> Fallible<Vec<String>> {
+    const CRATES_IO_MAX: usize = 100;
+    let mut result = Vec::new();
+    let mut page = 1;
+    while result.len() < count {
+        let q = format!(
+            "/crates?page={}&per_page={}&sort={}{}",
+            page,
+            CRATES_IO_MAX,
+            sort.as_query(),
+            filter
+        );
+        let json: CratesQuery = get_json(http, &q)?;
+        let done = json.crates.len() < CRATES_IO_MAX;
+        for c in json.crates.into_iter() {
+            result.push(c.name);
+        }
+        if done {
+            break;
+        }
+        page += 1;
+    }
+    result.truncate(count);
+    Ok(result)
+}
+
+/// Send a GET request to the API and decode the JSON response.
+///
+/// `path` is relative to `/api/v1`. Responses are cached, see the `http`
+/// module.
+fn get_json<T: DeserializeOwned>(http: &Http, path: &str) -> Fallible<T> {
+    let _span = info_span!("api", path).entered();
+    let _timer = timings::start("api", path);
+    let url = format!("{}/api/v1{}", http.api_url(), path);
+    let body = http.get_cached(&url)?;
+    let json = serde_json::from_str(&body)
+        .with_context(|_| format!("Failed to parse response from `{}`.", url))?;
+    Ok(json)
+}
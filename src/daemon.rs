@@ -0,0 +1,175 @@
+//! The `daemon` subcommand, for fetching on a schedule.
+//!
+//! Each fetch runs `cargo prefetch` again with the flags given before
+//! `daemon`, so a failed fetch is logged and retried at the next run instead
+//! of stopping the daemon. The time of each run is saved in a state file, so
+//! restarting the daemon doesn't fetch again until the next run is due.
+//!
+//! Unless `--incremental` is given, runs after the first use a state file
+//! next to the daemon's, so that only crates with changes in the index are
+//! fetched again.
+//!
+//! Runs are delayed by a random amount of up to a tenth of the interval, so
+//! that many machines started at once don't all fetch at the same time.
+
+use crate::clean::parse_age;
+use cargo_prefetch::cache;
+use clap::ArgMatches;
+use failure::{bail, Fallible, ResultExt};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+#[derive(Default, Deserialize, Serialize)]
+struct State {
+    /// When the last run started, in seconds since the Unix epoch.
+    last_run: Option<u64>,
+    /// When the last successful run started.
+    last_success: Option<u64>,
+    /// The exit code of the last run.
+    last_exit_code: Option<i32>,
+    /// When the next run is due.
+    next_run: Option<u64>,
+}
+
+pub fn daemon(matches: &ArgMatches<'_>) -> Fallible<()> {
+    let every = parse_age(matches.value_of("every").unwrap())?;
+    if every.as_secs() == 0 {
+        bail!("--every must be greater than zero");
+    }
+    let state_path = match matches.value_of("state") {
+        Some(path) => PathBuf::from(path),
+        None => cache::cargo_home()?.join("prefetch-daemon.json"),
+    };
+    let exe = env::current_exe().with_context(|_| "Failed to find the current executable.")?;
+    // The flags for the fetch are the ones given to `prefetch`, before the
+    // `daemon` subcommand.
+    let mut args: Vec<_> = env::args_os().skip(1).collect();
+    let position = subcommand_position(&args, matches)?;
+    args.truncate(position);
+    if !args.iter().any(|arg| arg == "--incremental") {
+        args.push("--incremental".into());
+        args.push(state_path.with_extension("index.json").into());
+    }
+    let mut state = load_state(&state_path)?;
+    info!(
+        "Fetching every {}, with state in `{}`",
+        matches.value_of("every").unwrap(),
+        state_path.display()
+    );
+    loop {
+        if let Some(next) = state.next_run {
+            let wait = Duration::from_secs(next.saturating_sub(now()));
+            if wait > Duration::from_secs(0) {
+                info!("Next fetch in {} minutes", wait.as_secs() / 60);
+                thread::sleep(wait);
+            }
+        }
+        let started = now();
+        info!("Starting fetch");
+        let code = match Command::new(&exe).args(&args).status() {
+            Ok(status) => status.code(),
+            Err(e) => {
+                warn!("failed to run `{}`: {}", exe.display(), e);
+                None
+            }
+        };
+        match code {
+            Some(0) => {
+                info!("Fetch finished in {} seconds", now() - started);
+                state.last_success = Some(started);
+            }
+            Some(code) => warn!("fetch failed with exit code {}", code),
+            None => warn!("fetch did not finish"),
+        }
+        state.last_run = Some(started);
+        state.last_exit_code = code;
+        state.next_run = Some(started + every.as_secs() + jitter(every).as_secs());
+        if let Err(e) = save_state(&state_path, &state) {
+            warn!("failed to save daemon state: {}", e);
+        }
+    }
+}
+
+/// The position of the `daemon` subcommand in `args`, which may also appear
+/// earlier as the value of a flag, such as `--exclude daemon`.
+///
+/// The subcommand is the first `daemon` where the rest of the arguments
+/// parse to the same daemon flags that clap matched.
+fn subcommand_position(args: &[OsString], matches: &ArgMatches<'_>) -> Fallible<usize> {
+    let same = |candidate: &ArgMatches<'_>| {
+        ["every", "state"]
+            .iter()
+            .all(|name| candidate.value_of(name) == matches.value_of(name))
+    };
+    for (i, arg) in args.iter().enumerate() {
+        if arg != "daemon" {
+            continue;
+        }
+        let rest = ["cargo-prefetch", "prefetch", "daemon"]
+            .iter()
+            .map(OsString::from)
+            .chain(args[i + 1..].iter().cloned());
+        if let Ok(parsed) = crate::app().get_matches_from_safe(rest) {
+            let parsed = parsed
+                .subcommand_matches("prefetch")
+                .and_then(|m| m.subcommand_matches("daemon"));
+            if parsed.is_some_and(same) {
+                return Ok(i);
+            }
+        }
+    }
+    bail!("could not find the `daemon` subcommand in the arguments");
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// A random delay of up to a tenth of the interval.
+fn jitter(every: Duration) -> Duration {
+    let max = every.as_secs() / 10;
+    if max == 0 {
+        return Duration::from_secs(0);
+    }
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_secs(random % max)
+}
+
+fn load_state(path: &Path) -> Fallible<State> {
+    if !path.exists() {
+        debug!("no daemon state at `{}`", path.display());
+        return Ok(State::default());
+    }
+    let contents = fs::read_to_string(path)
+        .with_context(|_| format!("Failed to read `{}`.", path.display()))?;
+    match serde_json::from_str(&contents) {
+        Ok(state) => Ok(state),
+        Err(e) => {
+            // Start over rather than refusing to run.
+            warn!("ignoring invalid daemon state `{}`: {}", path.display(), e);
+            Ok(State::default())
+        }
+    }
+}
+
+fn save_state(path: &Path, state: &State) -> Fallible<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(state)?)
+        .with_context(|_| format!("Failed to write `{}`.", tmp.display()))?;
+    fs::rename(&tmp, path).with_context(|_| format!("Failed to write `{}`.", path.display()))?;
+    Ok(())
+}
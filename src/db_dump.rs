@@ -0,0 +1,266 @@
+//! Rankings computed from the crates.io database dump.
+//!
+//! The dump is published daily at <https://crates.io/data-access>. It is
+//! downloaded into the cargo-prefetch data directory, and the rankings are
+//! computed once per download so that later runs can reuse them.
+
+use crate::cache;
+use crate::exit::NetworkError;
+use crate::http::Http;
+use failure::{bail, format_err, Fallible, ResultExt};
+use flate2::read::GzDecoder;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{debug, info, info_span};
+
+const DB_DUMP_URL: &str = "https://static.crates.io/db-dump.tar.gz";
+
+/// How long a downloaded dump is used before fetching a new one.
+const DUMP_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The number of crates saved in each ranking.
+const RANKING_SIZE: usize = 10000;
+
+/// Rankings computed from the database dump.
+pub struct Rankings {
+    /// The most depended-upon crates.
+    pub top_deps: Vec<String>,
+    /// The most downloaded crates.
+    pub top_downloads: Vec<String>,
+    /// The names of every crate, sorted.
+    pub names: Vec<String>,
+}
+
+/// Load the rankings, downloading a new dump if needed.
+pub fn rankings(http: &Http) -> Fallible<Rankings> {
+    let dir = cache::prefetch_dir()?.join("db-dump");
+    let dump = dir.join("db-dump.tar.gz");
+    let deps_path = dir.join("top-deps.txt");
+    let downloads_path = dir.join("top-downloads.txt");
+    let names_path = dir.join("names.txt");
+    let fresh = fs::metadata(&dump)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age < DUMP_TTL);
+    if !fresh || !deps_path.exists() || !downloads_path.exists() || !names_path.exists() {
+        fs::create_dir_all(&dir)?;
+        download(http, &dump)?;
+        info!("Computing rankings from the database dump...");
+        let rankings =
+            compute(&dump).with_context(|_| format!("Failed to read `{}`.", dump.display()))?;
+        fs::write(&deps_path, rankings.top_deps.join("\n"))?;
+        fs::write(&downloads_path, rankings.top_downloads.join("\n"))?;
+        fs::write(&names_path, rankings.names.join("\n"))?;
+        return Ok(rankings);
+    }
+    debug!("Using rankings from `{}`.", dir.display());
+    let read = |path: &PathBuf| -> Fallible<Vec<String>> {
+        Ok(fs::read_to_string(path)?
+            .lines()
+            .map(String::from)
+            .collect())
+    };
+    Ok(Rankings {
+        top_deps: read(&deps_path)?,
+        top_downloads: read(&downloads_path)?,
+        names: read(&names_path)?,
+    })
+}
+
+/// Download the dump to the given path.
+fn download(http: &Http, path: &Path) -> Fallible<()> {
+    let _span = info_span!("download", url = DB_DUMP_URL).entered();
+    info!("Downloading the crates.io database dump...");
+    let mut response = http.get(DB_DUMP_URL)?;
+    if !response.status().is_success() {
+        return Err(NetworkError(format!(
+            "failed to download `{}`: {}",
+            DB_DUMP_URL,
+            response.status()
+        ))
+        .into());
+    }
+    let tmp = path.with_extension("partial");
+    let mut file = File::create(&tmp)?;
+    io::copy(&mut response, &mut file)
+        .with_context(|_| format!("Failed to download `{}`.", DB_DUMP_URL))?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Data collected from the tables in the dump.
+#[derive(Default)]
+struct Tables {
+    /// Crate id to name.
+    names: HashMap<u32, String>,
+    /// Crate id to all-time downloads.
+    downloads: HashMap<u32, u64>,
+    /// Crate id to the newest non-yanked version (id, number).
+    newest: HashMap<u32, (u32, semver::Version)>,
+    /// Ids of the newest versions, filled in after reading the versions.
+    newest_ids: HashSet<u32>,
+    /// Pairs of (newest version id, crate id depended upon).
+    deps: HashSet<(u32, u32)>,
+}
+
+/// Compute the rankings from the dump.
+///
+/// The dependencies table is much larger than the others, so it is read in a
+/// second pass to only keep the dependencies of the newest versions.
+fn compute(dump: &Path) -> Fallible<Rankings> {
+    let mut tables = Tables::default();
+    for_each_table(dump, |name, input| match name {
+        "crates.csv" => read_crates(input, &mut tables),
+        "crate_downloads.csv" => read_crate_downloads(input, &mut tables),
+        "versions.csv" => read_versions(input, &mut tables),
+        _ => Ok(()),
+    })?;
+    if tables.names.is_empty() {
+        bail!("no crates found in the database dump");
+    }
+    tables.newest_ids = tables.newest.values().map(|(id, _)| *id).collect();
+    for_each_table(dump, |name, input| match name {
+        "dependencies.csv" => read_dependencies(input, &mut tables),
+        _ => Ok(()),
+    })?;
+
+    let mut dep_counts: HashMap<u32, u32> = HashMap::new();
+    for (_, crate_id) in &tables.deps {
+        *dep_counts.entry(*crate_id).or_insert(0) += 1;
+    }
+    let rank = |counts: HashMap<u32, u64>| -> Vec<String> {
+        let mut all: Vec<(u64, &String)> = counts
+            .into_iter()
+            .filter_map(|(id, count)| tables.names.get(&id).map(|name| (count, name)))
+            .collect();
+        all.sort_unstable_by(|a, b| b.cmp(a));
+        all.into_iter()
+            .take(RANKING_SIZE)
+            .map(|(_, name)| name.clone())
+            .collect()
+    };
+    let top_deps = rank(
+        dep_counts
+            .into_iter()
+            .map(|(id, count)| (id, u64::from(count)))
+            .collect(),
+    );
+    let top_downloads = rank(tables.downloads.clone());
+    let mut names: Vec<String> = tables.names.values().cloned().collect();
+    names.sort_unstable();
+    Ok(Rankings {
+        top_deps,
+        top_downloads,
+        names,
+    })
+}
+
+/// Call `f` with the file name and contents of each table in the dump.
+fn for_each_table(
+    dump: &Path,
+    mut f: impl FnMut(&str, &mut dyn Read) -> Fallible<()>,
+) -> Fallible<()> {
+    let mut archive = tar::Archive::new(GzDecoder::new(File::open(dump)?));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+            f(name, &mut entry)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the index of each of the named columns in the CSV headers.
+fn columns<R: Read>(reader: &mut csv::Reader<R>, names: &[&str]) -> Fallible<Vec<Option<usize>>> {
+    let headers = reader.headers()?;
+    Ok(names
+        .iter()
+        .map(|name| headers.iter().position(|h| h == *name))
+        .collect())
+}
+
+fn required(index: Option<usize>, table: &str, column: &str) -> Fallible<usize> {
+    index.ok_or_else(|| format_err!("missing `{}` column in {}", column, table))
+}
+
+fn read_crates(input: &mut dyn Read, tables: &mut Tables) -> Fallible<()> {
+    let mut reader = csv::Reader::from_reader(input);
+    let cols = columns(&mut reader, &["id", "name", "downloads"])?;
+    let id = required(cols[0], "crates.csv", "id")?;
+    let name = required(cols[1], "crates.csv", "name")?;
+    for record in reader.records() {
+        let record = record?;
+        let crate_id: u32 = record[id].parse()?;
+        tables.names.insert(crate_id, record[name].to_string());
+        // Older dumps have the downloads in the crates table.
+        if let Some(downloads) = cols[2] {
+            tables
+                .downloads
+                .insert(crate_id, record[downloads].parse().unwrap_or(0));
+        }
+    }
+    Ok(())
+}
+
+fn read_crate_downloads(input: &mut dyn Read, tables: &mut Tables) -> Fallible<()> {
+    let mut reader = csv::Reader::from_reader(input);
+    let cols = columns(&mut reader, &["crate_id", "downloads"])?;
+    let crate_id = required(cols[0], "crate_downloads.csv", "crate_id")?;
+    let downloads = required(cols[1], "crate_downloads.csv", "downloads")?;
+    for record in reader.records() {
+        let record = record?;
+        tables.downloads.insert(
+            record[crate_id].parse()?,
+            record[downloads].parse().unwrap_or(0),
+        );
+    }
+    Ok(())
+}
+
+fn read_versions(input: &mut dyn Read, tables: &mut Tables) -> Fallible<()> {
+    let mut reader = csv::Reader::from_reader(input);
+    let cols = columns(&mut reader, &["id", "crate_id", "num", "yanked"])?;
+    let id = required(cols[0], "versions.csv", "id")?;
+    let crate_id = required(cols[1], "versions.csv", "crate_id")?;
+    let num = required(cols[2], "versions.csv", "num")?;
+    let yanked = required(cols[3], "versions.csv", "yanked")?;
+    for record in reader.records() {
+        let record = record?;
+        if &record[yanked] == "t" {
+            continue;
+        }
+        let version = match semver::Version::parse(&record[num]) {
+            Ok(version) => version,
+            Err(_) => continue,
+        };
+        let version_id: u32 = record[id].parse()?;
+        let newest = tables
+            .newest
+            .entry(record[crate_id].parse()?)
+            .or_insert_with(|| (version_id, version.clone()));
+        if version > newest.1 {
+            *newest = (version_id, version);
+        }
+    }
+    Ok(())
+}
+
+fn read_dependencies(input: &mut dyn Read, tables: &mut Tables) -> Fallible<()> {
+    let mut reader = csv::Reader::from_reader(input);
+    let cols = columns(&mut reader, &["version_id", "crate_id"])?;
+    let version_id = required(cols[0], "dependencies.csv", "version_id")?;
+    let crate_id = required(cols[1], "dependencies.csv", "crate_id")?;
+    for record in reader.records() {
+        let record = record?;
+        let version: u32 = record[version_id].parse()?;
+        if tables.newest_ids.contains(&version) {
+            tables.deps.insert((version, record[crate_id].parse()?));
+        }
+    }
+    Ok(())
+}
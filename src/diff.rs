@@ -0,0 +1,127 @@
+//! The `diff-crate` subcommand for comparing two cached versions of a crate.
+
+use cargo_prefetch::cache;
+use clap::ArgMatches;
+use failure::{bail, format_err, Fallible, ResultExt};
+use flate2::read::GzDecoder;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use tracing::info;
+
+/// Contents of a `.crate` file, keyed by the path within the package.
+type Contents = BTreeMap<PathBuf, Vec<u8>>;
+
+pub fn diff_crate(matches: &ArgMatches<'_>) -> Fallible<()> {
+    let old = matches.value_of("old").unwrap();
+    let new = matches.value_of("new").unwrap();
+    let text = matches.is_present("text");
+
+    let old_contents = load(old)?;
+    let new_contents = load(new)?;
+
+    let mut added = 0;
+    let mut removed = 0;
+    let mut modified = 0;
+    for (path, old_data) in &old_contents {
+        match new_contents.get(path) {
+            Some(new_data) => {
+                if old_data != new_data {
+                    modified += 1;
+                    println!("M  {}", path.display());
+                    if text {
+                        print_text_diff(path, old_data, new_data);
+                    }
+                }
+            }
+            None => {
+                removed += 1;
+                println!("D  {}", path.display());
+                if text {
+                    print_text_diff(path, old_data, &[]);
+                }
+            }
+        }
+    }
+    for (path, new_data) in &new_contents {
+        if !old_contents.contains_key(path) {
+            added += 1;
+            println!("A  {}", path.display());
+            if text {
+                print_text_diff(path, &[], new_data);
+            }
+        }
+    }
+    info!(
+        "{} files added, {} removed, {} modified",
+        added, removed, modified
+    );
+    Ok(())
+}
+
+/// Load the contents of a cached crate from a `name@version` spec.
+fn load(spec: &str) -> Fallible<Contents> {
+    let mut splits = spec.splitn(2, '@');
+    let name = splits.next().unwrap();
+    let version = splits
+        .next()
+        .map(|v| v.trim_start_matches('='))
+        .ok_or_else(|| format_err!("expected `name@version`, got `{}`", spec))?;
+    let path = match cache::find_crate_file(name, version)? {
+        Some(path) => path,
+        None => bail!(
+            "{} {} is not in the cache, run `cargo prefetch {}@={}` first",
+            name,
+            version,
+            name,
+            version
+        ),
+    };
+    let contents =
+        read_crate(&path).with_context(|_| format!("Failed to read `{}`.", path.display()))?;
+    Ok(contents)
+}
+
+/// Read all files from a `.crate` file.
+///
+/// The leading `name-version` directory is stripped from the paths.
+fn read_crate(path: &Path) -> Fallible<Contents> {
+    let file = File::open(path)?;
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+    let mut contents = Contents::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let entry_path: PathBuf = entry
+            .path()?
+            .components()
+            .skip(1)
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        contents.insert(entry_path, data);
+    }
+    Ok(contents)
+}
+
+/// Print a unified diff of a file to stdout.
+fn print_text_diff(path: &Path, old: &[u8], new: &[u8]) {
+    let (old, new) = match (std::str::from_utf8(old), std::str::from_utf8(new)) {
+        (Ok(old), Ok(new)) => (old, new),
+        _ => {
+            println!("Binary file {} differs", path.display());
+            return;
+        }
+    };
+    let path = path.display().to_string();
+    let diff = similar::TextDiff::from_lines(old, new);
+    print!(
+        "{}",
+        diff.unified_diff()
+            .header(&format!("a/{}", path), &format!("b/{}", path))
+    );
+}
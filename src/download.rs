@@ -0,0 +1,134 @@
+//! Downloading `.crate` files directly from crates.io.
+//!
+//! This is only used for versions that Cargo can't be asked to fetch, such as
+//! yanked versions, which Cargo won't select when resolving the temp project.
+
+use crate::cache;
+use crate::exit::NetworkError;
+use crate::http::Http;
+use crate::index;
+use failure::{bail, Fallible, ResultExt};
+use serde_derive::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+use tracing::{info_span, trace, warn};
+
+/// The `config.json` file of the index.
+#[derive(Deserialize)]
+struct IndexConfig {
+    dl: String,
+}
+
+/// Returns the download URL for a version, following the `dl` template in
+/// the index config.
+pub fn download_url(http: &Http, name: &str, version: &str, cksum: &str) -> Fallible<String> {
+    let url = format!("{}config.json", index::CRATES_IO_SPARSE);
+    let config: IndexConfig = serde_json::from_str(&http.get_cached(&url)?)
+        .with_context(|_| format!("Failed to parse `{}`.", url))?;
+    let markers = [
+        "{crate}",
+        "{version}",
+        "{prefix}",
+        "{lowerprefix}",
+        "{sha256-checksum}",
+    ];
+    if !markers.iter().any(|marker| config.dl.contains(marker)) {
+        return Ok(format!("{}/{}/{}/download", config.dl, name, version));
+    }
+    let prefix = match name.len() {
+        1 => "1".to_string(),
+        2 => "2".to_string(),
+        3 => format!("3/{}", &name[..1]),
+        _ => format!("{}/{}", &name[..2], &name[2..4]),
+    };
+    Ok(config
+        .dl
+        .replace("{crate}", name)
+        .replace("{version}", version)
+        .replace("{prefix}", &prefix)
+        .replace("{lowerprefix}", &prefix.to_lowercase())
+        .replace("{sha256-checksum}", cksum))
+}
+
+/// The state of a version in Cargo's cache.
+pub enum Cached {
+    /// The file is cached with the expected checksum.
+    Valid,
+    Missing,
+    /// The file is cached, but with the wrong checksum.
+    Corrupt(PathBuf),
+}
+
+/// Check whether a version is in Cargo's cache with the given checksum.
+pub fn check_cached(name: &str, version: &str, cksum: &str) -> Fallible<Cached> {
+    let path = cache::crates_io_cache_dir()?.join(format!("{}-{}.crate", name, version));
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(_) => return Ok(Cached::Missing),
+    };
+    trace!("Checking {}", path.display());
+    if format!("{:x}", Sha256::digest(&data)) == cksum {
+        Ok(Cached::Valid)
+    } else {
+        Ok(Cached::Corrupt(path))
+    }
+}
+
+/// Returns whether a version is in Cargo's cache with the given checksum.
+///
+/// A cached file with the wrong checksum is removed, since Cargo won't
+/// download it again otherwise. The caller should hold the package cache
+/// lock.
+pub fn is_cached(name: &str, version: &str, cksum: &str) -> Fallible<bool> {
+    match check_cached(name, version, cksum)? {
+        Cached::Valid => Ok(true),
+        Cached::Missing => Ok(false),
+        Cached::Corrupt(path) => {
+            warn!("removing corrupt cached file `{}`", path.display());
+            fs::remove_file(&path)?;
+            Ok(false)
+        }
+    }
+}
+
+/// Download a version into Cargo's cache, unless it is already there.
+///
+/// The download is checked against the expected SHA-256 `cksum`. The caller
+/// should hold the package cache lock.
+pub fn download_crate(http: &Http, name: &str, version: &str, cksum: &str) -> Fallible<PathBuf> {
+    let _span = info_span!("download", name, version).entered();
+    let path = cache::crates_io_cache_dir()?.join(format!("{}-{}.crate", name, version));
+    if is_cached(name, version, cksum)? {
+        return Ok(path);
+    }
+    let url = download_url(http, name, version, cksum)?;
+    let mut response = http.get(&url)?;
+    if !response.status().is_success() {
+        return Err(NetworkError(format!(
+            "failed to download `{}`: {}",
+            url,
+            response.status()
+        ))
+        .into());
+    }
+    let mut data = Vec::new();
+    response
+        .copy_to(&mut data)
+        .with_context(|_| format!("Failed to download `{}`.", url))?;
+    let actual = format!("{:x}", Sha256::digest(&data));
+    if actual != cksum {
+        bail!(
+            "checksum mismatch for {}-{}: expected {}, got {}",
+            name,
+            version,
+            cksum,
+            actual
+        );
+    }
+    fs::create_dir_all(path.parent().unwrap())?;
+    let tmp = path.with_extension("partial");
+    fs::write(&tmp, &data)?;
+    fs::rename(&tmp, &path)?;
+    Ok(path)
+}
@@ -0,0 +1,115 @@
+//! Exit codes, so that scripts can tell what kind of failure happened.
+//!
+//! | Code | Meaning |
+//! |------|---------|
+//! | 0    | Everything was fetched. |
+//! | 1    | An error, such as an invalid argument. |
+//! | 2    | The run finished, but some crates were skipped or failed. |
+//! | 3    | A network or API request failed. |
+//! | 4    | Cargo failed to run. |
+//! | 130  | The run was interrupted with Ctrl-C. |
+
+use failure::{Error, Fail};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub const SUCCESS: i32 = 0;
+pub const ERROR: i32 = 1;
+pub const PARTIAL: i32 = 2;
+pub const NETWORK: i32 = 3;
+pub const CARGO: i32 = 4;
+pub const INTERRUPTED: i32 = 130;
+
+/// Whether some crates were skipped or failed during the run.
+static INCOMPLETE: AtomicBool = AtomicBool::new(false);
+
+/// Record that some crates were skipped or failed, but the run continued.
+pub fn mark_incomplete() {
+    INCOMPLETE.store(true, Ordering::Relaxed);
+}
+
+/// Returns the exit code for the result of the run.
+pub fn code(result: &Result<(), Error>) -> i32 {
+    match result {
+        Ok(()) if INCOMPLETE.load(Ordering::Relaxed) => PARTIAL,
+        Ok(()) => SUCCESS,
+        Err(e) => {
+            if e.iter_chain()
+                .any(|cause| cause.downcast_ref::<Interrupted>().is_some())
+            {
+                INTERRUPTED
+            } else if e
+                .iter_chain()
+                .any(|cause| cause.downcast_ref::<CargoError>().is_some())
+            {
+                CARGO
+            } else if e.iter_chain().any(|cause| {
+                cause.downcast_ref::<NetworkError>().is_some()
+                    || cause.downcast_ref::<reqwest::Error>().is_some()
+            }) {
+                NETWORK
+            } else {
+                ERROR
+            }
+        }
+    }
+}
+
+/// A request that didn't succeed.
+#[derive(Debug)]
+pub struct NetworkError(pub String);
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Fail for NetworkError {}
+
+/// Cargo (or another command run for it) exited with an error.
+#[derive(Debug)]
+pub struct CargoError(pub String);
+
+impl fmt::Display for CargoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Fail for CargoError {}
+
+/// The fetch was stopped with Ctrl-C.
+#[derive(Debug)]
+pub struct Interrupted;
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("interrupted, run again with `--resume` to continue")
+    }
+}
+
+impl Fail for Interrupted {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use failure::{format_err, ResultExt};
+
+    #[test]
+    fn codes() {
+        let err = |e: Error| code(&Err(e));
+        assert_eq!(code(&Ok(())), SUCCESS);
+        assert_eq!(err(format_err!("invalid argument")), ERROR);
+        assert_eq!(err(NetworkError("503".to_string()).into()), NETWORK);
+        assert_eq!(err(CargoError("exit 101".to_string()).into()), CARGO);
+        assert_eq!(err(Interrupted.into()), INTERRUPTED);
+        // The kind of failure is found under added context.
+        let wrapped: Result<(), Error> = Err(NetworkError("503".to_string()).into());
+        let wrapped = wrapped.context("Failed to fetch the top crates.");
+        assert_eq!(err(wrapped.unwrap_err().into()), NETWORK);
+        mark_incomplete();
+        assert_eq!(code(&Ok(())), PARTIAL);
+        assert_eq!(err(format_err!("invalid argument")), ERROR);
+    }
+}
@@ -0,0 +1,85 @@
+//! Reading files from GitHub repositories, for `--repo`.
+//!
+//! Files are fetched with the contents API. Credentials for `api.github.com`
+//! can be given in `~/.netrc` or with `--credential-process`, which raises
+//! GitHub's rate limit and allows private repositories.
+
+use crate::http::{self, Http};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use failure::{bail, format_err, Fallible, ResultExt};
+use serde_derive::Deserialize;
+use tracing::{debug, info_span};
+
+const GITHUB_API: &str = "https://api.github.com";
+
+/// A repository, from a spec such as `org/name` or `org/name@ref`.
+pub struct Repo {
+    pub owner: String,
+    pub name: String,
+    /// A branch, tag, or commit, or `None` for the default branch.
+    pub reference: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Contents {
+    #[serde(default)]
+    content: String,
+    #[serde(default)]
+    encoding: String,
+    /// Used for files over 1 MB, which the API doesn't include the contents
+    /// of.
+    download_url: Option<String>,
+}
+
+impl Repo {
+    pub fn parse(spec: &str) -> Fallible<Repo> {
+        let mut splits = spec.splitn(2, '@');
+        let path = splits.next().unwrap();
+        let reference = splits.next().map(String::from);
+        match path.split('/').collect::<Vec<_>>()[..] {
+            [owner, name] if !owner.is_empty() && !name.is_empty() => Ok(Repo {
+                owner: owner.to_string(),
+                name: name.trim_end_matches(".git").to_string(),
+                reference,
+            }),
+            _ => bail!(
+                "invalid repository `{}`, expected a value such as `org/name` or `org/name@ref`",
+                spec
+            ),
+        }
+    }
+
+    /// Read a file from the repository, returning `None` if it doesn't exist.
+    pub fn read_file(&self, http: &Http, path: &str) -> Fallible<Option<String>> {
+        let mut url = format!(
+            "{}/repos/{}/{}/contents/{}",
+            GITHUB_API, self.owner, self.name, path
+        );
+        if let Some(reference) = &self.reference {
+            url.push_str(&format!("?ref={}", reference));
+        }
+        let _span = info_span!("github", path).entered();
+        let mut response = http.get(&url)?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            debug!("{} not found in {}/{}", path, self.owner, self.name);
+            return Ok(None);
+        }
+        http::check_status(&url, &mut response)?;
+        let contents: Contents = response
+            .json()
+            .with_context(|_| format!("Failed to parse response from `{}`.", url))?;
+        if contents.encoding == "base64" {
+            let bytes = BASE64
+                .decode(contents.content.replace('\n', ""))
+                .map_err(|e| format_err!("invalid contents from `{}`: {}", url, e))?;
+            return Ok(Some(String::from_utf8(bytes)?));
+        }
+        let download_url = contents
+            .download_url
+            .ok_or_else(|| format_err!("no contents for `{}` in `{}`", path, url))?;
+        let mut response = http.get(&download_url)?;
+        http::check_status(&download_url, &mut response)?;
+        Ok(Some(response.text()?))
+    }
+}
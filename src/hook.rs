@@ -0,0 +1,90 @@
+//! `--post-hook`, a command run after a successful fetch.
+//!
+//! The command is run with the shell, with these environment variables:
+//!
+//! - `CARGO_PREFETCH_PACKAGES`: every package fetched, as space-separated
+//!   `name@version`.
+//! - `CARGO_PREFETCH_DOWNLOADED`: the packages that were downloaded by this
+//!   run, instead of already cached.
+//! - `CARGO_PREFETCH_COUNT` and `CARGO_PREFETCH_DOWNLOADED_COUNT`: the number
+//!   of each.
+//! - `CARGO_PREFETCH_LOCK` and `CARGO_PREFETCH_REPORT`: the paths given to
+//!   `--write-lock` and `--report`, if any.
+//! - `CARGO_HOME`: the cache that was fetched into.
+
+use cargo_prefetch::cache;
+use cargo_prefetch::message::{Event, Message};
+use cargo_prefetch::prefetch_lock::LockedPackage;
+use failure::{bail, Fallible, ResultExt};
+use std::process::Command;
+use tracing::info;
+
+/// Paths of files written by the run, passed to the hook.
+pub struct Outputs<'a> {
+    pub lock: Option<&'a str>,
+    pub report: Option<&'a str>,
+}
+
+/// Run the hook command.
+pub fn run(
+    hook: &str,
+    pkgs: &[LockedPackage],
+    messages: &[Message],
+    outputs: &Outputs<'_>,
+) -> Fallible<()> {
+    let downloaded: Vec<&LockedPackage> = pkgs
+        .iter()
+        .filter(|pkg| {
+            messages.iter().any(|m| {
+                m.event == Event::Done
+                    && m.name == pkg.name
+                    && m.version.as_deref() == Some(&pkg.version[..])
+            })
+        })
+        .collect();
+    let join = |pkgs: &mut dyn Iterator<Item = &LockedPackage>| {
+        pkgs.map(|pkg| format!("{}@{}", pkg.name, pkg.version))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let mut cmd = shell(hook);
+    cmd.env("CARGO_PREFETCH_PACKAGES", join(&mut pkgs.iter()))
+        .env(
+            "CARGO_PREFETCH_DOWNLOADED",
+            join(&mut downloaded.iter().copied()),
+        )
+        .env("CARGO_PREFETCH_COUNT", pkgs.len().to_string())
+        .env(
+            "CARGO_PREFETCH_DOWNLOADED_COUNT",
+            downloaded.len().to_string(),
+        )
+        .env("CARGO_HOME", cache::cargo_home()?);
+    if let Some(lock) = outputs.lock {
+        cmd.env("CARGO_PREFETCH_LOCK", lock);
+    }
+    if let Some(report) = outputs.report {
+        cmd.env("CARGO_PREFETCH_REPORT", report);
+    }
+    info!("Running post hook: {}", hook);
+    let status = cmd
+        .status()
+        .with_context(|_| format!("Failed to launch post hook `{}`.", hook))?;
+    if !status.success() {
+        bail!("post hook `{}` failed: {}", hook, status);
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C").arg(command);
+    cmd
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
@@ -0,0 +1,607 @@
+//! HTTP requests, with support for authentication and caching.
+//!
+//! Credentials are only sent to hosts they were configured for. The
+//! `--credential-process` command (which behaves like Cargo's
+//! `cargo:token-from-stdout` provider) is run for the `--credential-host`s
+//! and the host of a mirror of the API, and otherwise a `machine` entry for
+//! the host in `~/.netrc` is used. The netrc `default` entry is ignored, so
+//! that a private mirror's credentials aren't sent to crates.io or GitHub.
+//!
+//! API responses are cached in the cargo-prefetch data directory. They are
+//! used as-is until the TTL expires, and then revalidated with their ETag.
+//! Index files are cached the same way, but always revalidated.
+//!
+//! Rate limited requests (429) are retried after the delay given in the
+//! `Retry-After` header. Network and server errors are retried `net.retry`
+//! times, and with `net.offline` nothing is sent and cached responses are
+//! used however old they are.
+
+use crate::cache;
+use crate::exit::NetworkError;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use failure::{bail, format_err, Fallible, ResultExt};
+use reqwest::header::{AUTHORIZATION, ETAG, IF_NONE_MATCH, RETRY_AFTER, USER_AGENT};
+use reqwest::StatusCode;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, debug_span, trace, warn};
+
+pub struct Http {
+    client: reqwest::Client,
+    /// Command to run to get a token for a host.
+    credential_process: Option<String>,
+    /// The hosts that the credential process is run for.
+    credential_hosts: Vec<String>,
+    /// Cache of `Authorization` values for each host.
+    auth: Mutex<HashMap<String, Option<String>>>,
+    /// How long cached responses are used without revalidating.
+    cache_ttl: Duration,
+    user_agent: String,
+    /// The base URL of the crates.io API, without a trailing slash.
+    api_url: String,
+    /// How many times a request that fails with a network error or a server
+    /// error is retried, from `net.retry`.
+    retries: u32,
+    /// Don't send requests, and use cached responses however old they are,
+    /// from `--offline` or `net.offline`.
+    offline: bool,
+    /// How many requests were rate limited or retried after failing.
+    throttled: AtomicU64,
+}
+
+/// The `User-Agent` used unless one is configured.
+pub const DEFAULT_USER_AGENT: &str = concat!(
+    "cargo-prefetch/",
+    env!("CARGO_PKG_VERSION"),
+    " (https://github.com/ehuss/cargo-prefetch)"
+);
+
+/// The crates.io API, used unless another is configured.
+pub const DEFAULT_API_URL: &str = "https://crates.io";
+
+/// How many times a rate limited request is retried.
+const MAX_RETRIES: u32 = 8;
+
+/// How many times other failed requests are retried, unless `net.retry` is
+/// set. This is the same as Cargo's default.
+pub const DEFAULT_NET_RETRY: u32 = 3;
+
+/// A cached response.
+#[derive(Deserialize, Serialize)]
+struct CacheEntry {
+    url: String,
+    etag: Option<String>,
+    /// When the response was last fetched or revalidated, in seconds since
+    /// the Unix epoch.
+    fetched: u64,
+    body: String,
+}
+
+impl Http {
+    pub fn new(
+        credential_process: Option<String>,
+        mut credential_hosts: Vec<String>,
+        cache_ttl: Duration,
+        user_agent: String,
+        api_url: &str,
+        retries: u32,
+        offline: bool,
+    ) -> Http {
+        let api_url = api_url.trim_end_matches('/').to_string();
+        // A mirror of the API is what a credential process is usually for.
+        if let Some(host) = reqwest::Url::parse(&api_url)
+            .ok()
+            .and_then(|url| url.host_str().map(String::from))
+        {
+            if !is_public_host(&host) && !credential_hosts.contains(&host) {
+                credential_hosts.push(host);
+            }
+        }
+        Http {
+            client: reqwest::Client::new(),
+            credential_process,
+            credential_hosts,
+            auth: Mutex::new(HashMap::new()),
+            cache_ttl,
+            user_agent,
+            api_url,
+            retries,
+            offline,
+            throttled: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Default for Http {
+    /// A client for crates.io without a credential process, caching API
+    /// responses for a day.
+    fn default() -> Http {
+        Http::new(
+            None,
+            Vec::new(),
+            Duration::from_secs(24 * 60 * 60),
+            DEFAULT_USER_AGENT.to_string(),
+            DEFAULT_API_URL,
+            DEFAULT_NET_RETRY,
+            false,
+        )
+    }
+}
+
+impl Http {
+    /// Returns whether requests are disabled.
+    pub fn offline(&self) -> bool {
+        self.offline
+    }
+
+    /// Returns the base URL of the crates.io API.
+    pub fn api_url(&self) -> &str {
+        &self.api_url
+    }
+
+    /// Returns how many requests so far were rate limited or retried after
+    /// failing, a sign of sending too many at once.
+    pub fn throttled(&self) -> u64 {
+        self.throttled.load(Ordering::SeqCst)
+    }
+
+    /// Send a GET request.
+    pub fn get(&self, url: &str) -> Fallible<reqwest::Response> {
+        self.send(url, None)
+    }
+
+    /// Send a request, waiting and retrying if the server is rate limiting,
+    /// or if it fails with a network or server error.
+    fn send(&self, url: &str, etag: Option<&str>) -> Fallible<reqwest::Response> {
+        let _span = debug_span!("request", url).entered();
+        if self.offline {
+            return Err(NetworkError(format!("can't fetch `{}` in offline mode", url)).into());
+        }
+        let mut attempt = 0;
+        let mut failures = 0;
+        loop {
+            debug!("Sending request: {}", url);
+            let mut request = self
+                .client
+                .get(url)
+                .header(USER_AGENT, &self.user_agent[..]);
+            if let Some(auth) = self.authorization(url)? {
+                request = request.header(AUTHORIZATION, auth);
+            }
+            if let Some(etag) = etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            let response = match request.send() {
+                Ok(response) if response.status().is_server_error() && failures < self.retries => {
+                    failures += 1;
+                    self.throttled.fetch_add(1, Ordering::SeqCst);
+                    warn!(
+                        "server error {} for `{}`, retrying ({} of {})",
+                        response.status(),
+                        url,
+                        failures,
+                        self.retries
+                    );
+                    thread::sleep(Duration::from_secs(failures.into()));
+                    continue;
+                }
+                Err(e) if failures < self.retries => {
+                    failures += 1;
+                    self.throttled.fetch_add(1, Ordering::SeqCst);
+                    warn!(
+                        "failed to fetch `{}`, retrying ({} of {}): {}",
+                        url, failures, self.retries, e
+                    );
+                    thread::sleep(Duration::from_secs(failures.into()));
+                    continue;
+                }
+                result => result.with_context(|_| format!("Failed to fetch `{}`.", url))?,
+            };
+            trace!("Response status {} for {}", response.status(), url);
+            if response.status() != StatusCode::TOO_MANY_REQUESTS || attempt == MAX_RETRIES {
+                return Ok(response);
+            }
+            attempt += 1;
+            self.throttled.fetch_add(1, Ordering::SeqCst);
+            // `Retry-After` may also be an HTTP date, which isn't worth
+            // parsing, so that falls back to backing off.
+            let delay = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .unwrap_or(1 << attempt);
+            debug!(
+                "Rate limited by the server, retrying in {} seconds ({} of {})",
+                delay, attempt, MAX_RETRIES
+            );
+            thread::sleep(Duration::from_secs(delay));
+        }
+    }
+
+    /// Send a GET request and return the body, using the response cache.
+    pub fn get_cached(&self, url: &str) -> Fallible<String> {
+        Ok(self
+            .request_cached(url, self.cache_ttl, false)?
+            .unwrap_or_default())
+    }
+
+    /// Send a GET request and return the body, revalidating the cached
+    /// response with its ETag every time. Returns `None` if the server
+    /// doesn't have it.
+    pub fn get_revalidated(&self, url: &str) -> Fallible<Option<String>> {
+        self.request_cached(url, Duration::from_secs(0), true)
+    }
+
+    /// Send a GET request using the response cache, where responses younger
+    /// than `ttl` are used without revalidating. With `missing_ok`, a 404 or
+    /// 410 returns `None` instead of an error.
+    fn request_cached(
+        &self,
+        url: &str,
+        ttl: Duration,
+        missing_ok: bool,
+    ) -> Fallible<Option<String>> {
+        let path = cache::prefetch_dir()?
+            .join("http-cache")
+            .join(format!("{:x}.json", Sha256::digest(url.as_bytes())));
+        let cached: Option<CacheEntry> = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .filter(|entry: &CacheEntry| entry.url == url);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        if let Some(entry) = &cached {
+            if self.offline || now.saturating_sub(entry.fetched) < ttl.as_secs() {
+                debug!("Using cached response for {}", url);
+                return Ok(Some(entry.body.clone()));
+            }
+        }
+
+        let etag = cached.as_ref().and_then(|entry| entry.etag.as_deref());
+        let mut response = match self.send(url, etag) {
+            Ok(response) => response,
+            Err(e) => match cached {
+                Some(entry) => {
+                    warn!("{}", e);
+                    warn!("using a cached response for {}", url);
+                    return Ok(Some(entry.body));
+                }
+                None => return Err(e),
+            },
+        };
+        let entry = match cached {
+            Some(cached)
+                if response.status().is_server_error()
+                    || response.status() == StatusCode::TOO_MANY_REQUESTS =>
+            {
+                warn!(
+                    "{} returned {}, using a cached response",
+                    url,
+                    response.status()
+                );
+                return Ok(Some(cached.body));
+            }
+            Some(cached) if response.status() == StatusCode::NOT_MODIFIED => {
+                debug!("Cached response for {} is still valid", url);
+                CacheEntry {
+                    fetched: now,
+                    ..cached
+                }
+            }
+            _ if missing_ok
+                && matches!(response.status(), StatusCode::NOT_FOUND | StatusCode::GONE) =>
+            {
+                return Ok(None);
+            }
+            _ => {
+                check_status(url, &mut response)?;
+                CacheEntry {
+                    url: url.to_string(),
+                    etag: response
+                        .headers()
+                        .get(ETAG)
+                        .and_then(|v| v.to_str().ok())
+                        .map(String::from),
+                    fetched: now,
+                    body: response.text()?,
+                }
+            }
+        };
+        // Failing to write the cache shouldn't fail the request.
+        let _ = fs::create_dir_all(path.parent().unwrap())
+            .and_then(|_| fs::write(&path, serde_json::to_string(&entry).unwrap()));
+        Ok(Some(entry.body))
+    }
+
+    /// Returns the `Authorization` header value to use for the given URL.
+    fn authorization(&self, url: &str) -> Fallible<Option<String>> {
+        let parsed = reqwest::Url::parse(url)?;
+        let host = match parsed.host_str() {
+            Some(host) => host.to_string(),
+            None => return Ok(None),
+        };
+        let mut auth = self.auth.lock().unwrap();
+        if let Some(value) = auth.get(&host) {
+            return Ok(value.clone());
+        }
+        let value = match &self.credential_process {
+            Some(process) if self.credential_hosts.contains(&host) => {
+                let origin = parsed.origin().ascii_serialization();
+                Some(run_credential_process(process, &origin)?)
+            }
+            _ => netrc_auth(&host)?,
+        };
+        auth.insert(host, value.clone());
+        Ok(value)
+    }
+}
+
+/// Returns an error with the details of the response if it did not succeed.
+pub fn check_status(url: &str, response: &mut reqwest::Response) -> Fallible<()> {
+    let status = response.status();
+    if !status.is_success() {
+        let headers: Vec<_> = response
+            .headers()
+            .iter()
+            .map(|(key, value)| format!("{}: {:?}", key, value))
+            .collect();
+        return Err(NetworkError(format!(
+            "Failed to fetch `{}`.\n\
+            Status: {}\n\
+            Headers:\n\
+            {}\n\
+            {}
+            ",
+            url,
+            status,
+            headers.join("\n"),
+            response.text().unwrap_or_else(|e| format!("{:?}", e))
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Whether a host is one of the public services used by default, crates.io
+/// and GitHub.
+fn is_public_host(host: &str) -> bool {
+    ["crates.io", "github.com", "githubusercontent.com"]
+        .iter()
+        .any(|domain| host == *domain || host.ends_with(&format!(".{}", domain)))
+}
+
+/// Run the credential process, and return the token it prints to stdout.
+fn run_credential_process(process: &str, origin: &str) -> Fallible<String> {
+    let mut args = process.split_whitespace();
+    let program = args
+        .next()
+        .ok_or_else(|| format_err!("credential process must not be empty"))?;
+    let output = Command::new(program)
+        .args(args)
+        .env("CARGO_REGISTRY_INDEX_URL", origin)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|_| format!("Failed to launch credential process `{}`.", process))?;
+    if !output.status.success() {
+        bail!("credential process `{}` failed: {}", process, output.status);
+    }
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|_| format_err!("credential process `{}` printed invalid UTF-8", process))?;
+    let token = stdout.lines().next().unwrap_or("").trim();
+    if token.is_empty() {
+        bail!("credential process `{}` did not print a token", process);
+    }
+    Ok(token.to_string())
+}
+
+/// Look up basic-auth credentials for the host in the netrc file.
+///
+/// Only a `machine` entry for the host is used, not the `default` entry.
+fn netrc_auth(host: &str) -> Fallible<Option<String>> {
+    let path = match env::var_os("NETRC") {
+        Some(path) => PathBuf::from(path),
+        None => match home::home_dir() {
+            Some(home) => home.join(".netrc"),
+            None => return Ok(None),
+        },
+    };
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(_) => return Ok(None),
+    };
+    let entries = parse_netrc(&contents);
+    let entry = entries
+        .iter()
+        .find(|entry| entry.machine.as_deref() == Some(host));
+    Ok(entry.map(|entry| {
+        let credentials = format!(
+            "{}:{}",
+            entry.login,
+            entry.password.as_deref().unwrap_or("")
+        );
+        format!("Basic {}", BASE64.encode(credentials))
+    }))
+}
+
+/// An entry in a netrc file.
+struct NetrcEntry {
+    /// The host name, `None` for the `default` entry.
+    machine: Option<String>,
+    login: String,
+    password: Option<String>,
+}
+
+/// Parse the contents of a netrc file.
+fn parse_netrc(contents: &str) -> Vec<NetrcEntry> {
+    let mut entries: Vec<NetrcEntry> = Vec::new();
+    let mut tokens = contents.split_whitespace();
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" | "default" => {
+                let machine = if token == "machine" {
+                    tokens.next().map(|m| m.to_string())
+                } else {
+                    None
+                };
+                entries.push(NetrcEntry {
+                    machine,
+                    login: String::new(),
+                    password: None,
+                });
+            }
+            "login" => {
+                if let (Some(entry), Some(login)) = (entries.last_mut(), tokens.next()) {
+                    entry.login = login.to_string();
+                }
+            }
+            "password" => {
+                if let (Some(entry), Some(password)) = (entries.last_mut(), tokens.next()) {
+                    entry.password = Some(password.to_string());
+                }
+            }
+            "account" => {
+                tokens.next();
+            }
+            _ => {}
+        }
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn http(credential_process: Option<&str>, api_url: &str) -> Http {
+        Http::new(
+            credential_process.map(String::from),
+            Vec::new(),
+            Duration::from_secs(0),
+            DEFAULT_USER_AGENT.to_string(),
+            api_url,
+            0,
+            false,
+        )
+    }
+
+    #[test]
+    fn netrc() {
+        let entries = parse_netrc(
+            "machine mirror.example.com login user password secret\n\
+             default login anonymous",
+        );
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].machine.as_deref(), Some("mirror.example.com"));
+        assert_eq!(entries[0].login, "user");
+        assert_eq!(entries[0].password.as_deref(), Some("secret"));
+        assert_eq!(entries[1].machine, None);
+        assert_eq!(entries[1].login, "anonymous");
+        assert_eq!(entries[1].password, None);
+    }
+
+    #[test]
+    fn credential_hosts() {
+        assert!(http(None, DEFAULT_API_URL).credential_hosts.is_empty());
+        assert!(http(None, "https://index.crates.io")
+            .credential_hosts
+            .is_empty());
+        assert_eq!(
+            http(None, "https://mirror.example.com/").credential_hosts,
+            ["mirror.example.com"]
+        );
+        assert!(is_public_host("crates.io"));
+        assert!(is_public_host("static.crates.io"));
+        assert!(is_public_host("raw.githubusercontent.com"));
+        assert!(!is_public_host("notcrates.io"));
+    }
+
+    #[test]
+    fn authorization() {
+        let dir = tempfile::tempdir().unwrap();
+        let netrc = dir.path().join("netrc");
+        fs::write(
+            &netrc,
+            "machine mirror.example.com login u password p\n\
+             default login me password secret\n",
+        )
+        .unwrap();
+        env::set_var("NETRC", &netrc);
+        // `false` fails if it is run, so the netrc entry must be used.
+        let http = http(Some("false"), DEFAULT_API_URL);
+        let auth = |url| http.authorization(url).unwrap();
+        assert_eq!(
+            auth("https://mirror.example.com/index/config.json"),
+            Some("Basic dTpw".to_string())
+        );
+        // The `default` entry isn't sent to the public hosts.
+        assert_eq!(auth("https://crates.io/api/v1/crates"), None);
+        assert_eq!(
+            auth("https://static.crates.io/crates/a/a-1.0.0.crate"),
+            None
+        );
+        assert_eq!(auth("https://github.com/rust-lang/crates.io-index"), None);
+    }
+
+    /// Serve each of `responses` to one request, and return the
+    /// `If-None-Match` header of each request.
+    fn serve(responses: Vec<&'static str>) -> (String, thread::JoinHandle<Vec<Option<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/api/v1/crates", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            responses
+                .into_iter()
+                .map(|response| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let mut etag = None;
+                    for line in BufReader::new(stream.try_clone().unwrap()).lines() {
+                        let line = line.unwrap();
+                        if line.is_empty() {
+                            break;
+                        }
+                        if let Some((name, value)) = line.split_once(':') {
+                            if name.eq_ignore_ascii_case("if-none-match") {
+                                etag = Some(value.trim().to_string());
+                            }
+                        }
+                    }
+                    stream.write_all(response.as_bytes()).unwrap();
+                    etag
+                })
+                .collect()
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn cache_revalidation() {
+        cache::test_home();
+        let (url, server) = serve(vec![
+            "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: 5\r\n\
+             Connection: close\r\n\r\nfirst",
+            "HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\
+             Connection: close\r\n\r\n",
+        ]);
+        let http = http(None, DEFAULT_API_URL);
+        // Fetched, revalidated, and then used when the server fails.
+        for _ in 0..3 {
+            assert_eq!(http.get_cached(&url).unwrap(), "first");
+        }
+        let v1 = Some("\"v1\"".to_string());
+        assert_eq!(server.join().unwrap(), [None, v1.clone(), v1]);
+    }
+}
@@ -0,0 +1,291 @@
+//! Incremental fetches, which skip crates whose dependencies haven't changed
+//! in the index since the last run.
+//!
+//! The crates.io sparse index doesn't publish a log of changes, so the state
+//! file records a fingerprint of the index file of every package in the last
+//! resolution, and which packages each requested crate depended on. The next
+//! run fetches those index files again, which is much cheaper than resolving
+//! and checking the whole set, and only crates that depend on a changed
+//! package are resolved and downloaded.
+//!
+//! When `--index-snapshot` has made a mirror of the git index, the state
+//! also records the mirror's commit, and only the index files that changed
+//! in git since then are fetched again.
+
+use crate::http::Http;
+use crate::index;
+use crate::snapshot;
+use crate::{CrateSet, Package};
+use failure::{Fallible, ResultExt};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use tracing::{debug, info, warn};
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct State {
+    /// A hash of the index file of each package, or an empty string if the
+    /// package wasn't in the index.
+    index: BTreeMap<String, String>,
+    /// The packages each requested crate depends on, including itself, keyed
+    /// by `name` or `name@requirement`.
+    crates: BTreeMap<String, Vec<String>>,
+    /// The commit of the git index mirror when the index was last checked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    commit: Option<String>,
+}
+
+impl State {
+    /// Load the state, or start over if it doesn't exist or is invalid.
+    pub fn load(path: &Path) -> Fallible<State> {
+        if !path.exists() {
+            debug!("no incremental state at `{}`", path.display());
+            return Ok(State::default());
+        }
+        let contents = fs::read_to_string(path)
+            .with_context(|_| format!("Failed to read `{}`.", path.display()))?;
+        match serde_json::from_str(&contents) {
+            Ok(state) => Ok(state),
+            Err(e) => {
+                warn!(
+                    "ignoring invalid incremental state `{}`: {}",
+                    path.display(),
+                    e
+                );
+                Ok(State::default())
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Fallible<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Write and rename, so an interrupted write doesn't lose the state.
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)
+            .with_context(|_| format!("Failed to write `{}`.", tmp.display()))?;
+        fs::rename(&tmp, path)
+            .with_context(|_| format!("Failed to write `{}`.", path.display()))?;
+        Ok(())
+    }
+
+    /// Check the index for changes, and remove the crates that don't depend
+    /// on any changed package.
+    pub fn retain_changed(&mut self, http: &Http, crates: &mut CrateSet) -> Fallible<()> {
+        let mirror = if http.offline() {
+            None
+        } else {
+            snapshot::update_mirror().unwrap_or_else(|e| {
+                warn!("failed to update the index mirror: {}", e);
+                None
+            })
+        };
+        let since = self.commit.take();
+        self.commit = mirror.as_ref().map(|(_, commit)| commit.clone());
+        if self.crates.is_empty() {
+            return Ok(());
+        }
+        // Squashes of the git index remove old commits, so this falls back to
+        // checking every index file.
+        let in_git = match (&mirror, &since) {
+            (Some((mirror, commit)), Some(since)) => {
+                match snapshot::changed_packages(mirror, since, commit) {
+                    Ok(names) => Some(names),
+                    Err(e) => {
+                        debug!("can't diff the index since {}: {}", since, e);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        self.retain_with(crates, in_git.as_ref(), |name| fingerprint(http, name))
+    }
+
+    /// Remove the crates that don't depend on a package whose fingerprint
+    /// changed. Only the packages in `in_git` are checked, if it is given.
+    fn retain_with(
+        &mut self,
+        crates: &mut CrateSet,
+        in_git: Option<&HashSet<String>>,
+        mut fingerprint: impl FnMut(&str) -> Fallible<String>,
+    ) -> Fallible<()> {
+        let tracked: HashSet<&String> = crates
+            .iter()
+            .filter_map(|krate| self.crates.get(&key(krate)))
+            .flatten()
+            .collect();
+        info!(
+            "Checking {} packages for changes in the index",
+            tracked.len()
+        );
+        let mut changed = HashSet::new();
+        let mut updated = Vec::new();
+        for name in tracked {
+            if let Some(in_git) = in_git {
+                if !in_git.contains(&name.to_lowercase()) {
+                    continue;
+                }
+            }
+            let fingerprint = fingerprint(name)?;
+            if self.index.get(name) != Some(&fingerprint) {
+                debug!("{} changed in the index", name);
+                changed.insert(name.clone());
+                updated.push((name.clone(), fingerprint));
+            }
+        }
+        let before = crates.len();
+        crates.retain(|krate| match self.crates.get(&key(krate)) {
+            Some(deps) => deps.iter().any(|dep| changed.contains(dep)),
+            None => true,
+        });
+        self.index.extend(updated);
+        info!(
+            "{} packages changed, {} of {} crates unchanged since the last run",
+            changed.len(),
+            before - crates.len(),
+            before
+        );
+        Ok(())
+    }
+
+    /// Record the resolution of a set of crates.
+    pub fn record(&mut self, http: &Http, crates: &CrateSet, pkgs: &[Package]) -> Fallible<()> {
+        self.record_with(crates, pkgs, |name| fingerprint(http, name))
+    }
+
+    fn record_with(
+        &mut self,
+        crates: &CrateSet,
+        pkgs: &[Package],
+        mut fingerprint: impl FnMut(&str) -> Fallible<String>,
+    ) -> Fallible<()> {
+        let mut deps: HashMap<&str, Vec<&str>> = HashMap::new();
+        for pkg in pkgs {
+            deps.entry(&pkg.name[..]).or_default().extend(
+                pkg.dependencies
+                    .iter()
+                    .filter_map(|dep| dep.split(' ').next()),
+            );
+        }
+        for krate in crates {
+            let mut closure = vec![krate.0.clone()];
+            let mut seen: HashSet<&str> = HashSet::new();
+            let mut stack = vec![&krate.0[..]];
+            while let Some(name) = stack.pop() {
+                for &dep in deps.get(name).into_iter().flatten() {
+                    if dep != krate.0 && seen.insert(dep) {
+                        closure.push(dep.to_string());
+                        stack.push(dep);
+                    }
+                }
+            }
+            closure.sort();
+            for name in &closure {
+                if !self.index.contains_key(name) {
+                    let fingerprint = fingerprint(name)?;
+                    self.index.insert(name.clone(), fingerprint);
+                }
+            }
+            self.crates.insert(key(krate), closure);
+        }
+        Ok(())
+    }
+}
+
+/// The key of a crate in the state file.
+fn key((name, version): &(String, Option<String>)) -> String {
+    match version {
+        Some(version) => format!("{}@{}", name, version),
+        None => name.clone(),
+    }
+}
+
+fn fingerprint(http: &Http, name: &str) -> Fallible<String> {
+    Ok(match index::fetch_sparse(http, name)? {
+        Some(contents) => format!("{:x}", Sha256::digest(contents.as_bytes())),
+        None => String::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(name: &str, dependencies: &[&str]) -> Package {
+        Package {
+            name: name.to_string(),
+            version: "1.0.0".to_string(),
+            source: None,
+            checksum: None,
+            dependencies: dependencies.iter().map(|d| d.to_string()).collect(),
+        }
+    }
+
+    fn crates(names: &[&str]) -> CrateSet {
+        names.iter().map(|name| (name.to_string(), None)).collect()
+    }
+
+    #[test]
+    fn retain_changed() {
+        let mut state = State::default();
+        let pkgs = [
+            package("app", &["log", "serde 1.0.0"]),
+            package("cli", &["log"]),
+            package("log", &[]),
+            package("serde", &[]),
+        ];
+        state
+            .record_with(&crates(&["app", "cli"]), &pkgs, |name| {
+                Ok(format!("{}-1", name))
+            })
+            .unwrap();
+        assert_eq!(state.crates["app"], ["app", "log", "serde"]);
+        assert_eq!(state.crates["cli"], ["cli", "log"]);
+
+        let unchanged = |name: &str| Ok(format!("{}-1", name));
+        let mut set = crates(&["app", "cli", "new"]);
+        state.retain_with(&mut set, None, unchanged).unwrap();
+        assert_eq!(set, crates(&["new"]));
+
+        let serde_changed = |name: &str| Ok(format!("{}-{}", name, 1 + (name == "serde") as u8));
+        let mut set = crates(&["app", "cli"]);
+        state.retain_with(&mut set, None, serde_changed).unwrap();
+        assert_eq!(set, crates(&["app"]));
+        // The new fingerprint is saved, so it isn't a change the next time.
+        let mut set = crates(&["app", "cli"]);
+        state.retain_with(&mut set, None, serde_changed).unwrap();
+        assert!(set.is_empty());
+
+        // Packages that didn't change in git aren't checked.
+        let in_git: HashSet<String> = vec!["cli".to_string()].into_iter().collect();
+        let mut set = crates(&["app"]);
+        state
+            .retain_with(&mut set, Some(&in_git), |_| Ok("changed".to_string()))
+            .unwrap();
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state").join("index.json");
+        assert!(State::load(&path).unwrap().crates.is_empty());
+        let mut state = State::default();
+        state
+            .record_with(&crates(&["log"]), &[package("log", &[])], |_| {
+                Ok("abc".to_string())
+            })
+            .unwrap();
+        state.save(&path).unwrap();
+        assert!(!path.with_extension("json.tmp").exists());
+        let loaded = State::load(&path).unwrap();
+        assert_eq!(loaded.crates["log"], ["log"]);
+        assert_eq!(loaded.index["log"], "abc");
+        fs::write(&path, "{").unwrap();
+        assert!(State::load(&path).unwrap().crates.is_empty());
+    }
+}
@@ -0,0 +1,269 @@
+//! Registry index entries.
+
+use crate::cache;
+use crate::crates_io;
+use crate::exit::NetworkError;
+use crate::http::Http;
+use crate::timings;
+use failure::{bail, Fallible};
+use serde_derive::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+/// The crates.io sparse index.
+pub const CRATES_IO_SPARSE: &str = "https://index.crates.io/";
+
+/// A single version of a package in the index.
+#[derive(Deserialize)]
+pub struct IndexEntry {
+    pub vers: String,
+    #[serde(default)]
+    pub deps: Vec<IndexDep>,
+    #[serde(default)]
+    pub yanked: bool,
+    /// The SHA-256 checksum of the `.crate` file.
+    #[serde(default)]
+    pub cksum: String,
+    /// When the version was published, such as `2024-01-31T12:00:00Z`.
+    pub pubtime: Option<String>,
+    /// The oldest Rust version the package supports, if it declares one.
+    pub rust_version: Option<String>,
+    /// The features, and what each of them enables.
+    #[serde(default)]
+    pub features: BTreeMap<String, Vec<String>>,
+    /// Features that use newer syntax, such as `dep:name`, which are kept
+    /// separate so older versions of Cargo can ignore them.
+    #[serde(default)]
+    pub features2: BTreeMap<String, Vec<String>>,
+}
+
+/// A dependency of an index entry.
+#[derive(Deserialize)]
+pub struct IndexDep {
+    /// The name of the dependency, which may be renamed.
+    pub name: String,
+    /// The actual package name if the dependency is renamed.
+    pub package: Option<String>,
+    /// The version requirement.
+    #[serde(default)]
+    pub req: String,
+    #[serde(default)]
+    pub features: Vec<String>,
+    #[serde(default)]
+    pub optional: bool,
+    #[serde(default = "default_true")]
+    pub default_features: bool,
+    /// `normal`, `build`, or `dev`.
+    pub kind: Option<String>,
+    /// The index URL of the registry, if it isn't the same as the package's.
+    pub registry: Option<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl IndexDep {
+    /// Returns the name of the package, accounting for renames.
+    pub fn package_name(&self) -> &str {
+        self.package.as_deref().unwrap_or(&self.name)
+    }
+}
+
+/// Parse the contents of an index file, which has one JSON entry per line.
+///
+/// Lines that fail to parse are skipped.
+pub fn parse_index_file(contents: &str) -> Vec<IndexEntry> {
+    contents
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// Returns the newest non-yanked entry.
+pub fn newest(entries: &[IndexEntry]) -> Option<&IndexEntry> {
+    entries
+        .iter()
+        .filter(|entry| !entry.yanked)
+        .filter_map(|entry| {
+            semver::Version::parse(&entry.vers)
+                .ok()
+                .map(|version| (version, entry))
+        })
+        .max_by(|a, b| a.0.cmp(&b.0))
+        .map(|(_, entry)| entry)
+}
+
+/// Returns the path of a package's file within the index.
+pub fn index_path(name: &str) -> String {
+    let name = name.to_lowercase();
+    match name.len() {
+        1 => format!("1/{}", name),
+        2 => format!("2/{}", name),
+        3 => format!("3/{}/{}", &name[..1], name),
+        _ => format!("{}/{}/{}", &name[..2], &name[2..4], name),
+    }
+}
+
+/// Returns every entry of a package in the crates.io index.
+pub fn entries(http: &Http, name: &str) -> Fallible<Vec<IndexEntry>> {
+    match fetch_sparse(http, name)? {
+        Some(contents) => Ok(parse_index_file(&contents)),
+        None => bail!("crate `{}` was not found in the index", name),
+    }
+}
+
+/// Returns every non-yanked version of a package in the crates.io index.
+pub fn all_versions(http: &Http, name: &str) -> Fallible<Vec<String>> {
+    Ok(entries(http, name)?
+        .into_iter()
+        .filter(|entry| !entry.yanked)
+        .map(|entry| entry.vers)
+        .collect())
+}
+
+/// Returns the non-yanked versions of a package published on or after
+/// `since` and before `before`, which are dates such as `2024-01-31`.
+///
+/// Versions without a publish time are skipped.
+pub fn published_between(
+    http: &Http,
+    name: &str,
+    since: Option<&str>,
+    before: Option<&str>,
+) -> Fallible<Vec<String>> {
+    Ok(entries(http, name)?
+        .into_iter()
+        .filter(|entry| !entry.yanked)
+        .filter(|entry| match &entry.pubtime {
+            Some(pubtime) => {
+                let date = pubtime.get(..10).unwrap_or(pubtime);
+                since.is_none_or(|since| date >= since) && before.is_none_or(|before| date < before)
+            }
+            None => false,
+        })
+        .map(|entry| entry.vers)
+        .collect())
+}
+
+/// Returns the newest non-yanked release of a package that was published on
+/// or before `date`, such as `2024-01-31`.
+///
+/// Older versions don't have a publish time in the index, so those times
+/// come from the crates.io API.
+pub fn newest_as_of(http: &Http, name: &str, date: &str) -> Fallible<Option<String>> {
+    let entries = entries(http, name)?;
+    let api_times = if entries.iter().any(|entry| entry.pubtime.is_none()) {
+        crates_io::publish_times(http, name)?
+    } else {
+        HashMap::new()
+    };
+    Ok(entries
+        .iter()
+        .filter(|entry| !entry.yanked)
+        .filter(|entry| {
+            let pubtime = entry
+                .pubtime
+                .as_ref()
+                .or_else(|| api_times.get(&entry.vers));
+            pubtime.is_some_and(|t| t.get(..10).unwrap_or(t) <= date)
+        })
+        .filter_map(|entry| semver::Version::parse(&entry.vers).ok())
+        .filter(|version| version.pre.is_empty())
+        .max()
+        .map(|version| version.to_string()))
+}
+
+/// Returns the newest version of each of the `n` newest semver-incompatible
+/// release lines of a package (such as `0.8`, `0.7`, and `0.6`).
+///
+/// Yanked versions are skipped, and so are prereleases unless `prereleases`
+/// is set.
+pub fn latest_versions(
+    http: &Http,
+    name: &str,
+    n: usize,
+    prereleases: bool,
+) -> Fallible<Vec<String>> {
+    let mut versions: Vec<semver::Version> = all_versions(http, name)?
+        .iter()
+        .filter_map(|vers| semver::Version::parse(vers).ok())
+        .filter(|version| prereleases || version.pre.is_empty())
+        .collect();
+    versions.sort_unstable_by(|a, b| b.cmp(a));
+    let line = |v: &semver::Version| match (v.major, v.minor) {
+        (0, 0) => (0, 0, v.patch),
+        (0, minor) => (0, minor, 0),
+        (major, _) => (major, 0, 0),
+    };
+    versions.dedup_by(|a, b| line(a) == line(b));
+    Ok(versions.iter().take(n).map(|v| v.to_string()).collect())
+}
+
+/// Returns the newest non-yanked version of a package, including
+/// prereleases.
+pub fn newest_version(http: &Http, name: &str) -> Fallible<Option<String>> {
+    Ok(newest(&entries(http, name)?).map(|entry| entry.vers.clone()))
+}
+
+/// Read a package's entries from Cargo's local cache of a sparse index.
+///
+/// `registry` is the name of the registry's directory, such as
+/// `index.crates.io-1949cf8c6b5b557f`. Returns `None` if the package isn't
+/// cached.
+pub fn read_local_cache(registry: &str, name: &str) -> Fallible<Option<Vec<IndexEntry>>> {
+    Ok(read_local_cache_file(registry, name)?.map(|contents| parse_index_file(&contents)))
+}
+
+/// Read a package's index file from Cargo's local cache of a sparse index,
+/// in the same format as the index serves it.
+fn read_local_cache_file(registry: &str, name: &str) -> Fallible<Option<String>> {
+    let path = cache::cargo_home()?
+        .join("registry")
+        .join("index")
+        .join(registry)
+        .join(".cache")
+        .join(index_path(name));
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(_) => return Ok(None),
+    };
+    // The file starts with a version byte and a u32 index format version,
+    // followed by NUL-terminated strings: a revision, and then pairs of a
+    // version and its JSON entry.
+    let strings: Vec<&[u8]> = match data.get(5..) {
+        Some(rest) => rest.split(|b| *b == 0).collect(),
+        None => return Ok(None),
+    };
+    let lines: Vec<String> = strings
+        .get(1..)
+        .unwrap_or_default()
+        .chunks(2)
+        .filter_map(|pair| pair.get(1))
+        .map(|json| String::from_utf8_lossy(json).into_owned())
+        .collect();
+    Ok(Some(lines.join("\n")))
+}
+
+/// Fetch a package's index file from the crates.io sparse index.
+///
+/// Returns `None` if the package does not exist. The file is cached, and
+/// revalidated with its ETag so that it is only downloaded again when it
+/// changes. Offline, this reads Cargo's local copy of the index instead.
+pub fn fetch_sparse(http: &Http, name: &str) -> Fallible<Option<String>> {
+    let _timer = timings::start("index", name);
+    if http.offline() {
+        let cache_dir = cache::crates_io_cache_dir()?;
+        let registry = cache_dir.file_name().unwrap().to_string_lossy();
+        return match read_local_cache_file(&registry, name)? {
+            Some(contents) => Ok(Some(contents)),
+            None => Err(NetworkError(format!(
+                "`{}` isn't in Cargo's local copy of the index, and can't be fetched offline",
+                name
+            ))
+            .into()),
+        };
+    }
+    let url = format!("{}{}", CRATES_IO_SPARSE, index_path(name));
+    http.get_revalidated(&url)
+}
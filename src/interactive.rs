@@ -0,0 +1,280 @@
+//! `--interactive`, a terminal UI for choosing which packages to fetch.
+//!
+//! The candidates are every package in the resolution of the selected
+//! crates. Sizes come from the cache for packages that are already
+//! downloaded, and otherwise from the crates.io API. The chosen packages are
+//! then downloaded directly, without resolving again, so the total shown is
+//! exactly what will be fetched.
+
+use cargo_prefetch::cache;
+use cargo_prefetch::crates_io;
+use cargo_prefetch::download::{self, Cached};
+use cargo_prefetch::http::Http;
+use cargo_prefetch::Package;
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use failure::{bail, Fallible};
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use tracing::{info, warn};
+
+const HELP: &str = "space: toggle  a: all  n: none  /: search  enter: fetch  q: quit";
+
+struct Candidate {
+    pkg: Package,
+    size: Option<u64>,
+    cached: bool,
+    selected: bool,
+}
+
+/// What to do after a key press.
+enum Action {
+    Continue,
+    Fetch,
+    Quit,
+}
+
+struct Ui {
+    candidates: Vec<Candidate>,
+    /// Only candidates with names containing this are shown.
+    filter: String,
+    /// Whether keys are being typed into the filter.
+    searching: bool,
+    /// The position of the cursor in the visible candidates.
+    cursor: usize,
+    /// The first visible candidate shown on screen.
+    offset: usize,
+}
+
+/// Puts the terminal into raw mode for the UI, and restores it when dropped.
+struct Terminal;
+
+impl Terminal {
+    fn enter() -> Fallible<Terminal> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stderr(), EnterAlternateScreen, Hide)?;
+        Ok(Terminal)
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = execute!(io::stderr(), Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Show the packages, and return the ones that were chosen.
+///
+/// Returns an empty list if the user quits.
+pub fn select(http: &Http, pkgs: Vec<Package>) -> Fallible<Vec<Package>> {
+    if !io::stderr().is_terminal() {
+        bail!("--interactive requires a terminal");
+    }
+    info!("Looking up the sizes of {} packages...", pkgs.len());
+    let cache_dir = cache::crates_io_cache_dir()?;
+    let mut use_api = true;
+    let mut candidates = Vec::new();
+    for pkg in pkgs {
+        let checksum = match &pkg.checksum {
+            Some(checksum) => checksum,
+            None => continue,
+        };
+        let cached = matches!(
+            download::check_cached(&pkg.name, &pkg.version, checksum)?,
+            Cached::Valid
+        );
+        let size = if cached {
+            fs::metadata(cache_dir.join(format!("{}-{}.crate", pkg.name, pkg.version)))
+                .ok()
+                .map(|m| m.len())
+        } else if use_api {
+            match crates_io::crate_size(http, &pkg.name, &pkg.version) {
+                Ok(size) => size,
+                Err(e) => {
+                    // Don't wait for every request to fail.
+                    warn!("failed to look up crate sizes: {}", e);
+                    use_api = false;
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        candidates.push(Candidate {
+            pkg,
+            size,
+            cached,
+            selected: true,
+        });
+    }
+
+    let mut ui = Ui {
+        candidates,
+        filter: String::new(),
+        searching: false,
+        cursor: 0,
+        offset: 0,
+    };
+    let terminal = Terminal::enter()?;
+    loop {
+        ui.draw()?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match ui.handle(key) {
+                Action::Continue => {}
+                Action::Fetch => break,
+                Action::Quit => return Ok(Vec::new()),
+            }
+        }
+    }
+    drop(terminal);
+    Ok(ui
+        .candidates
+        .into_iter()
+        .filter(|c| c.selected)
+        .map(|c| c.pkg)
+        .collect())
+}
+
+impl Ui {
+    /// Returns the indexes of the candidates that match the filter.
+    fn visible(&self) -> Vec<usize> {
+        self.candidates
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.pkg.name.contains(&self.filter[..]))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    fn handle(&mut self, key: KeyEvent) -> Action {
+        let visible = self.visible();
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Action::Quit;
+        }
+        if self.searching {
+            match key.code {
+                KeyCode::Char(c) => self.filter.push(c),
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                }
+                KeyCode::Esc => {
+                    self.filter.clear();
+                    self.searching = false;
+                }
+                KeyCode::Enter => self.searching = false,
+                _ => {}
+            }
+            self.cursor = 0;
+            return Action::Continue;
+        }
+        let last = visible.len().saturating_sub(1);
+        let page = self.list_height();
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => self.cursor = (self.cursor + 1).min(last),
+            KeyCode::PageUp => self.cursor = self.cursor.saturating_sub(page),
+            KeyCode::PageDown => self.cursor = (self.cursor + page).min(last),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = last,
+            KeyCode::Char(' ') => {
+                if let Some(&i) = visible.get(self.cursor) {
+                    self.candidates[i].selected = !self.candidates[i].selected;
+                }
+            }
+            KeyCode::Char('a') | KeyCode::Char('n') => {
+                let selected = key.code == KeyCode::Char('a');
+                for i in visible {
+                    self.candidates[i].selected = selected;
+                }
+            }
+            KeyCode::Char('/') => self.searching = true,
+            KeyCode::Enter => return Action::Fetch,
+            KeyCode::Char('q') | KeyCode::Esc => return Action::Quit,
+            _ => {}
+        }
+        Action::Continue
+    }
+
+    /// The number of candidates that fit on screen.
+    fn list_height(&self) -> usize {
+        let rows = terminal::size().map_or(24, |(_, rows)| rows as usize);
+        rows.saturating_sub(3).max(1)
+    }
+
+    fn draw(&mut self) -> Fallible<()> {
+        let width = terminal::size().map_or(80, |(cols, _)| cols as usize);
+        let height = self.list_height();
+        let visible = self.visible();
+        if self.cursor < self.offset {
+            self.offset = self.cursor;
+        } else if self.cursor >= self.offset + height {
+            self.offset = self.cursor + 1 - height;
+        }
+
+        let mut out = io::stderr();
+        queue!(out, Clear(ClearType::All), MoveTo(0, 0))?;
+        queue!(
+            out,
+            SetAttribute(Attribute::Bold),
+            Print(fit(HELP, width)),
+            SetAttribute(Attribute::Reset)
+        )?;
+        for (row, &i) in visible.iter().skip(self.offset).take(height).enumerate() {
+            let c = &self.candidates[i];
+            let size = c.size.map_or_else(|| "?".to_string(), cache::human_size);
+            let line = format!(
+                "{} [{}] {} {} ({}{})",
+                if self.offset + row == self.cursor {
+                    '>'
+                } else {
+                    ' '
+                },
+                if c.selected { 'x' } else { ' ' },
+                c.pkg.name,
+                c.pkg.version,
+                size,
+                if c.cached { ", cached" } else { "" }
+            );
+            queue!(out, MoveTo(0, row as u16 + 1), Print(fit(&line, width)))?;
+        }
+
+        let selected: Vec<&Candidate> = self.candidates.iter().filter(|c| c.selected).collect();
+        let total: u64 = selected.iter().filter_map(|c| c.size).sum();
+        let to_download: u64 = selected
+            .iter()
+            .filter(|c| !c.cached)
+            .filter_map(|c| c.size)
+            .sum();
+        let unknown = selected.iter().filter(|c| c.size.is_none()).count();
+        let mut status = format!(
+            "Selected {} of {}, {} ({} to download)",
+            selected.len(),
+            self.candidates.len(),
+            cache::human_size(total),
+            cache::human_size(to_download)
+        );
+        if unknown > 0 {
+            status.push_str(&format!(", {} of unknown size", unknown));
+        }
+        let height = height as u16;
+        queue!(out, MoveTo(0, height + 1), Print(fit(&status, width)))?;
+        if self.searching || !self.filter.is_empty() {
+            let search = format!("/{}", self.filter);
+            queue!(out, MoveTo(0, height + 2), Print(fit(&search, width)))?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// Truncate a line to the width of the terminal.
+fn fit(line: &str, width: usize) -> String {
+    line.chars().take(width).collect()
+}
@@ -0,0 +1,1211 @@
+//! Download crates into Cargo's cache.
+//!
+//! This is the library behind `cargo prefetch`, for tools that want to
+//! prefetch crates without running it. Build a [`CrateSet`], either by hand
+//! with [`parse_spec`] or with the builders in [`sources`], and then pass it
+//! to [`fetch`]:
+//!
+//! ```no_run
+//! # fn main() -> failure::Fallible<()> {
+//! use cargo_prefetch::{cargo::Cargo, http::Http, sources, Options};
+//!
+//! let http = Http::default();
+//! let options = Options::new(Cargo::new(None, None, Vec::new())?);
+//! let mut crates = sources::top_deps(50);
+//! crates.extend(sources::lockfile("Cargo.lock".as_ref())?);
+//! cargo_prefetch::fetch(&options, &http, &crates, &[])?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Progress is logged with `tracing`.
+
+use color::Color;
+use concurrency::Concurrency;
+use exit::{CargoError, Interrupted};
+use failure::{bail, format_err, Fallible, ResultExt};
+use http::Http;
+use index::IndexEntry;
+use message::{Event, Messages};
+use prefetch_lock::LockedPackage;
+use serde_derive::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use temp::TempProject;
+use tracing::{debug, info, info_span, warn};
+
+pub mod cache;
+pub mod cargo;
+pub mod color;
+pub mod concurrency;
+pub mod config;
+pub mod crates_io;
+pub mod db_dump;
+pub mod download;
+pub mod exit;
+pub mod github;
+pub mod http;
+pub mod incremental;
+pub mod index;
+pub mod lock_cache;
+pub mod message;
+pub mod platforms;
+pub mod prefetch_lock;
+pub mod rank;
+pub mod registries;
+pub mod report;
+pub mod resolver;
+pub mod resume;
+pub mod snapshot;
+pub mod sources;
+pub mod suggest;
+pub mod temp;
+pub mod timings;
+pub mod tools;
+pub mod top;
+pub mod tracker;
+
+const TEMP_PROJ_NAME: &str = "temp_prefetch_project";
+
+/// Crates to fetch, as names with an optional version requirement.
+pub type CrateSet = HashSet<(String, Option<String>)>;
+
+/// Options that control how the temp project is resolved and fetched.
+pub struct Options {
+    /// Capture Cargo's output, and only print errors and a summary.
+    pub quiet: bool,
+    pub messages: Messages,
+    /// Directory where temp projects are created.
+    pub temp_dir: Option<PathBuf>,
+    /// Whether to leave temp projects behind for debugging.
+    pub keep_temp: bool,
+    /// The `rust-version` of the temp projects.
+    pub rust_version: Option<String>,
+    pub cargo: cargo::Cargo,
+    /// A state file for skipping crates whose dependencies haven't changed
+    /// in the index since the last fetch with it.
+    pub incremental: Option<PathBuf>,
+    /// The version of Cargo's resolver for the temp project, such as `"2"`.
+    pub resolver: Option<String>,
+    /// Resolve with the index and download directly, without running Cargo.
+    pub no_cargo: bool,
+    /// Crates to skip, instead of failing, if Cargo can't resolve them, such
+    /// as ones from download rankings.
+    pub optional: HashSet<String>,
+    /// How many packages to download at once. With more than one, packages
+    /// are downloaded directly instead of with `cargo fetch`.
+    pub jobs: usize,
+    /// How long a resolution of the same temp project is reused, instead of
+    /// resolving it again. Zero disables the cache.
+    pub resolve_cache_ttl: Duration,
+    /// Continue the last fetch that didn't finish, skipping the crates it
+    /// completed.
+    pub resume: bool,
+    /// The commit or date of the index snapshot that Cargo resolves with.
+    /// Cargo would download into the snapshot's own cache, so packages are
+    /// downloaded directly instead.
+    pub index_snapshot: Option<String>,
+    /// Packages that are never downloaded, even as dependencies.
+    pub exclude: Vec<Exclusion>,
+    /// The resolutions to fetch, whose packages are combined.
+    pub resolutions: Vec<Resolution>,
+}
+
+impl Options {
+    /// Options that run the given Cargo, with everything else off.
+    pub fn new(cargo: cargo::Cargo) -> Options {
+        Options {
+            quiet: false,
+            messages: Messages::new(false, false),
+            temp_dir: None,
+            keep_temp: false,
+            rust_version: None,
+            cargo,
+            incremental: None,
+            resolver: None,
+            no_cargo: false,
+            optional: HashSet::new(),
+            jobs: 1,
+            resolve_cache_ttl: Duration::from_secs(0),
+            resume: false,
+            index_snapshot: None,
+            exclude: Vec::new(),
+            resolutions: vec![Resolution::Maximal],
+        }
+    }
+}
+
+/// How the versions of dependencies are selected.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// The newest versions that match, as Cargo normally selects.
+    Maximal,
+    /// The oldest versions that match, with `-Z minimal-versions`, which
+    /// needs a nightly Cargo.
+    Minimal,
+}
+
+/// Parse a crate spec such as `serde`, `serde@=1.0.90`, or
+/// `serde@>=1.0.150, <1.0.190`.
+///
+/// The version may be any requirement that Cargo accepts, or `*all`. A bare
+/// prerelease version such as `1.0.0-beta.2` is pinned exactly, since as a
+/// requirement it would also match later prereleases and releases.
+pub fn parse_spec(spec: &str) -> Fallible<(String, Option<String>)> {
+    let mut splits = spec.splitn(2, '@');
+    let name = splits.next().unwrap();
+    if name.is_empty() {
+        bail!("expected a crate name in `{}`", spec);
+    }
+    let version = match splits.next() {
+        Some("*all") => Some("*all".to_string()),
+        Some("*") => None,
+        Some(version) if semver::Version::parse(version).is_ok_and(|v| !v.pre.is_empty()) => {
+            Some(format!("={}", version))
+        }
+        Some(req) => {
+            let req = semver::VersionReq::parse(req).map_err(|e| {
+                format_err!("invalid version requirement `{}` in `{}`: {}", req, spec, e)
+            })?;
+            Some(req.to_string())
+        }
+        None => None,
+    };
+    Ok((name.to_string(), version))
+}
+
+/// Parse a crate spec like [`parse_spec`], which may also list several exact
+/// versions, such as `serde@1.0.100,1.0.150`.
+///
+/// The versions are only split when each of them is a complete version, as
+/// otherwise the commas separate the parts of one requirement, such as in
+/// `serde@>=1.0.150, <1.0.190`.
+pub fn parse_specs(spec: &str) -> Fallible<Vec<(String, Option<String>)>> {
+    if let Some((name, versions)) = spec.split_once('@') {
+        let versions: Vec<&str> = versions.split(',').map(str::trim).collect();
+        if versions.len() > 1 && versions.iter().all(|v| semver::Version::parse(v).is_ok()) {
+            return versions
+                .into_iter()
+                .map(|version| parse_spec(&format!("{}@={}", name, version)))
+                .collect();
+        }
+    }
+    Ok(vec![parse_spec(spec)?])
+}
+
+/// A package to keep out of the cache, from `--exclude`.
+pub struct Exclusion {
+    /// The crate name, which may be a glob pattern.
+    pub name: String,
+    /// The versions that are excluded, or `None` for all of them.
+    pub version: Option<semver::VersionReq>,
+}
+
+impl Exclusion {
+    /// Parse a spec such as `openssl-sys` or `openssl-sys@<0.9.90`.
+    pub fn parse(spec: &str) -> Fallible<Exclusion> {
+        let (name, version) = match spec.split_once('@') {
+            Some((name, req)) => {
+                let req = semver::VersionReq::parse(req).map_err(|e| {
+                    format_err!("invalid version requirement `{}` in `{}`: {}", req, spec, e)
+                })?;
+                (name, Some(req))
+            }
+            None => (spec, None),
+        };
+        if name.is_empty() {
+            bail!("expected a crate name in `{}`", spec);
+        }
+        Ok(Exclusion {
+            name: name.to_string(),
+            version,
+        })
+    }
+
+    /// Whether this excludes the given version of a crate.
+    pub fn matches(&self, name: &str, version: &str) -> bool {
+        glob_match(&self.name, name)
+            && match &self.version {
+                Some(req) => semver::Version::parse(version).is_ok_and(|v| req.matches(&v)),
+                None => true,
+            }
+    }
+}
+
+/// Remove the packages excluded by `options.exclude`, and return them.
+///
+/// The resolution isn't changed, so a package that another one depends on
+/// is still missing when building offline, which is warned about.
+fn remove_excluded(options: &Options, pkgs: &mut Vec<Package>) -> Vec<Package> {
+    let (excluded, kept): (Vec<Package>, Vec<Package>) =
+        pkgs.drain(..).partition(|pkg: &Package| {
+            options
+                .exclude
+                .iter()
+                .any(|e| e.matches(&pkg.name, &pkg.version))
+        });
+    for pkg in &excluded {
+        debug!("excluding {}@{}", pkg.name, pkg.version);
+        let dependents: Vec<String> = kept
+            .iter()
+            .filter(|dependent| {
+                dependent.dependencies.iter().any(|dep| {
+                    let mut parts = dep.split(' ');
+                    parts.next() == Some(&pkg.name[..])
+                        && parts.next().is_none_or(|version| version == pkg.version)
+                })
+            })
+            .map(|dependent| format!("{}@{}", dependent.name, dependent.version))
+            .collect();
+        if !dependents.is_empty() {
+            warn!(
+                "excluded {}@{} is a dependency of {}, which can't be built offline without it",
+                pkg.name,
+                pkg.version,
+                dependents.join(", ")
+            );
+        }
+    }
+    *pkgs = kept;
+    excluded
+}
+
+/// Returns the names of the crates that don't specify a version.
+pub fn unversioned(crates: &CrateSet) -> Vec<String> {
+    crates
+        .iter()
+        .filter(|(_, version)| version.is_none())
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+/// Whether a crate name matches a glob pattern, where `*` matches any
+/// characters and `?` matches one.
+///
+/// `-` and `_` match each other, as crates.io treats them the same.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let normalize = |s: &str| s.replace('-', "_").into_bytes();
+    let (pattern, name) = (normalize(pattern), normalize(name));
+    let (mut p, mut n) = (0, 0);
+    // Where to continue after the last `*`, if the rest doesn't match.
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Split the crates into sets that can each be resolved in a single project.
+///
+/// Cargo doesn't allow multiple semver-compatible versions of a package in
+/// one project, so each set has at most one spec for each crate.
+pub fn partition(crates: &CrateSet) -> Vec<CrateSet> {
+    let mut by_name: HashMap<&str, Vec<&Option<String>>> = HashMap::new();
+    for (name, version) in crates {
+        by_name.entry(name).or_default().push(version);
+    }
+    let mut sets = vec![CrateSet::new()];
+    for (name, mut versions) in by_name {
+        versions.sort();
+        for (i, version) in versions.into_iter().enumerate() {
+            if i == sets.len() {
+                sets.push(CrateSet::new());
+            }
+            sets[i].insert((name.to_string(), version.clone()));
+        }
+    }
+    sets
+}
+
+/// Fetch the crates into the cache.
+///
+/// `yanked` are versions to download directly instead of with Cargo. Returns
+/// the packages that were fetched.
+///
+/// Progress is saved for `--resume`, and Ctrl-C stops after the downloads in
+/// progress instead of immediately.
+pub fn fetch(
+    options: &Options,
+    http: &Http,
+    crates: &CrateSet,
+    yanked: &[(String, IndexEntry)],
+) -> Fallible<Vec<LockedPackage>> {
+    temp::set_graceful(true);
+    let result = fetch_crates(options, http, crates, yanked);
+    temp::set_graceful(false);
+    match result {
+        // Cargo is interrupted too, which is reported as it failing.
+        Err(_) if temp::interrupted() => Err(Interrupted.into()),
+        result => result,
+    }
+}
+
+fn fetch_crates(
+    options: &Options,
+    http: &Http,
+    crates: &CrateSet,
+    yanked: &[(String, IndexEntry)],
+) -> Fallible<Vec<LockedPackage>> {
+    let mut fetched = Vec::new();
+    let mut cached_total = 0;
+    let mut unresolvable = Vec::new();
+    let mut state = match &options.incremental {
+        Some(path) => Some(incremental::State::load(path)?),
+        None => None,
+    };
+    let mut crates = crates.clone();
+    let saved = if options.resume {
+        resume::load()?
+    } else {
+        None
+    };
+    let mut progress = match saved {
+        Some(saved) => {
+            crates = saved.remaining();
+            info!(
+                "Resuming the last fetch, {} of {} crates are left",
+                crates.len(),
+                saved.crates.len()
+            );
+            saved
+        }
+        None => {
+            if options.resume {
+                info!("There is no unfinished fetch to resume");
+            }
+            resume::State {
+                crates: crates.clone(),
+                completed: CrateSet::new(),
+            }
+        }
+    };
+    resume::save(&progress)?;
+    if let Some(state) = &mut state {
+        state.retain_changed(http, &mut crates)?;
+    }
+    if options.no_cargo {
+        let _span = info_span!("fetch", crates = crates.len()).entered();
+        let mut pkgs = resolver::resolve(http, &crates, options.rust_version.as_deref())?;
+        for pkg in remove_excluded(options, &mut pkgs) {
+            options.messages.emit_with_reason(
+                Event::Skipped,
+                &pkg.name,
+                &pkg.version,
+                Some("excluded"),
+            );
+        }
+        if let Some(state) = &mut state {
+            state.record(http, &crates, &pkgs)?;
+        }
+        let mut cached = 0;
+        {
+            let _lock = cache::lock_package_cache(false)?;
+            for pkg in &pkgs {
+                if let Some(checksum) = &pkg.checksum {
+                    if download::is_cached(&pkg.name, &pkg.version, checksum)? {
+                        cached += 1;
+                    }
+                }
+            }
+        }
+        cached_total += cached;
+        if !options.quiet {
+            info!("{} cached, {} to download", cached, pkgs.len() - cached);
+        }
+        fetched.extend(fetch_selected(options, http, pkgs)?);
+        progress.completed.extend(crates.iter().cloned());
+        resume::save(&progress)?;
+    }
+    let sets = if options.no_cargo {
+        Vec::new()
+    } else {
+        partition(&crates)
+    };
+    if sets.len() > 1 {
+        debug!("Fetching with {} temp projects", sets.len());
+    }
+    // Each set is resolved once for each resolution, and packages that an
+    // earlier resolution selected are left out of the later ones.
+    let runs: Vec<(&CrateSet, Resolution)> = sets
+        .iter()
+        .flat_map(|set| options.resolutions.iter().map(move |&r| (set, r)))
+        .collect();
+    let mut seen: HashSet<(String, String)> = HashSet::new();
+    for (set, resolution) in runs {
+        if temp::interrupted() {
+            return Err(Interrupted.into());
+        }
+        if set.is_empty() {
+            continue;
+        }
+        let _span = info_span!("fetch", crates = set.len()).entered();
+        let dir = mktemp(options)?;
+        let tmp_path = dir.path();
+        let mut pkgs =
+            generate_lockfile(options, http, tmp_path, set, resolution, &mut unresolvable)?;
+        let excluded = remove_excluded(options, &mut pkgs);
+        for pkg in &excluded {
+            options.messages.emit_with_reason(
+                Event::Skipped,
+                &pkg.name,
+                &pkg.version,
+                Some("excluded"),
+            );
+        }
+        if let Some(state) = &mut state {
+            state.record(http, set, &pkgs)?;
+        }
+        pkgs.retain(|pkg| seen.insert((pkg.name.clone(), pkg.version.clone())));
+        for pkg in &pkgs {
+            options
+                .messages
+                .emit(Event::Queued, &pkg.name, &pkg.version);
+        }
+
+        // Skip packages that are already in the cache. The lock is released
+        // before running Cargo.
+        let lock = cache::lock_package_cache(false)?;
+        let mut missing = Vec::new();
+        for pkg in &pkgs {
+            if let Some(checksum) = &pkg.checksum {
+                if download::is_cached(&pkg.name, &pkg.version, checksum)? {
+                    options.messages.emit_with_reason(
+                        Event::Skipped,
+                        &pkg.name,
+                        &pkg.version,
+                        Some("already cached"),
+                    );
+                    continue;
+                }
+            }
+            missing.push(pkg);
+        }
+        drop(lock);
+        let cached = pkgs.len() - missing.len();
+        cached_total += cached;
+        if !options.quiet {
+            info!("{} cached, {} to download", cached, missing.len());
+        }
+
+        // `cargo fetch` would download the excluded packages in the lockfile
+        // too.
+        let direct = options.jobs > 1 || options.index_snapshot.is_some() || !excluded.is_empty();
+        if !missing.is_empty() && direct && missing.iter().all(|p| p.checksum.is_some()) {
+            // Several `cargo fetch` processes would only wait on each other
+            // for the package cache lock, so download the packages that Cargo
+            // selected directly instead.
+            let _lock = cache::lock_package_cache(false)?;
+            let downloads: Vec<_> = missing
+                .iter()
+                .map(|pkg| {
+                    (
+                        &pkg.name[..],
+                        &pkg.version[..],
+                        pkg.checksum.as_deref().unwrap(),
+                    )
+                })
+                .collect();
+            download_parallel(options, http, &downloads)?;
+        } else if !missing.is_empty() {
+            for pkg in &missing {
+                options
+                    .messages
+                    .emit(Event::Downloading, &pkg.name, &pkg.version);
+            }
+            let _timer = timings::start("fetch", format!("{} packages", missing.len()));
+            let status = cargo_fetch(options, tmp_path)?;
+            if !status.success() {
+                let reason = format!("`cargo fetch` failed: {}", status);
+                for pkg in &missing {
+                    options.messages.emit_with_reason(
+                        Event::Failed,
+                        &pkg.name,
+                        &pkg.version,
+                        Some(&reason),
+                    );
+                }
+                return Err(CargoError(format!("`cargo` failed to run: {}", status)).into());
+            }
+            for pkg in &missing {
+                let downloaded = match &pkg.checksum {
+                    Some(checksum) => download::is_cached(&pkg.name, &pkg.version, checksum)?,
+                    None => true,
+                };
+                if downloaded {
+                    options.messages.emit(Event::Done, &pkg.name, &pkg.version);
+                } else {
+                    options.messages.emit_with_reason(
+                        Event::Failed,
+                        &pkg.name,
+                        &pkg.version,
+                        Some("not downloaded by `cargo fetch`"),
+                    );
+                    warn!("{}@{} was not downloaded", pkg.name, pkg.version);
+                    exit::mark_incomplete();
+                }
+            }
+        }
+        if Some(&resolution) == options.resolutions.last() {
+            progress.completed.extend(set.iter().cloned());
+            resume::save(&progress)?;
+        }
+        for pkg in pkgs {
+            if let Some(checksum) = pkg.checksum {
+                fetched.push(LockedPackage {
+                    name: pkg.name,
+                    version: pkg.version,
+                    checksum,
+                });
+            }
+        }
+    }
+    let _lock = cache::lock_package_cache(false)?;
+    for (name, entry) in yanked {
+        options.messages.emit(Event::Queued, name, &entry.vers);
+        download_direct(options, http, name, &entry.vers, &entry.cksum)?;
+        fetched.push(LockedPackage {
+            name: name.clone(),
+            version: entry.vers.clone(),
+            checksum: entry.cksum.clone(),
+        });
+    }
+    warn_unresolvable(&unresolvable);
+    if let (Some(state), Some(path)) = (&state, &options.incremental) {
+        state.save(path)?;
+    }
+    resume::remove()?;
+    if options.quiet {
+        info!(
+            "Fetched {} packages ({} already cached)",
+            fetched.len(),
+            cached_total
+        );
+    }
+
+    Ok(fetched)
+}
+
+/// Run `cargo fetch` in a temp project. With `--quiet`, Cargo's output is
+/// only shown if it fails.
+pub(crate) fn cargo_fetch(options: &Options, dir: &Path) -> Fallible<ExitStatus> {
+    debug!("Running: cargo fetch");
+    let mut cmd = options.cargo.command("fetch");
+    cmd.current_dir(dir);
+    if options.quiet {
+        let output = cmd.output().with_context(|_| "Failed to launch `cargo`.")?;
+        if !output.status.success() {
+            eprint!("{}", String::from_utf8_lossy(&output.stderr));
+        }
+        Ok(output.status)
+    } else {
+        Ok(cmd.status().with_context(|_| "Failed to launch `cargo`.")?)
+    }
+}
+
+/// Download exactly the given packages, such as ones chosen with
+/// `--interactive`.
+pub fn fetch_selected(
+    options: &Options,
+    http: &Http,
+    pkgs: Vec<Package>,
+) -> Fallible<Vec<LockedPackage>> {
+    let _lock = cache::lock_package_cache(false)?;
+    let mut fetched = Vec::new();
+    for pkg in pkgs {
+        if let Some(checksum) = pkg.checksum {
+            options
+                .messages
+                .emit(Event::Queued, &pkg.name, &pkg.version);
+            fetched.push(LockedPackage {
+                name: pkg.name,
+                version: pkg.version,
+                checksum,
+            });
+        }
+    }
+    let downloads: Vec<_> = fetched
+        .iter()
+        .map(|pkg| (&pkg.name[..], &pkg.version[..], &pkg.checksum[..]))
+        .collect();
+    download_parallel(options, http, &downloads)?;
+    Ok(fetched)
+}
+
+/// Download `(name, version, checksum)` packages without Cargo, with up to
+/// `options.jobs` downloads at once, adjusting how many run to the
+/// throughput and rate limiting.
+///
+/// Downloads stop being started after the first failure, and its error is
+/// returned once the ones in progress finish. The caller should hold the
+/// package cache lock.
+fn download_parallel(options: &Options, http: &Http, pkgs: &[(&str, &str, &str)]) -> Fallible<()> {
+    let jobs = options.jobs.max(1).min(pkgs.len());
+    if jobs <= 1 {
+        for (name, version, cksum) in pkgs {
+            if temp::interrupted() {
+                return Err(Interrupted.into());
+            }
+            download_direct(options, http, name, version, cksum)?;
+        }
+        return Ok(());
+    }
+    debug!(
+        "Downloading {} packages with up to {} jobs",
+        pkgs.len(),
+        jobs
+    );
+    let next = AtomicUsize::new(0);
+    let error = Mutex::new(None);
+    let stop = || error.lock().unwrap().is_some() || temp::interrupted();
+    let concurrency = Concurrency::new(jobs, http.throttled());
+    thread::scope(|s| {
+        for _ in 0..jobs {
+            s.spawn(|| loop {
+                if stop() || !concurrency.acquire(stop) {
+                    break;
+                }
+                let Some((name, version, cksum)) = pkgs.get(next.fetch_add(1, Ordering::SeqCst))
+                else {
+                    concurrency.release(0, http.throttled());
+                    break;
+                };
+                let result = download_direct(options, http, name, version, cksum);
+                concurrency.release(*result.as_ref().unwrap_or(&0), http.throttled());
+                if let Err(e) = result {
+                    error.lock().unwrap().get_or_insert(e);
+                    break;
+                }
+            });
+        }
+    });
+    match error.into_inner().unwrap() {
+        Some(e) => Err(e),
+        None if temp::interrupted() => Err(Interrupted.into()),
+        None => Ok(()),
+    }
+}
+
+/// Download a package without Cargo, such as one that is yanked.
+///
+/// Returns the size of the downloaded file, or 0 if it was already cached.
+/// The caller should hold the package cache lock.
+pub fn download_direct(
+    options: &Options,
+    http: &Http,
+    name: &str,
+    version: &str,
+    cksum: &str,
+) -> Fallible<u64> {
+    if download::is_cached(name, version, cksum)? {
+        options
+            .messages
+            .emit_with_reason(Event::Skipped, name, version, Some("already cached"));
+        return Ok(0);
+    }
+    options.messages.emit(Event::Downloading, name, version);
+    let _timer = timings::start("download", format!("{}@{}", name, version));
+    match download::download_crate(http, name, version, cksum) {
+        Ok(path) => {
+            debug!("Downloaded {}", path.display());
+            if !options.quiet {
+                // The same as Cargo's status for the packages it downloads.
+                color::status(
+                    Color::Green,
+                    "Downloaded",
+                    &format!("{} v{}", name, version),
+                );
+            }
+            options.messages.emit(Event::Done, name, version);
+            Ok(fs::metadata(&path).map_or(0, |metadata| metadata.len()))
+        }
+        Err(e) => {
+            let reason = e.to_string();
+            options
+                .messages
+                .emit_with_reason(Event::Failed, name, version, Some(&reason));
+            Err(e)
+        }
+    }
+}
+
+/// Resolve the crates, and return every package in the resolution, sorted
+/// by name and version.
+pub fn resolve_all(
+    options: &Options,
+    http: &Http,
+    crates: &CrateSet,
+    yanked: &[(String, IndexEntry)],
+) -> Fallible<Vec<Package>> {
+    let mut pkgs = Vec::new();
+    let mut unresolvable = Vec::new();
+    if options.no_cargo {
+        pkgs.extend(resolver::resolve(
+            http,
+            crates,
+            options.rust_version.as_deref(),
+        )?);
+    }
+    let sets = if options.no_cargo {
+        Vec::new()
+    } else {
+        partition(crates)
+    };
+    for set in sets {
+        for &resolution in &options.resolutions {
+            let dir = mktemp(options)?;
+            pkgs.extend(generate_lockfile(
+                options,
+                http,
+                dir.path(),
+                &set,
+                resolution,
+                &mut unresolvable,
+            )?);
+        }
+    }
+    warn_unresolvable(&unresolvable);
+    remove_excluded(options, &mut pkgs);
+    pkgs.extend(yanked.iter().map(|(name, entry)| Package {
+        name: name.clone(),
+        version: entry.vers.clone(),
+        source: None,
+        checksum: Some(entry.cksum.clone()),
+        dependencies: Vec::new(),
+    }));
+    pkgs.sort_by(|a, b| {
+        let version = |pkg: &Package| semver::Version::parse(&pkg.version).ok();
+        a.name
+            .cmp(&b.name)
+            .then_with(|| version(a).cmp(&version(b)))
+    });
+    pkgs.dedup_by(|a, b| a.name == b.name && a.version == b.version);
+    Ok(pkgs)
+}
+
+/// Resolve the crates in a temp project, and return the packages in the
+/// lockfile of each of `options.resolutions`.
+pub fn resolve(options: &Options, http: &Http, crates: &CrateSet) -> Fallible<Vec<Package>> {
+    let mut pkgs: Vec<Package> = Vec::new();
+    let mut unresolvable = Vec::new();
+    for &resolution in &options.resolutions {
+        let dir = mktemp(options)?;
+        for pkg in generate_lockfile(
+            options,
+            http,
+            dir.path(),
+            crates,
+            resolution,
+            &mut unresolvable,
+        )? {
+            if !pkgs
+                .iter()
+                .any(|p| p.name == pkg.name && p.version == pkg.version)
+            {
+                pkgs.push(pkg);
+            }
+        }
+    }
+    warn_unresolvable(&unresolvable);
+    remove_excluded(options, &mut pkgs);
+    Ok(pkgs)
+}
+
+/// Create the temp project, generate its lockfile, and return the packages
+/// in it.
+///
+/// Crates in `options.optional` that Cargo can't resolve are skipped and
+/// added to `unresolvable`, and the rest are resolved again without them. If
+/// a crate doesn't exist, the error suggests similar names.
+fn generate_lockfile(
+    options: &Options,
+    http: &Http,
+    tmp_path: &Path,
+    crates: &CrateSet,
+    resolution: Resolution,
+    unresolvable: &mut Vec<String>,
+) -> Fallible<Vec<Package>> {
+    let _span = info_span!("resolve").entered();
+    let _timer = timings::start(
+        "resolve",
+        format!("temp project with {} crates", crates.len()),
+    );
+    let mut crates = crates.clone();
+    let mut skipped: Vec<String> = Vec::new();
+    make_project(
+        tmp_path,
+        &crates,
+        options.resolver.as_deref(),
+        options.rust_version.as_deref(),
+    )?;
+    let mut manifest = fs::read_to_string(tmp_path.join("Cargo.toml"))?;
+    if resolution == Resolution::Minimal {
+        // Resolutions are cached by the manifest, which is the same for
+        // either kind.
+        manifest.push_str("# minimal-versions\n");
+    }
+    let cached = match options.resolve_cache_ttl.as_secs() {
+        0 => None,
+        _ => lock_cache::load(&options.cargo, &manifest, options.resolve_cache_ttl)?,
+    }
+    // Crates that were skipped need to still be allowed to be skipped.
+    .filter(|entry| entry.skipped.iter().all(|n| options.optional.contains(n)));
+    if let Some(entry) = cached {
+        for name in &entry.skipped {
+            skip_unresolvable(options, name, unresolvable);
+        }
+        crates.retain(|(n, _)| !entry.skipped.contains(n));
+        make_project(
+            tmp_path,
+            &crates,
+            options.resolver.as_deref(),
+            options.rust_version.as_deref(),
+        )?;
+        fs::write(tmp_path.join("Cargo.lock"), &entry.lockfile)?;
+        let mut pkgs = parse_lockfile(&entry.lockfile)?;
+        pkgs.retain(|pkg| pkg.name != TEMP_PROJ_NAME);
+        return Ok(pkgs);
+    }
+    loop {
+        make_project(
+            tmp_path,
+            &crates,
+            options.resolver.as_deref(),
+            options.rust_version.as_deref(),
+        )?;
+        debug!("Running: cargo generate-lockfile");
+        let mut cmd = options.cargo.command("generate-lockfile");
+        if resolution == Resolution::Minimal {
+            cmd.arg("-Zminimal-versions");
+        }
+        let output = cmd
+            .current_dir(tmp_path)
+            .output()
+            .with_context(|_| "Failed to launch `cargo`.")?;
+        if output.status.success() {
+            break;
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let skip = unresolvable_crate(&stderr).filter(|name| {
+            options.optional.contains(*name) && crates.iter().any(|(n, _)| n == name)
+        });
+        if let Some(name) = skip {
+            skip_unresolvable(options, name, unresolvable);
+            crates.retain(|(n, _)| n != name);
+            skipped.push(name.to_string());
+            continue;
+        }
+        if let Some(name) = missing_crate(&stderr) {
+            if http.offline() {
+                return Err(CargoError(format!(
+                    "crate `{}` isn't in Cargo's local copy of the index, \
+                     and can't be fetched offline",
+                    name
+                ))
+                .into());
+            }
+            let mut msg = format!("crate `{}` was not found in the crates.io index", name);
+            let similar = suggest::similar_names(http, name);
+            if !similar.is_empty() {
+                let similar: Vec<String> = similar.iter().map(|n| format!("`{}`", n)).collect();
+                msg.push_str(&format!("\n\nhelp: did you mean {}?", similar.join(" or ")));
+            }
+            return Err(CargoError(msg).into());
+        }
+        return Err(CargoError(format!(
+            "`cargo` failed to run:\n{}\n{}\n{}\n",
+            output.status,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        ))
+        .into());
+    }
+    if options.resolve_cache_ttl.as_secs() > 0 {
+        let entry = lock_cache::Entry {
+            lockfile: fs::read_to_string(tmp_path.join("Cargo.lock"))?,
+            skipped,
+        };
+        if let Err(e) = lock_cache::save(&options.cargo, &manifest, &entry) {
+            warn!("failed to cache the resolution: {}", e);
+        }
+    }
+    let mut pkgs = load_from_lock(tmp_path)?;
+    pkgs.retain(|pkg| pkg.name != TEMP_PROJ_NAME);
+    Ok(pkgs)
+}
+
+fn skip_unresolvable(options: &Options, name: &str, unresolvable: &mut Vec<String>) {
+    warn!("skipping `{}`, it could not be resolved", name);
+    options
+        .messages
+        .skip_crate(name, "it could not be resolved");
+    exit::mark_incomplete();
+    unresolvable.push(name.to_string());
+}
+
+/// Log the crates that were skipped because they couldn't be resolved.
+fn warn_unresolvable(unresolvable: &[String]) {
+    if !unresolvable.is_empty() {
+        warn!(
+            "skipped {} crates that could not be resolved: {}",
+            unresolvable.len(),
+            unresolvable.join(", ")
+        );
+    }
+}
+
+/// Returns the name of the dependency of the temp project that Cargo failed
+/// to resolve.
+///
+/// Cargo's errors end with the chain of packages that required the one that
+/// failed, so this is the last name before the temp project.
+fn unresolvable_crate(stderr: &str) -> Option<&str> {
+    let end = stderr.find(&format!("package `{} ", TEMP_PROJ_NAME))?;
+    let token = stderr[..end].split('`').skip(1).step_by(2).last()?;
+    let name = token.split([' ', '@']).next()?;
+    // Renamed dependencies for multiple versions are `name_prefetchN`.
+    Some(match name.rfind("_prefetch") {
+        Some(i) if name[i + 9..].chars().all(|c| c.is_ascii_digit()) => &name[..i],
+        _ => name,
+    })
+}
+
+/// Returns the name of a crate that Cargo couldn't find, if it was requested
+/// directly by the temp project.
+fn missing_crate(stderr: &str) -> Option<&str> {
+    // Cargo uses the second form when it has its own suggestion, for names
+    // that differ in `-` and `_`.
+    let start = ["no matching package named `", "searched package name: `"]
+        .iter()
+        .find_map(|prefix| stderr.find(prefix).map(|i| i + prefix.len()))?;
+    let len = stderr[start..].find('`')?;
+    let required_by = format!("required by package `{} ", TEMP_PROJ_NAME);
+    if !stderr[start..].contains(&required_by) {
+        return None;
+    }
+    Some(&stderr[start..start + len])
+}
+
+/// Create a temporary Cargo project with the given dependencies.
+///
+/// `resolver` is the version of Cargo's resolver to use, such as `"2"`,
+/// along with the first edition that uses it by default. Otherwise the
+/// project uses the 2018 edition, with the original resolver. With a
+/// `rust_version`, Cargo prefers versions of the dependencies that support
+/// it.
+pub fn make_project(
+    tmp_path: &Path,
+    crates: &CrateSet,
+    resolver: Option<&str>,
+    rust_version: Option<&str>,
+) -> Fallible<()> {
+    let newest = "*".to_string();
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    // Sorted so that the same crates always make the same project.
+    let mut crates: Vec<_> = crates.iter().collect();
+    crates.sort();
+    let deps: Vec<String> = crates
+        .into_iter()
+        .map(|(name, version)| {
+            let version = version.as_ref().unwrap_or(&newest);
+            // Multiple versions of the same crate need to be renamed.
+            let count = seen.entry(name).or_insert(0);
+            *count += 1;
+            if *count == 1 {
+                format!("\"{}\" = \"{}\"\n", name, version)
+            } else {
+                format!(
+                    "\"{}_prefetch{}\" = {{ package = \"{}\", version = \"{}\" }}\n",
+                    name, count, name, version
+                )
+            }
+        })
+        .collect();
+
+    // NOTE: This method of using a single project to resolve all dependencies
+    // may result in some crates using an older version due to restrictive
+    // version requirements. In practice I haven't seen any that are forced to
+    // resolve to an older version.
+
+    let (edition, resolver) = match resolver {
+        Some("2") => ("2021", "resolver = \"2\""),
+        Some("3") => ("2024", "resolver = \"3\""),
+        Some(_) => ("2018", "resolver = \"1\""),
+        None => ("2018", ""),
+    };
+    fs::write(
+        tmp_path.join("Cargo.toml"),
+        format!(
+            r#"
+            [package]
+            name = "{}"
+            version = "0.0.0"
+            edition = "{}"
+            {}
+            {}
+
+            [dependencies]
+            {}
+            "#,
+            TEMP_PROJ_NAME,
+            edition,
+            resolver,
+            rust_version
+                .map(|version| format!("rust-version = \"{}\"", version))
+                .unwrap_or_default(),
+            deps.join("")
+        ),
+    )?;
+    fs::create_dir_all(tmp_path.join("src"))?;
+    fs::write(tmp_path.join("src").join("lib.rs"), "")?;
+    Ok(())
+}
+
+pub fn mktemp(options: &Options) -> Fallible<TempProject> {
+    TempProject::new(options.temp_dir.as_deref(), options.keep_temp)
+}
+
+/// A package in a `Cargo.lock` file.
+#[derive(Deserialize)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    pub source: Option<String>,
+    pub checksum: Option<String>,
+    /// The packages it depends on, as `name`, or `name version` if there are
+    /// several versions in the lockfile.
+    #[serde(default)]
+    pub dependencies: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Lockfile {
+    package: Option<Vec<Package>>,
+}
+
+/// Load a list of packages from the Cargo.lock file in a directory.
+fn load_from_lock(dir: &Path) -> Fallible<Vec<Package>> {
+    load_lockfile(&dir.join("Cargo.lock"))
+}
+
+/// Load a list of packages from a Cargo.lock file.
+pub fn load_lockfile(path: &Path) -> Fallible<Vec<Package>> {
+    parse_lockfile(&fs::read_to_string(path)?)
+}
+
+/// Parse the contents of a Cargo.lock file.
+pub fn parse_lockfile(contents: &str) -> Fallible<Vec<Package>> {
+    let lock: Lockfile = toml::from_str(contents)?;
+    Ok(lock.package.unwrap_or_default())
+}
+
+/// Returns whether a lockfile `source` is a registry, crates.io or another.
+pub fn is_registry(source: &str) -> bool {
+    source.starts_with("registry+") || source.starts_with("sparse+")
+}
+
+/// Returns whether a lockfile `source` is crates.io.
+pub fn is_crates_io(source: &str) -> bool {
+    source == "registry+https://github.com/rust-lang/crates.io-index"
+        || source == "sparse+https://index.crates.io/"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(crates: &[(&str, Option<&str>)]) -> CrateSet {
+        crates
+            .iter()
+            .map(|(name, version)| (name.to_string(), version.map(String::from)))
+            .collect()
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_match("tokio-*", "tokio-util"));
+        assert!(glob_match("tokio-*", "tokio-"));
+        assert!(!glob_match("tokio-*", "tokio"));
+        assert!(glob_match("*-sys", "openssl-sys"));
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("serde?json", "serde_json"));
+        assert!(!glob_match("serde?json", "serdejson"));
+        assert!(glob_match("a*b*c", "a-x-b-y-c"));
+        assert!(!glob_match("a*b*c", "a-x-b-y"));
+        assert!(glob_match("exact", "exact"));
+        assert!(!glob_match("exact", "exactly"));
+        // `-` and `_` are the same in crate names.
+        assert!(glob_match("serde-*", "serde_json"));
+        assert!(glob_match("serde_json", "serde-json"));
+    }
+
+    #[test]
+    fn partitions() {
+        assert_eq!(partition(&CrateSet::new()), vec![CrateSet::new()]);
+        let crates = set(&[("a", None), ("b", Some("^1"))]);
+        assert_eq!(partition(&crates), vec![crates.clone()]);
+        let mut sets = partition(&set(&[
+            ("a", Some("=1.0.0")),
+            ("a", Some("=2.0.0")),
+            ("a", Some("=3.0.0")),
+            ("b", None),
+        ]));
+        sets.sort_by_key(|set| std::cmp::Reverse(set.len()));
+        assert_eq!(sets.len(), 3);
+        assert!(sets
+            .iter()
+            .all(|s| s.iter().filter(|(n, _)| n == "a").count() == 1));
+        assert_eq!(sets.iter().map(CrateSet::len).sum::<usize>(), 4);
+        assert!(sets[0].contains(&("b".to_string(), None)));
+    }
+
+    #[test]
+    fn specs() {
+        let spec = |s: &str| parse_specs(s).unwrap();
+        let one =
+            |name: &str, version: Option<&str>| vec![(name.to_string(), version.map(String::from))];
+        assert_eq!(spec("serde"), one("serde", None));
+        assert_eq!(spec("serde@*"), one("serde", None));
+        assert_eq!(spec("serde@*all"), one("serde", Some("*all")));
+        assert_eq!(spec("serde@=1.0.90"), one("serde", Some("=1.0.90")));
+        assert_eq!(
+            spec("serde@1.0.0-beta.2"),
+            one("serde", Some("=1.0.0-beta.2"))
+        );
+        assert_eq!(
+            spec("serde@>=1.0.150, <1.0.190"),
+            one("serde", Some(">=1.0.150, <1.0.190"))
+        );
+        assert_eq!(
+            spec("serde@1.0.100,1.0.150"),
+            vec![
+                ("serde".to_string(), Some("=1.0.100".to_string())),
+                ("serde".to_string(), Some("=1.0.150".to_string())),
+            ]
+        );
+        assert!(parse_specs("@1.0").is_err());
+        assert!(parse_specs("serde@not-a-version").is_err());
+    }
+}
@@ -0,0 +1,100 @@
+//! The `where` subcommand for printing where the cache is.
+
+use cargo_prefetch::cache;
+use clap::ArgMatches;
+use failure::Fallible;
+use serde_derive::Serialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Serialize)]
+struct Locations {
+    cargo_home: PathBuf,
+    registry_cache: PathBuf,
+    registry_index: PathBuf,
+    registry_src: PathBuf,
+    /// Where cargo-prefetch stores its own data.
+    prefetch: PathBuf,
+    registries: Vec<Registry>,
+}
+
+/// The directories for one registry, which are named after the registry's
+/// host and a hash of its URL.
+#[derive(Serialize)]
+struct Registry {
+    name: String,
+    crates_io: bool,
+    cache: PathBuf,
+    index: PathBuf,
+    src: PathBuf,
+}
+
+pub fn print_locations(matches: &ArgMatches<'_>) -> Fallible<()> {
+    let cargo_home = cache::cargo_home()?;
+    let registry = cargo_home.join("registry");
+    let (cache_dir, index_dir, src_dir) = (
+        registry.join("cache"),
+        registry.join("index"),
+        registry.join("src"),
+    );
+    // Include crates.io even if nothing has been downloaded yet.
+    let mut names = BTreeSet::new();
+    names.insert(
+        cache::crates_io_cache_dir()?
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned(),
+    );
+    for dir in &[&cache_dir, &index_dir, &src_dir] {
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries {
+                names.insert(entry?.file_name().to_string_lossy().into_owned());
+            }
+        }
+    }
+    let registries = names
+        .into_iter()
+        .map(|name| Registry {
+            crates_io: name.starts_with("index.crates.io-") || name.starts_with("github.com-"),
+            cache: cache_dir.join(&name),
+            index: index_dir.join(&name),
+            src: src_dir.join(&name),
+            name,
+        })
+        .collect();
+    let locations = Locations {
+        prefetch: cache::prefetch_dir()?,
+        cargo_home,
+        registry_cache: cache_dir,
+        registry_index: index_dir,
+        registry_src: src_dir,
+        registries,
+    };
+
+    if matches.value_of("format") == Some("json") {
+        println!("{}", serde_json::to_string_pretty(&locations)?);
+        return Ok(());
+    }
+    println!("CARGO_HOME: {}", locations.cargo_home.display());
+    println!("Registry cache: {}", locations.registry_cache.display());
+    println!("Registry index: {}", locations.registry_index.display());
+    println!("Registry src: {}", locations.registry_src.display());
+    println!("cargo-prefetch data: {}", locations.prefetch.display());
+    println!("Registries:");
+    for registry in &locations.registries {
+        let exists = registry.cache.exists() || registry.index.exists();
+        println!(
+            "  {}{}{}",
+            registry.name,
+            if registry.crates_io {
+                " (crates.io)"
+            } else {
+                ""
+            },
+            if exists { "" } else { " (not created yet)" }
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,74 @@
+//! A cache of temp project resolutions, so that resolving the same crates
+//! again doesn't need to run `cargo generate-lockfile`.
+//!
+//! Entries are keyed by a hash of the temp project's `Cargo.toml` and the
+//! Cargo that resolved it. The sparse index has no cheap way to tell if
+//! anything was published since, so entries are only used until they are
+//! older than `--resolve-cache-ttl`.
+
+use crate::cache;
+use crate::cargo::Cargo;
+use failure::{Fallible, ResultExt};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use tracing::debug;
+
+/// A cached resolution.
+#[derive(Deserialize, Serialize)]
+pub struct Entry {
+    /// The contents of `Cargo.lock`.
+    pub lockfile: String,
+    /// Crates that were removed from the temp project because they couldn't
+    /// be resolved.
+    #[serde(default)]
+    pub skipped: Vec<String>,
+}
+
+fn path(cargo: &Cargo, manifest: &str) -> Fallible<PathBuf> {
+    let mut hasher = Sha256::new();
+    hasher.update(cargo.fingerprint().as_bytes());
+    hasher.update(b"\0");
+    hasher.update(manifest.as_bytes());
+    Ok(cache::prefetch_dir()?
+        .join("resolve-cache")
+        .join(format!("{:x}.json", hasher.finalize())))
+}
+
+/// Returns the cached resolution of a temp project, if there is one newer
+/// than `ttl`.
+pub fn load(cargo: &Cargo, manifest: &str, ttl: Duration) -> Fallible<Option<Entry>> {
+    let path = path(cargo, manifest)?;
+    let fresh = fs::metadata(&path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age < ttl);
+    if !fresh {
+        return Ok(None);
+    }
+    let contents = fs::read_to_string(&path)
+        .with_context(|_| format!("Failed to read `{}`.", path.display()))?;
+    match serde_json::from_str(&contents) {
+        Ok(entry) => {
+            debug!("Using the cached resolution `{}`", path.display());
+            Ok(Some(entry))
+        }
+        Err(e) => {
+            debug!("ignoring invalid resolution `{}`: {}", path.display(), e);
+            Ok(None)
+        }
+    }
+}
+
+pub fn save(cargo: &Cargo, manifest: &str, entry: &Entry) -> Fallible<()> {
+    let path = path(cargo, manifest)?;
+    fs::create_dir_all(path.parent().unwrap())?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string(entry)?)
+        .with_context(|_| format!("Failed to write `{}`.", tmp.display()))?;
+    fs::rename(&tmp, &path).with_context(|_| format!("Failed to write `{}`.", path.display()))?;
+    Ok(())
+}
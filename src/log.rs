@@ -0,0 +1,80 @@
+//! Setting up the output of log messages.
+//!
+//! Messages are logged with `tracing`. By default, informational messages
+//! are printed to stderr as plain text, with warnings and errors prefixed
+//! like Cargo does. `-v` adds debug messages and `-vv` adds trace messages.
+//! With `--log-format json`, each message is printed as a JSON object along
+//! with the spans it happened in.
+
+use cargo_prefetch::color::{self, Color};
+use failure::{bail, format_err, Fallible};
+use std::fmt;
+use std::io;
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::fmt::format::{FormatEvent, FormatFields, Writer};
+use tracing_subscriber::fmt::FmtContext;
+use tracing_subscriber::registry::LookupSpan;
+
+/// How log messages are printed.
+#[derive(Clone, Copy)]
+pub enum LogFormat {
+    Human,
+    Json,
+}
+
+impl LogFormat {
+    pub fn parse(s: &str) -> Fallible<LogFormat> {
+        match s {
+            "human" => Ok(LogFormat::Human),
+            "json" => Ok(LogFormat::Json),
+            _ => bail!("unknown log format `{}`", s),
+        }
+    }
+}
+
+/// Install the global logger.
+///
+/// `verbosity` is the number of times `-v` was given.
+pub fn init(verbosity: u64, format: LogFormat) -> Fallible<()> {
+    let level = match verbosity {
+        0 => Level::INFO,
+        1 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(io::stderr);
+    match format {
+        LogFormat::Human => builder.event_format(Human).try_init(),
+        LogFormat::Json => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .try_init(),
+    }
+    .map_err(|e| format_err!("failed to set up logging: {}", e))
+}
+
+/// Formats messages like Cargo's own output.
+struct Human;
+
+impl<S, N> FormatEvent<S, N> for Human
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        match *event.metadata().level() {
+            Level::ERROR => write!(writer, "{} ", color::paint(Color::Red, "error:"))?,
+            Level::WARN => write!(writer, "{} ", color::paint(Color::Yellow, "warning:"))?,
+            _ => {}
+        }
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
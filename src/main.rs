@@ -1,15 +1,50 @@
-use clap::{crate_version, App, AppSettings, Arg, SubCommand};
+use cargo_prefetch::color::{self, Color};
+use cargo_prefetch::http::{self, Http};
+use cargo_prefetch::index::{self, IndexEntry};
+use cargo_prefetch::message::{Event, Messages};
+use cargo_prefetch::prefetch_lock::{self, LockedPackage};
+use cargo_prefetch::sources::Provenance;
+use cargo_prefetch::{
+    cache, cargo, config, crates_io, db_dump, download, exit, platforms, rank, registries, report,
+    snapshot, sources, temp, timings, tools, top, tracker,
+};
+use cargo_prefetch::{
+    download_direct, fetch, fetch_selected, glob_match, is_crates_io, parse_specs, resolve_all,
+    unversioned, CrateSet, Exclusion, Options, Package, Resolution,
+};
+use clap::{crate_version, App, AppSettings, Arg, Shell, SubCommand};
 use failure::{bail, format_err, Fallible, ResultExt};
-use serde_derive::Deserialize;
-use std::collections::HashSet;
+use regex::Regex;
+use serde_derive::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
 use std::fs;
-use std::path::Path;
-use std::process::Command;
-use tempfile::TempDir;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{debug, error, info, warn};
 
-mod top;
+mod check;
+mod classroom;
+mod clean;
+mod daemon;
+mod diff;
+mod hook;
+mod interactive;
+mod locations;
+mod log;
+mod make_top;
+mod script;
+mod search;
+mod status;
+mod toolchain;
+mod verify;
+mod watch;
 
-const TEMP_PROJ_NAME: &str = "temp_prefetch_project";
+/// The environment variable for Cargo's `resolver.incompatible-rust-versions`
+/// config.
+const RESOLVER_RUST_VERSIONS: &str = "CARGO_RESOLVER_INCOMPATIBLE_RUST_VERSIONS";
 
 const HELP: &str = "\
 This command is used to download some popular dependencies into Cargo's cache. \
@@ -21,19 +56,24 @@ dependencies (--top-deps=100).
 ";
 
 fn main() {
-    if let Err(e) = run() {
-        eprintln!("Error: {}", e);
+    let result = run();
+    if let Err(e) = &result {
+        let mut message = e.to_string();
         for cause in e.iter_causes() {
-            eprintln!("Caused by: {}", cause);
+            message.push_str(&format!("\nCaused by: {}", cause));
+        }
+        if tracing::dispatcher::has_been_set() {
+            error!("{}", message);
+        } else {
+            // Logging failed to start, so nothing would be printed.
+            eprintln!("error: {}", message);
         }
-        std::process::exit(1);
     }
+    std::process::exit(exit::code(&result));
 }
 
-type CrateSet = HashSet<(String, Option<String>)>;
-
-fn run() -> Fallible<()> {
-    let app_matches = App::new("cargo-prefetch")
+fn app() -> App<'static, 'static> {
+    App::new("cargo-prefetch")
         .version(crate_version!())
         .bin_name("cargo")
         .setting(AppSettings::SubcommandRequiredElseHelp)
@@ -51,11 +91,350 @@ fn run() -> Fallible<()> {
                         .long("list")
                         .help("List what is downloaded instead of downloading."),
                 )
+                .arg(
+                    Arg::with_name("summary")
+                        .long("summary")
+                        .conflicts_with_all(&["list", "emit-script"])
+                        .help(
+                            "Print the number of crates and versions that would be \
+                             downloaded, their estimated size, and where they come from, \
+                             instead of downloading.",
+                        ),
+                )
+                .arg(Arg::with_name("detailed").long("detailed").help(
+                    "Include download counts, the last update date, the license \
+                     and size, the description, and why each crate is included \
+                     with --list.",
+                ))
+                .arg(
+                    Arg::with_name("missing-only")
+                        .long("missing-only")
+                        .requires("list")
+                        .help("With --list, only show packages that are not in the cache yet."),
+                )
+                .arg(
+                    Arg::with_name("emit-script")
+                        .long("emit-script")
+                        .value_name("TOOL")
+                        .possible_values(&["curl", "wget", "aria2"])
+                        .conflicts_with("list")
+                        .help(
+                            "Print a shell script that downloads the selected packages \
+                             into Cargo's cache with TOOL, checking their checksums, \
+                             instead of fetching them.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("list-sort")
+                        .long("list-sort")
+                        .value_name("ORDER")
+                        .possible_values(&["name", "size", "downloads"])
+                        .requires("list")
+                        .help(
+                            "The order of --list. `size` and `downloads` put the largest \
+                             first, and look them up on crates.io. Defaults to `name`.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("list-filter")
+                        .long("list-filter")
+                        .value_name("GLOB")
+                        .requires("list")
+                        .help(
+                            "With --list, only show packages whose name matches GLOB, \
+                             such as `tokio-*`.",
+                        ),
+                )
+                .arg(Arg::with_name("urls").long("urls").requires("list").help(
+                    "With --list, print the download URL of each package instead, \
+                     for download managers or firewall allowlists.",
+                ))
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .possible_values(&["toml", "pins", "json", "markdown", "html"])
+                        .default_value("toml")
+                        .help(
+                            "The format of --list. `pins` prints `name@=version` lines \
+                             which can be passed back in with `cargo prefetch -`. `json` \
+                             prints an array including each package's checksum. \
+                             `markdown` and `html` print a table with the size and \
+                             license of each package, implying --detailed.",
+                        ),
+                )
                 .arg(
                     Arg::with_name("verbose")
                         .short("v")
                         .long("verbose")
-                        .help("Print some extra info to stderr."),
+                        .multiple(true)
+                        .help("Print some extra info to stderr. Use -vv for even more."),
+                )
+                .arg(
+                    Arg::with_name("message-format")
+                        .long("message-format")
+                        .value_name("FORMAT")
+                        .possible_values(&["human", "json"])
+                        .default_value("human")
+                        .help(
+                            "The format of progress messages. `json` prints a JSON \
+                             object on stdout for each package as it is queued, \
+                             downloaded, skipped, or fails.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("color")
+                        .long("color")
+                        .value_name("WHEN")
+                        .possible_values(&["auto", "always", "never"])
+                        .help(
+                            "Whether to color the output. Defaults to `auto`, which \
+                             colors it when stderr is a terminal and NO_COLOR isn't \
+                             set, or to CARGO_TERM_COLOR if it is set.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("log-format")
+                        .long("log-format")
+                        .value_name("FORMAT")
+                        .possible_values(&["human", "json"])
+                        .default_value("human")
+                        .help(
+                            "The format of messages printed to stderr. `json` prints \
+                             one JSON object per line, including the spans (such as \
+                             the crate being downloaded) it happened in.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("quiet")
+                        .short("q")
+                        .long("quiet")
+                        .conflicts_with("verbose")
+                        .help(
+                            "Hide Cargo's progress output, and only print errors and a \
+                             one-line summary.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("cargo-home")
+                        .long("cargo-home")
+                        .value_name("DIR")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help(
+                            "Use DIR as Cargo's home directory instead of CARGO_HOME, \
+                             such as to populate a cache for a container image. \
+                             If given more than once, the crates are downloaded into \
+                             the first, and then linked or copied into the others.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("output-dir")
+                        .long("output-dir")
+                        .value_name("DIR")
+                        .help(
+                            "Also copy every fetched .crate file into DIR, along with a \
+                             manifest.json listing their names, versions, and checksums.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("temp-dir")
+                        .long("temp-dir")
+                        .value_name("PATH")
+                        .help(
+                            "Directory where the temporary project is created. \
+                             Defaults to the system temp directory.",
+                        ),
+                )
+                .arg(Arg::with_name("keep-temp").long("keep-temp").help(
+                    "Keep the temporary project after running, to inspect the \
+                     generated Cargo.toml and Cargo.lock.",
+                ))
+                .arg(
+                    Arg::with_name("rust-version")
+                        .long("rust-version")
+                        .value_name("VERSION")
+                        .validator(|v| {
+                            if toolchain::is_version(&v) {
+                                Ok(())
+                            } else {
+                                Err(format!("`{}` is not a Rust version such as 1.70", v))
+                            }
+                        })
+                        .help(
+                            "Prefer versions of crates that support this Rust version, \
+                             such as 1.70. Defaults to the version in a rust-toolchain.toml \
+                             or rust-toolchain file in the current directory or its \
+                             parents. Requires Cargo 1.84 or newer.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("cargo")
+                        .long("cargo")
+                        .value_name("PATH")
+                        .help(
+                            "The cargo binary to run. Defaults to the CARGO environment \
+                             variable, or `cargo`.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("toolchain")
+                        .long("toolchain")
+                        .value_name("TOOLCHAIN")
+                        .help("Run cargo with a rustup toolchain, such as `nightly`."),
+                )
+                .arg(
+                    Arg::with_name("no-cargo")
+                        .long("no-cargo")
+                        .conflicts_with_all(&[
+                            "cargo",
+                            "toolchain",
+                            "cargo-arg",
+                            "resolver",
+                            "index-snapshot",
+                            "minimal-versions",
+                            "resolutions",
+                        ])
+                        .help(
+                            "Resolve using the crates.io index and download directly, \
+                             without running Cargo, such as on a machine without Rust \
+                             installed. This selects the newest matching version of each \
+                             dependency, which may differ from what Cargo would select.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("jobs")
+                        .short("j")
+                        .long("jobs")
+                        .value_name("N")
+                        .help(
+                            "The most packages to download at once. With more than one, \
+                             Cargo is only used to resolve the temp project, and the \
+                             packages it selects are downloaded directly, since several \
+                             `cargo fetch` processes wait on each other for the package cache. \
+                             The number running starts at half of N, and goes up while the \
+                             throughput improves and down when the server rate limits.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("index-snapshot")
+                        .long("index-snapshot")
+                        .value_name("COMMIT|DATE")
+                        .help(
+                            "Resolve with the crates.io index as it was at a commit of \
+                             its git repository, or at the end of a date such as \
+                             2024-01-31, so that the same crates are selected however \
+                             much later this runs. The first use clones the git index, \
+                             which is large. Requires git and cargo 1.63 or newer.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("resolver")
+                        .long("resolver")
+                        .value_name("VERSION")
+                        .possible_values(&["1", "2", "3"])
+                        .help(
+                            "The version of Cargo's resolver to use for the temp project, \
+                             which changes which versions and optional dependencies are \
+                             selected. Version 3 requires Cargo 1.84 or newer.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("minimal-versions")
+                        .long("minimal-versions")
+                        .help(
+                            "Resolve with `-Z minimal-versions`, for offline \
+                             `cargo update -Z minimal-versions` jobs. Crates without a \
+                             version are pinned to their newest version, and their \
+                             dependencies get the oldest versions they allow. Requires a \
+                             nightly Cargo, such as with `--toolchain nightly`.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("resolutions")
+                        .long("resolutions")
+                        .value_name("KIND,...")
+                        .possible_values(&["min", "max"])
+                        .use_delimiter(true)
+                        .conflicts_with("minimal-versions")
+                        .help(
+                            "Resolve the crates once for each kind, and fetch the packages \
+                             of all of them. `max` is Cargo's usual resolution, and `min` is \
+                             the same as --minimal-versions. Use `min,max` for projects \
+                             that test against both ends of their version ranges.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("cargo-arg")
+                        .long("cargo-arg")
+                        .value_name("ARG")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .allow_hyphen_values(true)
+                        .help(
+                            "Pass an extra argument to the cargo commands that are run, \
+                             such as `--cargo-arg=--config=net.git-fetch-with-cli=true`.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("credential-process")
+                        .long("credential-process")
+                        .value_name("COMMAND")
+                        .help(
+                            "Command that prints a token to use for HTTP requests to the \
+                             --credential-hosts, and to the host of --api-url if it isn't \
+                             crates.io. Other hosts use their `machine` entry in ~/.netrc.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("credential-host")
+                        .long("credential-host")
+                        .value_name("HOST")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .requires("credential-process")
+                        .help("A host to send the token from --credential-process to."),
+                )
+                .arg(
+                    Arg::with_name("user-agent")
+                        .long("user-agent")
+                        .value_name("STRING")
+                        .help(
+                            "The User-Agent header to send with HTTP requests. \
+                             May also be set with `prefetch.user-agent` in Cargo's config.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("api-url")
+                        .long("api-url")
+                        .value_name("URL")
+                        .help(
+                            "The base URL of the crates.io API, such as a mirror. \
+                             May also be set with `prefetch.api-url` in Cargo's config. \
+                             Defaults to https://crates.io.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("api-cache-ttl")
+                        .long("api-cache-ttl")
+                        .value_name("HOURS")
+                        .default_value("24")
+                        .validator(|v| parse_ttl(&v, 60 * 60).map(drop))
+                        .help(
+                            "How long crates.io API responses are cached before checking \
+                             if they have changed.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("resolve-cache-ttl")
+                        .long("resolve-cache-ttl")
+                        .value_name("MINUTES")
+                        .default_value("60")
+                        .validator(|v| parse_ttl(&v, 60).map(drop))
+                        .help(
+                            "How long the resolution of the same crates is reused instead \
+                             of running `cargo generate-lockfile` again. Versions published \
+                             since then aren't selected until it expires. 0 disables it.",
+                        ),
                 )
                 .arg(
                     Arg::with_name("top-deps")
@@ -67,6 +446,20 @@ fn run() -> Fallible<()> {
                              Specify a value for the number to download, default is 100.",
                         ),
                 )
+                .arg(Arg::with_name("refresh-top").long("refresh-top").help(
+                    "Compute a current list for --top-deps from the crates.io index \
+                     instead of using the built-in list. The list is cached for a week.",
+                ))
+                .arg(
+                    Arg::with_name("db-dump")
+                        .long("db-dump")
+                        .conflicts_with("refresh-top")
+                        .help(
+                            "Compute --top-deps and --top-downloads from the crates.io \
+                             database dump instead of the built-in list and the crates.io API. \
+                             The dump is downloaded once a day.",
+                        ),
+                )
                 .arg(
                     Arg::with_name("top-downloads")
                         .long("top-downloads")
@@ -77,18 +470,912 @@ fn run() -> Fallible<()> {
                              Specify a value for the number to download, default is 100.",
                         ),
                 )
+                .arg(
+                    Arg::with_name("top-keyword")
+                        .long("top-keyword")
+                        .value_name("KEYWORD[=N]")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help(
+                            "Download the most downloaded crates with the given keyword. \
+                             Specify a value for the number to download, default is 100.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("top-category")
+                        .long("top-category")
+                        .value_name("CATEGORY[=N],...")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .use_delimiter(true)
+                        .help(
+                            "Download the most downloaded crates in the given categories, \
+                             such as `embedded=100,wasm=50`. The default number for each \
+                             is 100. See `cargo prefetch categories` for the categories.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("top-platform")
+                        .long("top-platform")
+                        .value_name("PLATFORM[=N],...")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .use_delimiter(true)
+                        .help(
+                            "Download a list of crates that are important on a platform, \
+                             which is `windows`, `unix`, `linux`, `macos`, or `wasm`. \
+                             Specify a value for the number to download, default is all.",
+                        ),
+                )
+                .arg(Arg::with_name("no-std-only").long("no-std-only").help(
+                    "Only keep the crates that can be used without std, such as \
+                     for embedded development. A crate counts if it has a `std` \
+                     feature, or is in the `no-std` category or has a `no-std` \
+                     keyword on crates.io. Their dependencies aren't filtered.",
+                ))
+                .arg(
+                    Arg::with_name("top-combined")
+                        .long("top-combined")
+                        .value_name("N")
+                        .help(
+                            "Download the top crates from a ranking that combines \
+                             --top-deps and --top-downloads.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("combined-weight")
+                        .long("combined-weight")
+                        .value_name("WEIGHT")
+                        .default_value("0.5")
+                        .help(
+                            "The weight given to the most frequent dependencies for \
+                             --top-combined, between 0 and 1. The rest of the weight is \
+                             given to the most downloaded crates.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("recent")
+                        .long("recent")
+                        .value_name("N")
+                        .help("Download the N most recently updated crates."),
+                )
+                .arg(
+                    Arg::with_name("reverse-deps")
+                        .long("reverse-deps")
+                        .value_name("CRATE[=N]")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help(
+                            "Download the most downloaded crates that depend on the given crate. \
+                             Specify a value for the number to download, default is 100.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("ecosystem")
+                        .long("ecosystem")
+                        .value_name("CRATE[=N]")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help(
+                            "Download a crate along with its most downloaded dependents \
+                             (and all of their dependencies). \
+                             Specify a value for the number of dependents, default is 100.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("owner")
+                        .long("owner")
+                        .value_name("LOGIN")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Download all crates owned by the given crates.io user."),
+                )
+                .arg(
+                    Arg::with_name("team")
+                        .long("team")
+                        .value_name("TEAM")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help(
+                            "Download all crates owned by the given crates.io team, \
+                             such as `github:org:team`.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("match")
+                        .long("match")
+                        .value_name("REGEX")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help(
+                            "Download every crate on crates.io whose name matches a regular \
+                             expression, such as `^embedded-hal(-.*)?$`. The list of \
+                             names comes from the crates.io database dump, which is \
+                             downloaded once a day like with --db-dump.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("all-versions")
+                        .long("all-versions")
+                        .value_name("CRATE")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help(
+                            "Download every published version of the given crate. \
+                             This is the same as `CRATE@*all`.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("latest-n")
+                        .long("latest-n")
+                        .value_name("N")
+                        .help(
+                            "Download the newest version of each of the N newest \
+                             semver-incompatible releases (such as 0.8, 0.7, and 0.6) \
+                             of each crate that doesn't specify a version.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("include-prereleases")
+                        .long("include-prereleases")
+                        .help(
+                            "Allow crates that don't specify a version to resolve to a \
+                             prerelease, if that is the newest version.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("no-prerelease")
+                        .long("no-prerelease")
+                        .conflicts_with("include-prereleases")
+                        .help(
+                            "Make sure crates that don't specify a version never resolve to \
+                             a prerelease. Crates that only have prereleases are skipped \
+                             instead of failing the whole set.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("published-since")
+                        .long("published-since")
+                        .value_name("DATE")
+                        .help(
+                            "Download every version published on or after the given date \
+                             (such as 2024-01-31) of each crate that doesn't specify a version.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("published-before")
+                        .long("published-before")
+                        .value_name("DATE")
+                        .help(
+                            "Download every version published before the given date \
+                             of each crate that doesn't specify a version.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("as-of")
+                        .long("as-of")
+                        .value_name("DATE")
+                        .conflicts_with_all(&["published-since", "published-before", "latest-n"])
+                        .help(
+                            "Download the newest version that had been published by the end \
+                             of the given date (such as 2023-06-01) of each crate that \
+                             doesn't specify a version. This only pins the crates given, \
+                             not their dependencies, which are still resolved to the \
+                             newest compatible versions; use --index-snapshot to also \
+                             resolve them as of that date.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("write-lock")
+                        .long("write-lock")
+                        .value_name("PATH")
+                        .help(
+                            "After fetching, write the exact versions and checksums of \
+                             everything fetched to a file such as `prefetch.lock`.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("interactive")
+                        .long("interactive")
+                        .conflicts_with("list")
+                        .help(
+                            "Choose which of the resolved packages to fetch in a terminal \
+                             UI, with their sizes and a search.",
+                        ),
+                )
+                .arg(Arg::with_name("search").long("search").help(
+                    "Search crates.io at a prompt, and add crates from the results \
+                     to the ones to fetch.",
+                ))
+                .arg(
+                    Arg::with_name("report")
+                        .long("report")
+                        .value_name("PATH")
+                        .help(
+                            "After fetching, write a JSON summary of the run to a file, \
+                             with the requested crates, resolved versions, bytes \
+                             downloaded, skipped crates, and timing.",
+                        ),
+                )
+                .arg(Arg::with_name("offline").long("offline").help(
+                    "Don't access the network. Versions are resolved from Cargo's \
+                     local copy of the index, and crates.io API responses from \
+                     earlier runs are used however old they are. Mostly useful \
+                     with --list, since a fetch can only use what is already \
+                     cached. Same as Cargo's `net.offline`.",
+                ))
+                .arg(
+                    Arg::with_name("resume")
+                        .long("resume")
+                        .conflicts_with_all(&["list", "interactive", "locked"])
+                        .help(
+                            "Continue the last fetch that was interrupted or failed, \
+                             with the same crates, skipping the ones it already \
+                             downloaded. Without an unfinished fetch, this fetches \
+                             normally.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("timings")
+                        .long("timings")
+                        .value_name("PATH")
+                        .min_values(0)
+                        .max_values(1)
+                        .require_equals(true)
+                        .help(
+                            "At the end of the run, print how long was spent querying the \
+                             index and API, resolving, and downloading, and for each temp \
+                             project. With `--timings=PATH`, also write the timings to a file, as \
+                             HTML if it ends with `.html` and JSON otherwise.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("post-hook")
+                        .long("post-hook")
+                        .value_name("COMMAND")
+                        .help(
+                            "Run a shell command after a successful fetch, with \
+                             environment variables such as CARGO_PREFETCH_DOWNLOADED \
+                             describing what was fetched. May also be set with \
+                             `prefetch.post-hook` in Cargo's config.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("locked")
+                        .long("locked")
+                        .value_name("PATH")
+                        .help(
+                            "Download exactly the packages recorded in a file written with \
+                             --write-lock, checking their checksums.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("lockfile")
+                        .long("lockfile")
+                        .value_name("PATH")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help(
+                            "Download the crates.io packages in the given Cargo.lock, \
+                             which may be a path or an http(s) URL. May be given \
+                             multiple times.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("lockfile-diff")
+                        .long("lockfile-diff")
+                        .value_names(&["OLD", "NEW"])
+                        .help(
+                            "Download the crates.io packages in the NEW Cargo.lock that \
+                             are not in the OLD one, such as after updating dependencies.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("repo")
+                        .long("repo")
+                        .value_name("ORG/NAME[@REF]")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help(
+                            "Download the crates.io packages in a GitHub repository's \
+                             Cargo.lock, or resolve its Cargo.toml if it doesn't have \
+                             one. REF may be a branch, tag, or commit.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("tools")
+                        .long("tools")
+                        .value_name("PATH")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help(
+                            "Download everything needed to `cargo install --offline` \
+                             the tools listed in PATH, which has a `[tools]` table of \
+                             names and versions, or is a Cargo.toml with \
+                             `[package.metadata.bin]` like cargo-run-bin uses.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("incremental")
+                        .long("incremental")
+                        .value_name("PATH")
+                        .conflicts_with_all(&["list", "interactive", "locked"])
+                        .help(
+                            "Record the resolution in a state file, and on later runs only \
+                             fetch crates that depend on a package that changed in the \
+                             index since then. Crates removed from the cache are not \
+                             fetched again until they change.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("watch")
+                        .long("watch")
+                        .requires("lockfile")
+                        .conflicts_with_all(&["list", "interactive", "search", "locked"])
+                        .help(
+                            "Keep running, and fetch again whenever a --lockfile or the \
+                             Cargo.toml next to it changes.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("exclude")
+                        .long("exclude")
+                        .value_name("SPEC")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help(
+                            "Never download a crate, even as a dependency. A version \
+                             requirement excludes only the matching versions, such as \
+                             `openssl-sys@<0.9.90`. The name may be a pattern such as \
+                             `windows-*`.",
+                        ),
+                )
+                .arg(Arg::with_name("allow-yanked").long("allow-yanked").help(
+                    "Download yanked versions in --lockfile directly from crates.io, \
+                     since Cargo won't select them in the temp project.",
+                ))
+                .arg(
+                    Arg::with_name("sort")
+                        .long("sort")
+                        .possible_values(&["downloads", "recent"])
+                        .default_value("downloads")
+                        .help(
+                            "The ranking used for --top-downloads, --top-keyword, and \
+                             --top-category. \
+                             `recent` uses downloads from the last 90 days.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("glob-limit")
+                        .long("glob-limit")
+                        .value_name("N")
+                        .default_value("100")
+                        .help(
+                            "Ask before fetching more than N crates matching a pattern \
+                             such as `tokio-*`, or fail when stdin isn't a terminal.",
+                        ),
+                )
                 .arg(Arg::with_name("crates").multiple(true).help(
                     "Specify individual crates to download. \
-                     Use the syntax `crate_name@=2.7.0` to download a specific version.",
-                )),
+                     Use the syntax `crate_name@=2.7.0` to download a specific version. \
+                     A name may be a pattern, such as `serde*` or `tokio-*`, which is \
+                     matched against the crates on crates.io. \
+                     Any version requirement is accepted, such as `tokio@^1.30` or \
+                     `serde@>=1.0.150,<1.0.190`. Several exact versions can be listed, \
+                     such as `serde@1.0.100,1.0.150`. Use `crate_name@*all` for every version. \
+                     Use `-` to read crates from stdin, one per line.",
+                ))
+                .subcommand(
+                    SubCommand::with_name("classroom")
+                        .about("Create a bundle for an offline workshop.")
+                        .arg(
+                            Arg::with_name("project")
+                                .long("project")
+                                .value_name("PATH_OR_URL")
+                                .required(true)
+                                .help("The template project, as a local path or git URL."),
+                        )
+                        .arg(
+                            Arg::with_name("out")
+                                .long("out")
+                                .value_name("DIR")
+                                .required(true)
+                                .help("Directory where the bundle is created."),
+                        )
+                        .arg(Arg::with_name("crates").multiple(true).help(
+                            "Additional crates to include, in addition to \
+                             common beginner crates.",
+                        )),
+                )
+                .subcommand(
+                    SubCommand::with_name("make-top")
+                        .about("Generate the list of the most common dependencies.")
+                        .arg(
+                            Arg::with_name("count")
+                                .long("count")
+                                .value_name("N")
+                                .help("The number of crates to include, default is 1000."),
+                        )
+                        .arg(
+                            Arg::with_name("index")
+                                .long("index")
+                                .value_name("PATH")
+                                .help(
+                                    "Path to a local checkout of the index, \
+                                     instead of fetching from index.crates.io.",
+                                ),
+                        )
+                        .arg(
+                            Arg::with_name("output")
+                                .long("output")
+                                .short("o")
+                                .value_name("FILE")
+                                .help("File to write the list to, default is stdout."),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("daemon")
+                        .about(
+                            "Keep running, and fetch on a schedule with the flags \
+                             given before `daemon`.",
+                        )
+                        .arg(
+                            Arg::with_name("every")
+                                .long("every")
+                                .value_name("INTERVAL")
+                                .required(true)
+                                .help("How often to fetch, such as `6h` or `1d`."),
+                        )
+                        .arg(
+                            Arg::with_name("state")
+                                .long("state")
+                                .value_name("PATH")
+                                .help(
+                                    "File recording when the last fetch ran, \
+                                     default is `prefetch-daemon.json` in Cargo's home.",
+                                ),
+                        ),
+                )
+                .subcommand(SubCommand::with_name("cache-key").about(
+                    "Print a hash of the crates given before `cache-key` and the \
+                     versions they resolve to, for use as a CI cache key.",
+                ))
+                .subcommand(
+                    SubCommand::with_name("status")
+                        .about("Show statistics about the crates in Cargo's cache."),
+                )
+                .subcommand(
+                    SubCommand::with_name("categories")
+                        .about("List the categories on crates.io with their crate counts."),
+                )
+                .subcommand(
+                    SubCommand::with_name("check")
+                        .about(
+                            "Check that every package in a lockfile is cached, so that it \
+                             can be built offline.",
+                        )
+                        .arg(
+                            Arg::with_name("lockfile")
+                                .long("lockfile")
+                                .value_name("PATH")
+                                .required(true)
+                                .multiple(true)
+                                .number_of_values(1)
+                                .help(
+                                    "A Cargo.lock to check. Repeat to check several. \
+                                     Exits with an error listing what is missing if any \
+                                     `.crate` file or index entry isn't in the cache.",
+                                ),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("where")
+                        .about("Print the locations of Cargo's cache.")
+                        .arg(
+                            Arg::with_name("format")
+                                .long("format")
+                                .possible_values(&["human", "json"])
+                                .default_value("human")
+                                .help("The output format."),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("clean")
+                        .about("Remove old crates from Cargo's cache.")
+                        .arg(
+                            Arg::with_name("older-than")
+                                .long("older-than")
+                                .value_name("AGE")
+                                .help(
+                                    "Remove crates downloaded longer ago than AGE, \
+                                     such as `90d`, `12h`, or `4w`.",
+                                ),
+                        )
+                        .arg(
+                            Arg::with_name("max-size")
+                                .long("max-size")
+                                .value_name("SIZE")
+                                .help(
+                                    "Remove the oldest crates until the cache is smaller \
+                                     than SIZE, such as `5GiB`.",
+                                ),
+                        )
+                        .arg(
+                            Arg::with_name("dry-run")
+                                .long("dry-run")
+                                .help("Print what would be removed without removing anything."),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("prune")
+                        .about("Remove all but the newest versions of each crate in Cargo's cache.")
+                        .arg(
+                            Arg::with_name("keep")
+                                .long("keep")
+                                .value_name("N")
+                                .required(true)
+                                .help("The number of versions of each crate to keep."),
+                        )
+                        .arg(
+                            Arg::with_name("dry-run")
+                                .long("dry-run")
+                                .help("Print what would be removed without removing anything."),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("verify")
+                        .about("Check the cached .crate files against the checksums in the index.")
+                        .arg(
+                            Arg::with_name("repair").long("repair").help(
+                                "Download corrupt files again, and remove partial downloads.",
+                            ),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("diff-crate")
+                        .about("Compare two cached versions of a crate.")
+                        .arg(
+                            Arg::with_name("text")
+                                .long("text")
+                                .help("Print a unified diff of the changed text files."),
+                        )
+                        .arg(
+                            Arg::with_name("old")
+                                .required(true)
+                                .help("The old version, as `crate_name@1.2.0`."),
+                        )
+                        .arg(
+                            Arg::with_name("new")
+                                .required(true)
+                                .help("The new version, as `crate_name@1.3.0`."),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("completions")
+                        .about("Print shell completions for `cargo prefetch`.")
+                        .arg(
+                            Arg::with_name("shell")
+                                .required(true)
+                                .possible_values(&Shell::variants())
+                                .help("The shell to print completions for."),
+                        ),
+                ),
         )
-        .get_matches();
+}
+
+fn run() -> Fallible<()> {
+    let app_matches = app().get_matches();
 
     let matches = app_matches
         .subcommand_matches("prefetch")
         .expect("Expected `prefetch` subcommand.");
+    let log_format = matches.value_of("log-format").unwrap();
+    let color = match matches.value_of("color") {
+        // Escape codes would end up inside the JSON.
+        _ if log_format == "json" => "never".to_string(),
+        Some(color) => color.to_string(),
+        None => env::var("CARGO_TERM_COLOR").unwrap_or_else(|_| "auto".to_string()),
+    };
+    log::init(
+        matches.occurrences_of("verbose"),
+        log::LogFormat::parse(log_format)?,
+    )?;
+    // Colors are looked up when a message is logged, so this can come after
+    // logging is set up, and its errors still get logged.
+    color::init(&color)?;
+    let started = SystemTime::now();
+    timings::begin();
+    let _timings = if matches.is_present("timings") {
+        Some(timings::Report {
+            path: matches.value_of("timings").map(PathBuf::from),
+        })
+    } else {
+        None
+    };
+
+    let mut cargo_homes = Vec::new();
+    for cargo_home in matches.values_of("cargo-home").into_iter().flatten() {
+        fs::create_dir_all(cargo_home)
+            .with_context(|_| format!("Failed to create `{}`.", cargo_home))?;
+        cargo_homes.push(fs::canonicalize(cargo_home)?);
+    }
+    if let Some(cargo_home) = cargo_homes.first() {
+        // This is inherited by `cargo`, and used for direct downloads.
+        env::set_var("CARGO_HOME", cargo_home);
+    }
+    let copy_out = |pkgs: &[LockedPackage]| -> Fallible<()> {
+        for home in cargo_homes.iter().skip(1) {
+            info!("Copying {} packages to `{}`", pkgs.len(), home.display());
+            cache::copy_to_home(home, pkgs)?;
+        }
+        if let Some(dir) = matches.value_of("output-dir") {
+            info!("Copying {} packages to `{}`", pkgs.len(), dir);
+            cache::copy_to_dir(Path::new(dir), pkgs)?;
+        }
+        Ok(())
+    };
+    // Cargo's cache tracker is updated in every home, after the copies.
+    let mark_used = |pkgs: &[LockedPackage]| -> Fallible<()> {
+        let homes = if cargo_homes.is_empty() {
+            vec![cache::cargo_home()?]
+        } else {
+            cargo_homes.clone()
+        };
+        for home in &homes {
+            if let Err(e) = tracker::mark_used(home, pkgs) {
+                warn!(
+                    "failed to update Cargo's cache tracker in `{}`: {}",
+                    home.display(),
+                    e
+                );
+            }
+        }
+        Ok(())
+    };
+
+    if let Some(completions_matches) = matches.subcommand_matches("completions") {
+        let shell: Shell = completions_matches
+            .value_of("shell")
+            .unwrap()
+            .parse()
+            .unwrap();
+        app().gen_completions_to("cargo", shell, &mut io::stdout());
+        return Ok(());
+    }
+    if let Some(diff_matches) = matches.subcommand_matches("diff-crate") {
+        return diff::diff_crate(diff_matches);
+    }
+    if let Some(check_matches) = matches.subcommand_matches("check") {
+        return check::check(check_matches);
+    }
+    if let Some(where_matches) = matches.subcommand_matches("where") {
+        return locations::print_locations(where_matches);
+    }
+    if matches.subcommand_matches("status").is_some() {
+        return status::status();
+    }
+    if let Some(clean_matches) = matches.subcommand_matches("clean") {
+        return clean::clean(clean_matches);
+    }
+    if let Some(prune_matches) = matches.subcommand_matches("prune") {
+        return clean::prune(prune_matches);
+    }
+    if matches.is_present("watch") {
+        let lockfiles: Vec<&str> = matches
+            .values_of("lockfile")
+            .into_iter()
+            .flatten()
+            .collect();
+        return watch::watch(&lockfiles);
+    }
+    if let Some(daemon_matches) = matches.subcommand_matches("daemon") {
+        return daemon::daemon(daemon_matches);
+    }
+
+    let rust_version = match matches.value_of("rust-version") {
+        Some(version) => Some(version.to_string()),
+        None => match toolchain::find_rust_version(&env::current_dir()?)? {
+            Some((path, version)) => {
+                debug!("Using Rust {} from {}", version, path.display());
+                Some(version)
+            }
+            None => None,
+        },
+    };
+    // Cargo only prefers versions that support the `rust-version` when this
+    // is set.
+    if rust_version.is_some() && env::var_os(RESOLVER_RUST_VERSIONS).is_none() {
+        env::set_var(RESOLVER_RUST_VERSIONS, "fallback");
+    }
+    let config = config::load()?;
+    config.export_registries();
+    config.net.export();
+    let offline = matches.is_present("offline") || config.net.offline == Some(true);
+    if matches.is_present("offline") {
+        env::set_var("CARGO_NET_OFFLINE", "true");
+    }
+    if offline {
+        let remote_lockfile = matches
+            .values_of("lockfile")
+            .into_iter()
+            .flatten()
+            .chain(matches.values_of("lockfile-diff").into_iter().flatten())
+            .any(sources::is_url);
+        for (flag, needed) in [
+            ("--refresh-top", matches.is_present("refresh-top")),
+            ("--repo", matches.is_present("repo")),
+            ("--search", matches.is_present("search")),
+            ("a lockfile URL", remote_lockfile),
+        ] {
+            if needed {
+                bail!("{} needs the network, and can't be used offline", flag);
+            }
+        }
+    }
+    let post_hook = matches
+        .value_of("post-hook")
+        .map(String::from)
+        .or(config.post_hook.clone());
+    let mut options = Options {
+        quiet: matches.is_present("quiet"),
+        messages: Messages::new(
+            matches.value_of("message-format") == Some("json"),
+            matches.is_present("report") || post_hook.is_some(),
+        ),
+        temp_dir: matches.value_of("temp-dir").map(PathBuf::from),
+        keep_temp: matches.is_present("keep-temp"),
+        rust_version,
+        incremental: matches.value_of("incremental").map(PathBuf::from),
+        resolver: matches.value_of("resolver").map(String::from),
+        optional: HashSet::new(),
+        resolve_cache_ttl: parse_ttl(matches.value_of("resolve-cache-ttl").unwrap(), 60)
+            .map_err(|e| format_err!("resolve-cache-ttl {}", e))?,
+        resume: matches.is_present("resume"),
+        index_snapshot: matches.value_of("index-snapshot").map(String::from),
+        resolutions: if matches.is_present("minimal-versions") {
+            vec![Resolution::Minimal]
+        } else {
+            let mut resolutions = Vec::new();
+            for kind in matches.values_of("resolutions").into_iter().flatten() {
+                let resolution = match kind {
+                    "min" => Resolution::Minimal,
+                    _ => Resolution::Maximal,
+                };
+                if !resolutions.contains(&resolution) {
+                    resolutions.push(resolution);
+                }
+            }
+            if resolutions.is_empty() {
+                resolutions.push(Resolution::Maximal);
+            }
+            resolutions
+        },
+        exclude: matches.values_of("exclude").map_or_else(
+            || Ok(Vec::new()),
+            |values| values.map(Exclusion::parse).collect(),
+        )?,
+        jobs: match matches.value_of("jobs") {
+            Some(jobs) => match jobs.parse::<usize>() {
+                Ok(jobs) if jobs > 0 => jobs,
+                _ => bail!("--jobs must be a positive integer, got `{}`", jobs),
+            },
+            None => 1,
+        },
+        no_cargo: matches.is_present("no-cargo"),
+        cargo: if matches.is_present("no-cargo") {
+            cargo::Cargo::not_installed()
+        } else {
+            let mut args: Vec<String> = matches
+                .values_of("cargo-arg")
+                .map_or_else(Vec::new, |values| values.map(String::from).collect());
+            if let Some(rev) = matches.value_of("index-snapshot") {
+                args.extend(snapshot::cargo_args(rev)?);
+            }
+            cargo::Cargo::new(
+                matches.value_of("cargo"),
+                matches.value_of("toolchain"),
+                args,
+            )?
+        },
+    };
+    if options.index_snapshot.is_some() {
+        options.cargo.require("--index-snapshot", 1, 63)?;
+    }
+    let minimal = options.resolutions.contains(&Resolution::Minimal);
+    if minimal && !options.cargo.is_nightly() {
+        bail!("minimal versions require a nightly Cargo, such as with `--toolchain nightly`");
+    }
+    temp::install_signal_handler()?;
+    if let Some(classroom_matches) = matches.subcommand_matches("classroom") {
+        if options.no_cargo {
+            bail!("classroom requires Cargo, and can't be used with --no-cargo");
+        }
+        return classroom::classroom(&options, classroom_matches);
+    }
+    let cache_ttl = parse_ttl(matches.value_of("api-cache-ttl").unwrap(), 60 * 60)
+        .map_err(|e| format_err!("api-cache-ttl {}", e))?;
+    let http = http::Http::new(
+        matches.value_of("credential-process").map(String::from),
+        matches
+            .values_of("credential-host")
+            .map_or_else(Vec::new, |values| values.map(String::from).collect()),
+        cache_ttl,
+        matches
+            .value_of("user-agent")
+            .map(String::from)
+            .or(config.user_agent.clone())
+            .unwrap_or_else(|| http::DEFAULT_USER_AGENT.to_string()),
+        matches
+            .value_of("api-url")
+            .or(config.api_url.as_deref())
+            .unwrap_or(http::DEFAULT_API_URL),
+        config.net.retry.unwrap_or(http::DEFAULT_NET_RETRY),
+        offline,
+    );
+    if let Some(make_top_matches) = matches.subcommand_matches("make-top") {
+        return make_top::make_top(&http, make_top_matches);
+    }
+    if matches.subcommand_matches("categories").is_some() {
+        for category in crates_io::categories(&http)? {
+            println!(
+                "{:<28} {:>7}  {}",
+                category.slug, category.crates_cnt, category.description
+            );
+        }
+        return Ok(());
+    }
+    if let Some(verify_matches) = matches.subcommand_matches("verify") {
+        return verify::verify(&http, verify_matches);
+    }
 
-    let verbose = matches.is_present("verbose");
+    let outputs = hook::Outputs {
+        lock: matches.value_of("write-lock"),
+        report: matches.value_of("report"),
+    };
+    if let Some(path) = matches.value_of("locked") {
+        let pkgs = prefetch_lock::read(Path::new(path))?;
+        let lock = cache::lock_package_cache(false)?;
+        for pkg in &pkgs {
+            if matches.is_present("list") {
+                println!("{} = \"{}\"", pkg.name, pkg.version);
+                continue;
+            }
+            options
+                .messages
+                .emit(Event::Queued, &pkg.name, &pkg.version);
+            download_direct(&options, &http, &pkg.name, &pkg.version, &pkg.checksum)?;
+        }
+        drop(lock);
+        if !matches.is_present("list") {
+            copy_out(&pkgs)?;
+            mark_used(&pkgs)?;
+            if let Some(path) = matches.value_of("report") {
+                let requested: Vec<String> = pkgs
+                    .iter()
+                    .map(|pkg| format!("{}@={}", pkg.name, pkg.version))
+                    .collect();
+                let history = options.messages.history();
+                let mut provenance = Provenance::default();
+                let source = format!("locked {}", matches.value_of("locked").unwrap());
+                let pinned = pkgs
+                    .iter()
+                    .map(|pkg| (pkg.name.clone(), Some(format!("={}", pkg.version))));
+                provenance.add(&mut CrateSet::new(), &source, pinned);
+                report::write(
+                    Path::new(path),
+                    started,
+                    &requested,
+                    &pkgs,
+                    &history,
+                    &provenance,
+                )?;
+            }
+            if let Some(post_hook) = &post_hook {
+                hook::run(post_hook, &pkgs, &options.messages.history(), &outputs)?;
+            }
+        }
+        return Ok(());
+    }
 
     let parse_int = |name: &str| match matches.value_of(name) {
         Some(value) => match value.parse::<usize>() {
@@ -106,208 +1393,1110 @@ fn run() -> Fallible<()> {
 
     let mut top_deps = parse_int("top-deps")?;
     let top_downloads = parse_int("top-downloads")?;
+    let recent = parse_int("recent")?;
+    let top_combined = parse_int("top-combined")?;
+    let sort = match matches.value_of("sort") {
+        Some("recent") => crates_io::Sort::RecentDownloads,
+        _ => crates_io::Sort::Downloads,
+    };
+    let named_counts = |name: &str| {
+        matches.values_of(name).map_or_else(
+            || Ok(Vec::new()),
+            |values| values.map(parse_named_count).collect::<Fallible<Vec<_>>>(),
+        )
+    };
+    let top_keywords = named_counts("top-keyword")?;
+    let top_categories = named_counts("top-category")?;
+    let top_platforms = named_counts("top-platform")?;
+    let reverse_deps = named_counts("reverse-deps")?;
+    let ecosystems = named_counts("ecosystem")?;
 
     // Default behavior with no command-line options.
-    if !matches.is_present("crates") && top_deps.is_none() && top_downloads.is_none() {
+    if !matches.is_present("crates")
+        && !matches.is_present("search")
+        && !matches.is_present("all-versions")
+        && !matches.is_present("lockfile")
+        && !matches.is_present("repo")
+        && !matches.is_present("tools")
+        && !matches.is_present("lockfile-diff")
+        && top_deps.is_none()
+        && top_downloads.is_none()
+        && recent.is_none()
+        && top_combined.is_none()
+        && top_keywords.is_empty()
+        && top_categories.is_empty()
+        && top_platforms.is_empty()
+        && reverse_deps.is_empty()
+        && ecosystems.is_empty()
+        && !matches.is_present("owner")
+        && !matches.is_present("team")
+        && !matches.is_present("match")
+    {
         top_deps = Some(100);
     }
 
+    let db_rankings = if matches.is_present("db-dump") {
+        if matches.value_of("sort") == Some("recent") {
+            bail!("--sort recent is not supported with --db-dump");
+        }
+        Some(db_dump::rankings(&http)?)
+    } else {
+        None
+    };
+    let top_deps_list = |count: usize| -> Fallible<Vec<String>> {
+        let mut list = if let Some(rankings) = &db_rankings {
+            rankings.top_deps.clone()
+        } else if matches.is_present("refresh-top") {
+            rank::refreshed_top_deps(&http)?
+        } else {
+            top::TOP_CRATES.iter().map(|s| s.to_string()).collect()
+        };
+        list.truncate(count);
+        Ok(list)
+    };
+
+    let top_downloads_list = |count: usize| -> Fallible<Vec<String>> {
+        match &db_rankings {
+            Some(rankings) => Ok(rankings.top_downloads.iter().take(count).cloned().collect()),
+            None => crates_io::top_downloads(&http, sort, count),
+        }
+    };
+
     let mut crates: CrateSet = HashSet::new();
+    let mut provenance = Provenance::default();
     if let Some(top) = top_deps {
-        for name in top::TOP_CRATES.iter().take(top) {
-            crates.insert((name.to_string(), None));
-        }
+        provenance.add(&mut crates, "top-deps", sources::names(top_deps_list(top)?));
+    }
+    if let Some(top) = top_combined {
+        let weight = matches
+            .value_of("combined-weight")
+            .unwrap()
+            .parse::<f64>()
+            .ok()
+            .filter(|w| (0.0..=1.0).contains(w))
+            .ok_or_else(|| format_err!("combined-weight must be a number between 0 and 1"))?;
+        let deps = top_deps_list(top)?;
+        let downloads = top_downloads_list(top)?;
+        let names = rank::combine(&deps, &downloads, weight, top);
+        provenance.add(&mut crates, "top-combined", sources::names(names));
     }
     if let Some(top) = top_downloads {
-        for name in top_crates_io(verbose, top)? {
-            crates.insert((name.to_string(), None));
+        let names = top_downloads_list(top)?;
+        provenance.add(&mut crates, "top-downloads", sources::names(names));
+    }
+    if let Some(count) = recent {
+        let names = crates_io::recently_updated(&http, count)?;
+        provenance.add(&mut crates, "recent", sources::names(names));
+    }
+    for (keyword, top) in top_keywords {
+        let names = crates_io::top_keyword(&http, sort, &keyword, top)?;
+        let source = format!("top-keyword {}", keyword);
+        provenance.add(&mut crates, &source, sources::names(names));
+    }
+    for (platform, top) in top_platforms {
+        let names = platforms::crates(&platform).ok_or_else(|| {
+            let known: Vec<&str> = platforms::PLATFORMS.iter().map(|(name, _)| *name).collect();
+            format_err!(
+                "unknown platform `{}` for --top-platform, expected one of {}",
+                platform,
+                known.join(", ")
+            )
+        })?;
+        let source = format!("top-platform {}", platform);
+        let names = names.iter().take(top).map(|s| s.to_string());
+        provenance.add(&mut crates, &source, sources::names(names));
+    }
+    for (category, top) in top_categories {
+        let names = crates_io::top_category(&http, sort, &category, top)?;
+        let source = format!("top-category {}", category);
+        provenance.add(&mut crates, &source, sources::names(names));
+    }
+
+    for (name, top) in reverse_deps {
+        let rdeps = crates_io::reverse_dependencies(&http, &name, top)?;
+        let source = format!("reverse-deps {}", name);
+        provenance.add(&mut crates, &source, sources::names(rdeps));
+    }
+    for (name, top) in ecosystems {
+        let mut names = crates_io::reverse_dependencies(&http, &name, top)?;
+        names.push(name.clone());
+        let source = format!("ecosystem {}", name);
+        provenance.add(&mut crates, &source, sources::names(names));
+    }
+    if let Some(owners) = matches.values_of("owner") {
+        for owner in owners {
+            let names = crates_io::user_crates(&http, owner)?;
+            let source = format!("owner {}", owner);
+            provenance.add(&mut crates, &source, sources::names(names));
+        }
+    }
+    if let Some(teams) = matches.values_of("team") {
+        for team in teams {
+            let names = crates_io::team_crates(&http, team)?;
+            let source = format!("team {}", team);
+            provenance.add(&mut crates, &source, sources::names(names));
         }
     }
 
     if let Some(explicit_crates) = matches.values_of("crates") {
+        let glob_limit = parse_int("glob-limit")?.unwrap();
+        let expand = |specs| expand_globs(&http, specs, glob_limit);
         for krate in explicit_crates {
-            let mut splits = krate.split('@');
-            let name = splits.next().ok_or_else(|| format_err!("empty argument"))?;
-            let version = splits.next().map(|s| s.to_string());
-            crates.insert((name.to_string(), version));
+            if krate == "-" {
+                let mut input = String::new();
+                io::stdin()
+                    .read_to_string(&mut input)
+                    .with_context(|_| "Failed to read crates from stdin.")?;
+                for line in input.lines() {
+                    let line = line.trim();
+                    if !line.is_empty() && !line.starts_with('#') {
+                        provenance.add(&mut crates, "stdin", expand(parse_specs(line)?)?);
+                    }
+                }
+            } else {
+                provenance.add(&mut crates, "argument", expand(parse_specs(krate)?)?);
+            }
         }
     }
-
-    if matches.is_present("list") {
-        list(verbose, &crates)
-    } else {
-        if verbose {
-            list(verbose, &crates)?;
+    if let Some(patterns) = matches.values_of("match") {
+        let regexes = patterns
+            .map(|pattern| {
+                Regex::new(pattern)
+                    .map(|re| (pattern, re))
+                    .map_err(|e| format_err!("invalid --match `{}`: {}", pattern, e))
+            })
+            .collect::<Fallible<Vec<_>>>()?;
+        // The API can only list names 100 at a time, so they come from the
+        // database dump instead.
+        let all_names = match &db_rankings {
+            Some(rankings) => rankings.names.clone(),
+            None => db_dump::rankings(&http)?.names,
+        };
+        for (pattern, re) in regexes {
+            let names: Vec<String> = all_names
+                .iter()
+                .filter(|name| re.is_match(name))
+                .cloned()
+                .collect();
+            if names.is_empty() {
+                warn!("no crates on crates.io match `{}`", pattern);
+            } else {
+                info!("`{}` matches {} crates", pattern, names.len());
+            }
+            let source = format!("match {}", pattern);
+            provenance.add(&mut crates, &source, sources::names(names));
         }
-        do_fetch(verbose, &crates)
     }
-}
+    if matches.is_present("search") {
+        let names = search::prompt(&http)?;
+        provenance.add(&mut crates, "search", sources::names(names));
+    }
 
-/// Perform the download.
-fn do_fetch(verbose: bool, crates: &CrateSet) -> Fallible<()> {
-    let dir = mktemp()?;
-    let tmp_path = dir.path();
-    make_project(tmp_path, crates)?;
+    let mut lock_pkgs = Vec::new();
+    let load_lockfile = |path: &str| {
+        if sources::is_url(path) {
+            sources::lockfile_url_packages(&http, path)
+        } else {
+            sources::lockfile_packages(Path::new(path))
+        }
+    };
+    let pinned = |pkgs: &[Package]| -> Vec<(String, Option<String>)> {
+        pkgs.iter()
+            .map(|pkg| (pkg.name.clone(), Some(format!("={}", pkg.version))))
+            .collect()
+    };
+    // Packages from other registries are fetched by their own temp projects.
+    let mut registry_pkgs = Vec::new();
+    let mut split_registries = |pkgs: Vec<Package>| -> Vec<Package> {
+        let (crates_io, other): (Vec<_>, Vec<_>) = pkgs
+            .into_iter()
+            .partition(|pkg| pkg.source.as_deref().is_some_and(is_crates_io));
+        registry_pkgs.extend(other);
+        crates_io
+    };
+    for path in matches.values_of("lockfile").into_iter().flatten() {
+        let pkgs = split_registries(load_lockfile(path)?);
+        let source = format!("lockfile {}", path);
+        provenance.add(&mut crates, &source, pinned(&pkgs));
+        lock_pkgs.extend(pkgs);
+    }
+    if let Some(mut paths) = matches.values_of("lockfile-diff") {
+        let old = load_lockfile(paths.next().unwrap())?;
+        let new_path = paths.next().unwrap();
+        let new = load_lockfile(new_path)?;
+        let new_len = new.len();
+        let added: Vec<_> = new
+            .into_iter()
+            .filter(|pkg| {
+                !old.iter().any(|o| {
+                    o.name == pkg.name && o.version == pkg.version && o.source == pkg.source
+                })
+            })
+            .collect();
+        info!(
+            "{} of {} packages are new in the lockfile",
+            added.len(),
+            new_len
+        );
+        let added = split_registries(added);
+        let source = format!("lockfile-diff {}", new_path);
+        provenance.add(&mut crates, &source, pinned(&added));
+        lock_pkgs.extend(added);
+    }
+    // Several lockfiles often share packages, which only need fetching once.
+    registry_pkgs
+        .sort_by(|a, b| (&a.name, &a.version, &a.source).cmp(&(&b.name, &b.version, &b.source)));
+    registry_pkgs
+        .dedup_by(|a, b| a.name == b.name && a.version == b.version && a.source == b.source);
+    for repo in matches.values_of("repo").into_iter().flatten() {
+        let source = format!("repo {}", repo);
+        provenance.add(&mut crates, &source, sources::repo(&http, repo)?);
+    }
+    for path in matches.values_of("tools").into_iter().flatten() {
+        for tool in tools::parse(Path::new(path))? {
+            let source = format!("tool {}", tool.name);
+            provenance.add(&mut crates, &source, tools::crates(&options, &http, &tool)?);
+        }
+    }
+    // Download rankings sometimes include crates that can't be used as a
+    // dependency, which are skipped unless they were also asked for some
+    // other way.
+    options.optional = crates
+        .iter()
+        .map(|(name, _)| name)
+        .filter(|name| {
+            let sources = provenance.sources(name);
+            !sources.is_empty()
+                && sources
+                    .iter()
+                    .all(|s| *s == "top-downloads" || *s == "top-combined")
+        })
+        .cloned()
+        .collect();
+    // Projects often share packages, which only need to be checked once.
+    lock_pkgs.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+    lock_pkgs.dedup_by(|a, b| a.name == b.name && a.version == b.version);
+    let mut requested: Vec<String> = crates
+        .iter()
+        .map(|(name, version)| match version {
+            Some(version) => format!("{}@{}", name, version),
+            None => name.clone(),
+        })
+        .collect();
+    requested.sort();
+    let mut yanked = Vec::new();
+    if matches.is_present("allow-yanked") {
+        for pkg in &lock_pkgs {
+            let entry = index::entries(&http, &pkg.name)?
+                .into_iter()
+                .find(|entry| entry.vers == pkg.version && entry.yanked);
+            if let Some(entry) = entry {
+                warn!(
+                    "{}@{} is yanked, it will be downloaded directly",
+                    pkg.name, pkg.version
+                );
+                crates.remove(&(pkg.name.clone(), Some(format!("={}", pkg.version))));
+                yanked.push((pkg.name.clone(), entry));
+            }
+        }
+    }
 
-    if verbose {
-        eprintln!("Running: cargo fetch");
+    let mut all_versions: Vec<String> = matches
+        .values_of("all-versions")
+        .map_or_else(Vec::new, |values| values.map(String::from).collect());
+    crates.retain(|(name, version)| {
+        if version.as_deref() == Some("*all") {
+            all_versions.push(name.clone());
+            false
+        } else {
+            true
+        }
+    });
+    for name in all_versions {
+        for version in index::all_versions(&http, &name)? {
+            crates.insert((name.clone(), Some(format!("={}", version))));
+        }
+    }
+    let date = |name: &str| -> Fallible<Option<&str>> {
+        match matches.value_of(name) {
+            Some(date) if !is_date(date) => {
+                bail!("{} must be a date such as 2024-01-31, got `{}`", name, date)
+            }
+            date => Ok(date),
+        }
+    };
+    let since = date("published-since")?;
+    let before = date("published-before")?;
+    if since.is_some() || before.is_some() {
+        for name in unversioned(&crates) {
+            crates.remove(&(name.clone(), None));
+            for version in index::published_between(&http, &name, since, before)? {
+                crates.insert((name.clone(), Some(format!("={}", version))));
+            }
+        }
+    }
+    if let Some(as_of) = date("as-of")? {
+        for name in unversioned(&crates) {
+            crates.remove(&(name.clone(), None));
+            match index::newest_as_of(&http, &name, as_of)? {
+                Some(version) => {
+                    crates.insert((name, Some(format!("={}", version))));
+                }
+                None => {
+                    warn!("skipping `{}`, it had no releases by {}", name, as_of);
+                    let reason = format!("it had no releases by {}", as_of);
+                    options.messages.skip_crate(&name, &reason);
+                    exit::mark_incomplete();
+                }
+            }
+        }
     }
 
-    let status = Command::new("cargo")
-        .arg("fetch")
-        .current_dir(tmp_path)
-        .status()
-        .with_context(|_| "Failed to launch `cargo`.")?;
-    if !status.success() {
-        bail!("`cargo` failed to run: {}", status);
+    if matches.is_present("no-std-only") {
+        let mut names: Vec<String> = crates.iter().map(|(name, _)| name.clone()).collect();
+        names.sort();
+        names.dedup();
+        let mut skipped = 0;
+        for name in names {
+            if !is_no_std(&http, &name)? {
+                debug!("skipping `{}`, it doesn't appear to support no_std", name);
+                options
+                    .messages
+                    .skip_crate(&name, "it doesn't appear to support no_std");
+                crates.retain(|(n, _)| *n != name);
+                skipped += 1;
+            }
+        }
+        if skipped > 0 {
+            info!(
+                "Skipped {} crates that don't appear to support no_std",
+                skipped
+            );
+        }
+    }
+    if matches.is_present("no-prerelease") {
+        for name in unversioned(&crates) {
+            let has_release = index::entries(&http, &name)?.iter().any(|entry| {
+                !entry.yanked && semver::Version::parse(&entry.vers).is_ok_and(|v| v.pre.is_empty())
+            });
+            if !has_release {
+                warn!("skipping `{}`, it only has prereleases", name);
+                options
+                    .messages
+                    .skip_crate(&name, "it only has prereleases");
+                exit::mark_incomplete();
+                crates.remove(&(name, None));
+            }
+        }
+    }
+    let prereleases = matches.is_present("include-prereleases");
+    if let Some(n) = parse_int("latest-n")? {
+        for name in unversioned(&crates) {
+            crates.remove(&(name.clone(), None));
+            for version in index::latest_versions(&http, &name, n, prereleases)? {
+                crates.insert((name.clone(), Some(format!("={}", version))));
+            }
+        }
+    }
+    if minimal {
+        // The oldest version of a crate asked for without a version is
+        // rarely what anyone wants.
+        for name in unversioned(&crates) {
+            if let Some(newest) = index::latest_versions(&http, &name, 1, prereleases)?.pop() {
+                crates.remove(&(name.clone(), None));
+                crates.insert((name, Some(format!("={}", newest))));
+            }
+        }
+    }
+    if prereleases {
+        // Cargo never selects a prerelease for `*`, so pin the newest version
+        // when it is one.
+        for name in unversioned(&crates) {
+            if let Some(newest) = index::newest_version(&http, &name)? {
+                if semver::Version::parse(&newest).is_ok_and(|v| !v.pre.is_empty()) {
+                    crates.remove(&(name.clone(), None));
+                    crates.insert((name, Some(format!("={}", newest))));
+                }
+            }
+        }
     }
 
-    Ok(())
-}
+    if !options.exclude.is_empty() {
+        // Exact versions that are excluded are dropped before resolving, so
+        // that their dependencies aren't selected either.
+        let excluded = |name: &str, version: Option<&str>| {
+            options.exclude.iter().any(|e| match version {
+                Some(version) => e.matches(name, version),
+                None => e.version.is_none() && glob_match(&e.name, name),
+            })
+        };
+        crates.retain(|(name, version)| {
+            let exact = version.as_deref().and_then(|v| v.strip_prefix('='));
+            if excluded(name, exact) {
+                debug!("excluding `{}`", name);
+                options.messages.skip_crate(name, "excluded");
+                return false;
+            }
+            true
+        });
+        yanked.retain(|(name, entry)| !excluded(name, Some(&entry.vers)));
+        registry_pkgs.retain(|pkg| !excluded(&pkg.name, Some(&pkg.version)));
+    }
 
-/// Print all packages that would be downloaded.
-fn list(verbose: bool, crates: &CrateSet) -> Fallible<()> {
-    let dir = mktemp()?;
-    let tmp_path = dir.path();
-    make_project(tmp_path, crates)?;
-    if verbose {
-        eprintln!("Running: cargo generate-lockfile");
-    }
-    let output = Command::new("cargo")
-        .arg("generate-lockfile")
-        .current_dir(tmp_path)
-        .output()
-        .with_context(|_| "Failed to launch `cargo`.")?;
-    if !output.status.success() {
-        bail!(
-            "`cargo` failed to run:\n{}\n{}\n{}\n",
-            output.status,
-            String::from_utf8_lossy(&output.stdout),
-            String::from_utf8_lossy(&output.stderr)
+    let list_options = ListOptions {
+        format: match matches.value_of("format") {
+            _ if matches.is_present("urls") => ListFormat::Urls,
+            Some("pins") => ListFormat::Pins,
+            Some("json") => ListFormat::Json,
+            Some("markdown") => ListFormat::Markdown,
+            Some("html") => ListFormat::Html,
+            _ => ListFormat::Toml,
+        },
+        detailed: matches.is_present("detailed")
+            || matches!(matches.value_of("format"), Some("markdown" | "html")),
+        missing_only: matches.is_present("missing-only"),
+        sort: match matches.value_of("list-sort") {
+            Some("size") => ListSort::Size,
+            Some("downloads") => ListSort::Downloads,
+            _ => ListSort::Name,
+        },
+        filter: matches.value_of("list-filter").map(String::from),
+    };
+    let fetching = !["cache-key", "summary", "emit-script", "list", "interactive"]
+        .iter()
+        .any(|name| matches.is_present(name) || matches.subcommand_matches(name).is_some());
+    if !fetching && !registry_pkgs.is_empty() {
+        warn!(
+            "leaving out {} packages from registries other than crates.io, \
+             they are only fetched",
+            registry_pkgs.len()
         );
     }
-    let pkgs = load_from_lock(tmp_path)?;
-    for pkg in pkgs {
-        if pkg.name != TEMP_PROJ_NAME {
-            println!("{} = \"{}\"", pkg.name, pkg.version);
+    if matches.subcommand_matches("cache-key").is_some() {
+        let pkgs = resolve_all(&options, &http, &crates, &yanked)?;
+        println!("{}", cache_key(&requested, &pkgs));
+        return Ok(());
+    }
+    if matches.is_present("summary") {
+        let pkgs = resolve_all(&options, &http, &crates, &yanked)?;
+        return summary(&http, &pkgs, &provenance);
+    }
+    if let Some(tool) = matches.value_of("emit-script") {
+        let pkgs = resolve_all(&options, &http, &crates, &yanked)?;
+        print!("{}", script::emit(&http, tool, &pkgs)?);
+        return Ok(());
+    }
+    if matches.is_present("list") {
+        list(
+            &options,
+            &http,
+            &crates,
+            &yanked,
+            &provenance,
+            &list_options,
+        )
+    } else {
+        let fetched = if matches.is_present("interactive") {
+            let pkgs = interactive::select(&http, resolve_all(&options, &http, &crates, &yanked)?)?;
+            if pkgs.is_empty() {
+                info!("Nothing selected");
+                return Ok(());
+            }
+            fetch_selected(&options, &http, pkgs)?
+        } else {
+            if matches.is_present("verbose") {
+                list(
+                    &options,
+                    &http,
+                    &crates,
+                    &yanked,
+                    &provenance,
+                    &list_options,
+                )?;
+            }
+            let fetched = fetch(&options, &http, &crates, &yanked)?;
+            let registry_fetched = registries::fetch(&options, &config, &registry_pkgs)?;
+            let outputs = ["output-dir", "write-lock", "report"];
+            if registry_fetched > 0
+                && (cargo_homes.len() > 1
+                    || post_hook.is_some()
+                    || outputs.iter().any(|name| matches.is_present(name)))
+            {
+                warn!(
+                    "the {} packages from other registries are only fetched into Cargo's \
+                     cache, and are left out of --output-dir, --write-lock, --report, \
+                     the other --cargo-home directories, and the post-fetch hook",
+                    registry_fetched
+                );
+            }
+            fetched
+        };
+        if !options.quiet {
+            print_totals(&options.messages);
+        }
+        copy_out(&fetched)?;
+        mark_used(&fetched)?;
+        if let Some(path) = matches.value_of("write-lock") {
+            prefetch_lock::write(Path::new(path), &fetched)?;
+        }
+        if let Some(path) = matches.value_of("report") {
+            let history = options.messages.history();
+            report::write(
+                Path::new(path),
+                started,
+                &requested,
+                &fetched,
+                &history,
+                &provenance,
+            )?;
+        }
+        if let Some(post_hook) = &post_hook {
+            hook::run(post_hook, &fetched, &options.messages.history(), &outputs)?;
         }
+        Ok(())
     }
-    Ok(())
 }
 
-/// Create a temporary Cargo project with the given dependencies.
-fn make_project(tmp_path: &Path, crates: &CrateSet) -> Fallible<()> {
-    let newest = "*".to_string();
-    let deps: Vec<String> = crates
+/// Print how many packages were downloaded, skipped, and failed.
+fn print_totals(messages: &Messages) {
+    let part = |event, color, what| {
+        let count = messages.count(event);
+        (
+            Some(color).filter(|_| count > 0),
+            format!("{} {}", count, what),
+        )
+    };
+    let parts = [
+        part(Event::Done, Color::Green, "downloaded"),
+        part(Event::Skipped, Color::Yellow, "skipped"),
+        part(Event::Failed, Color::Red, "failed"),
+    ];
+    color::print_line(&[
+        (parts[0].0, &parts[0].1),
+        (None, ", "),
+        (parts[1].0, &parts[1].1),
+        (None, ", "),
+        (parts[2].0, &parts[2].1),
+    ]);
+}
+
+/// Returns whether a crate can likely be used without `std`, for
+/// `--no-std-only`.
+///
+/// A `std` feature in the newest version usually means `std` can be turned
+/// off. Otherwise the crate counts if it is in the `no-std` category or has a
+/// keyword for it on crates.io.
+fn is_no_std(http: &Http, name: &str) -> Fallible<bool> {
+    let entries = index::entries(http, name)?;
+    let newest = entries
         .iter()
-        .map(|(name, version)| {
-            format!(
-                "\"{}\" = \"{}\"\n",
-                name,
-                version.as_ref().unwrap_or(&newest)
-            )
+        .rev()
+        .find(|entry| !entry.yanked)
+        .or(entries.last());
+    if newest.is_some_and(|entry| {
+        entry.features.contains_key("std") || entry.features2.contains_key("std")
+    }) {
+        return Ok(true);
+    }
+    let tags = crates_io::crate_tags(http, name)?;
+    Ok(tags
+        .categories
+        .unwrap_or_default()
+        .iter()
+        .any(|c| c == "no-std" || c.starts_with("no-std::"))
+        || tags
+            .keywords
+            .unwrap_or_default()
+            .iter()
+            .any(|k| matches!(k.as_str(), "no-std" | "no_std" | "nostd")))
+}
+
+/// Returns whether the value is a date such as `2024-01-31`.
+fn is_date(value: &str) -> bool {
+    value.len() == 10
+        && value.char_indices().all(|(i, c)| match i {
+            4 | 7 => c == '-',
+            _ => c.is_ascii_digit(),
         })
-        .collect();
+}
 
-    // NOTE: This method of using a single project to resolve all dependencies
-    // may result in some crates using an older version due to restrictive
-    // version requirements. In practice I haven't seen any that are forced to
-    // resolve to an older version.
-
-    fs::write(
-        tmp_path.join("Cargo.toml"),
-        format!(
-            r#"
-            [package]
-            name = "{}"
-            version = "0.0.0"
-
-            [dependencies]
-            {}
-            "#,
-            TEMP_PROJ_NAME,
-            deps.join("")
-        ),
-    )?;
-    fs::create_dir(tmp_path.join("src"))?;
-    fs::write(tmp_path.join("src").join("lib.rs"), "")?;
-    Ok(())
+/// Parse a count of `unit` seconds from a TTL option value.
+fn parse_ttl(value: &str, unit: u64) -> Result<Duration, String> {
+    let count = value
+        .parse::<u64>()
+        .map_err(|e| format!("must be an integer: {}", e))?;
+    count
+        .checked_mul(unit)
+        .map(Duration::from_secs)
+        .ok_or_else(|| "is too large".to_string())
 }
 
-fn mktemp() -> Fallible<TempDir> {
-    Ok(tempfile::tempdir().with_context(|_| "Failed to create temp directory.")?)
+/// Replace the specs whose names are patterns with the matching crates.
+///
+/// If more than `limit` crates match a pattern, this asks whether to fetch
+/// them all.
+fn expand_globs(
+    http: &Http,
+    specs: Vec<(String, Option<String>)>,
+    limit: usize,
+) -> Fallible<Vec<(String, Option<String>)>> {
+    let mut result = Vec::new();
+    for (name, version) in specs {
+        if !name.contains(['*', '?']) {
+            result.push((name, version));
+            continue;
+        }
+        let names = crates_io::glob_names(http, &name)?;
+        if names.is_empty() {
+            bail!("no crates on crates.io match `{}`", name);
+        }
+        info!("`{}` matches {} crates", name, names.len());
+        if names.len() > limit {
+            if !io::stdin().is_terminal() {
+                bail!(
+                    "`{}` matches {} crates, which is more than --glob-limit {}",
+                    name,
+                    names.len(),
+                    limit
+                );
+            }
+            eprint!(
+                "Fetch all {} crates matching `{}`? [y/N] ",
+                names.len(),
+                name
+            );
+            io::stderr().flush()?;
+            let mut answer = String::new();
+            io::stdin().read_line(&mut answer)?;
+            if !matches!(answer.trim(), "y" | "Y" | "yes") {
+                bail!("not fetching the crates matching `{}`", name);
+            }
+        }
+        result.extend(names.into_iter().map(|name| (name, version.clone())));
+    }
+    Ok(result)
 }
 
-#[derive(Deserialize)]
-struct Package {
-    name: String,
-    version: String,
+/// Parse a `name=N` option value, where the count defaults to 100.
+fn parse_named_count(value: &str) -> Fallible<(String, usize)> {
+    let mut splits = value.splitn(2, '=');
+    let name = splits.next().unwrap();
+    if name.is_empty() {
+        bail!("expected a name in `{}`", value);
+    }
+    let count = match splits.next() {
+        Some(count) => count
+            .parse::<usize>()
+            .map_err(|e| format_err!("`{}` count must be an integer: {}", value, e))?,
+        None => 100,
+    };
+    Ok((name.to_string(), count))
 }
 
-#[derive(Deserialize)]
-struct Lockfile {
-    package: Option<Vec<Package>>,
+/// The output format for `--list`.
+#[derive(Clone, Copy)]
+enum ListFormat {
+    /// `name = "version"` lines.
+    Toml,
+    /// `name@=version` lines, suitable as input to `cargo prefetch -`.
+    Pins,
+    /// The URL each `.crate` file is downloaded from.
+    Urls,
+    /// A JSON array of [`ListEntry`].
+    Json,
+    /// A table for a review, such as in a change request.
+    Markdown,
+    Html,
 }
 
-#[derive(Deserialize)]
-struct CratesQuery {
-    crates: Vec<CrateInfo>,
+/// A package in `--list --format json`.
+#[derive(Serialize)]
+struct ListEntry<'a> {
+    name: &'a str,
+    version: &'a str,
+    /// The sha256 of the `.crate` file, from the index.
+    checksum: Option<&'a str>,
+    /// Details from crates.io, only with `--detailed`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    downloads: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    updated_at: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    license: Option<String>,
+    /// The size of the `.crate` file, in bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    /// Why the package is included.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<String>,
 }
 
-#[derive(Deserialize)]
-struct CrateInfo {
-    name: String,
+/// Options for `--list`.
+struct ListOptions {
+    format: ListFormat,
+    /// Whether to include information from crates.io.
+    detailed: bool,
+    /// Whether to skip packages that are already cached.
+    missing_only: bool,
+    sort: ListSort,
+    /// Only show packages whose name matches this glob.
+    filter: Option<String>,
 }
 
-/// Load a list of packages from a Cargo.lock file.
-fn load_from_lock(dir: &Path) -> Fallible<Vec<Package>> {
-    let contents = fs::read_to_string(dir.join("Cargo.lock"))?;
-    let lock: Lockfile = toml::from_str(&contents)?;
-    Ok(lock.package.unwrap_or_default())
+/// The order of `--list`.
+#[derive(Clone, Copy)]
+enum ListSort {
+    Name,
+    /// The size of the `.crate` file, largest first.
+    Size,
+    /// All-time downloads, most first.
+    Downloads,
 }
 
-/// Return the top downloaded crates by querying crates.io.
-fn top_crates_io(verbose: bool, mut count: usize) -> Fallible<Vec<String>> {
-    const CRATES_IO_MAX: usize = 100;
-    let mut result = Vec::new();
-    let mut page = 1;
-    while count > 0 {
-        let n = count.min(CRATES_IO_MAX);
-        let q = format!(
-            "https://crates.io/api/v1/crates?page={}&per_page={}&sort=downloads",
-            page, n
-        );
-        if verbose {
-            eprintln!("Sending request: {}", q);
-        }
-        let mut response =
-            reqwest::get(&q).with_context(|_| "Failed to fetch top crates from crates.io.")?;
-        let status = response.status();
-        if !status.is_success() {
-            let headers: Vec<_> = response
-                .headers()
-                .iter()
-                .map(|(key, value)| format!("{}: {:?}", key, value))
-                .collect();
-            bail!(
-                "Failed to fetch top crates crom crates.io.\n\
-                Status: {}\n\
-                Headers:\n\
-                {}\n\
-                {}
-                ",
-                status,
-                headers.join("\n"),
-                response.text().unwrap_or_else(|e| format!("{:?}", e))
+/// Print all packages that would be downloaded.
+fn list(
+    options: &Options,
+    http: &Http,
+    crates: &CrateSet,
+    yanked: &[(String, IndexEntry)],
+    provenance: &Provenance,
+    list_options: &ListOptions,
+) -> Fallible<()> {
+    let all = resolve_all(options, http, crates, yanked)?;
+    let mut pkgs = Vec::new();
+    for pkg in &all {
+        if let Some(filter) = &list_options.filter {
+            if !glob_match(filter, &pkg.name) {
+                continue;
+            }
+        }
+        if list_options.missing_only {
+            let cached = match &pkg.checksum {
+                Some(checksum) => matches!(
+                    download::check_cached(&pkg.name, &pkg.version, checksum)?,
+                    download::Cached::Valid
+                ),
+                None => false,
+            };
+            if cached {
+                continue;
+            }
+        }
+        pkgs.push(pkg);
+    }
+    let mut details = if list_options.detailed || matches!(list_options.sort, ListSort::Downloads) {
+        let mut names: Vec<&str> = pkgs.iter().map(|pkg| pkg.name.as_str()).collect();
+        names.dedup();
+        crates_io::crate_details(http, &names)?
+    } else {
+        HashMap::new()
+    };
+    let table = matches!(list_options.format, ListFormat::Markdown | ListFormat::Html);
+    let versions = if list_options.detailed || table || matches!(list_options.sort, ListSort::Size)
+    {
+        let mut names: Vec<&str> = pkgs.iter().map(|pkg| pkg.name.as_str()).collect();
+        names.sort_unstable();
+        names.dedup();
+        crates_io::all_version_details(http, &names)?
+    } else {
+        HashMap::new()
+    };
+    let version_details = |pkg: &Package| {
+        versions
+            .get(&pkg.name)
+            .and_then(|versions| versions.get(&pkg.version))
+    };
+    // All of the packages are needed to find the roots of the shown ones.
+    let dependents = if list_options.detailed {
+        dependents(&all, provenance)
+    } else {
+        HashMap::new()
+    };
+    match list_options.sort {
+        ListSort::Name => {}
+        ListSort::Size => {
+            pkgs.sort_by_key(|pkg| {
+                std::cmp::Reverse(version_details(pkg).and_then(|v| v.crate_size).unwrap_or(0))
+            });
+        }
+        ListSort::Downloads => {
+            pkgs.sort_by_key(|pkg| {
+                std::cmp::Reverse(details.get(&pkg.name).map_or(0, |d| d.downloads))
+            });
+            if !list_options.detailed {
+                details.clear();
+            }
+        }
+    }
+    let mut entries = Vec::new();
+    let mut rows = Vec::new();
+    for pkg in &pkgs {
+        let from = if list_options.detailed {
+            let sources = provenance.sources(&pkg.name);
+            if !sources.is_empty() {
+                Some(format!("from {}", sources.join(", ")))
+            } else {
+                let roots = dependents.get(&pkg.name[..]).map_or(&[][..], |r| &r[..]);
+                let mut from = format!("dependency of {}", roots[..roots.len().min(3)].join(", "));
+                if roots.len() > 3 {
+                    from.push_str(&format!(" and {} more", roots.len() - 3));
+                }
+                Some(from)
+            }
+        } else {
+            None
+        };
+        let version = if list_options.detailed || table {
+            version_details(pkg)
+        } else {
+            None
+        };
+        let size = version.and_then(|v| v.crate_size).map(cache::human_size);
+        let license = version.and_then(|v| v.license.clone());
+        if let ListFormat::Json = list_options.format {
+            let details = details.get(&pkg.name);
+            entries.push(ListEntry {
+                name: &pkg.name,
+                version: &pkg.version,
+                checksum: pkg.checksum.as_deref(),
+                downloads: details.map(|d| d.downloads),
+                updated_at: details.map(|d| d.updated_at.as_str()),
+                description: details.and_then(|d| d.description.as_deref()),
+                license: license.clone(),
+                size: version.and_then(|v| v.crate_size),
+                from,
+            });
+            continue;
+        }
+        if let ListFormat::Markdown | ListFormat::Html = list_options.format {
+            rows.push([
+                pkg.name.clone(),
+                pkg.version.clone(),
+                size.unwrap_or_default(),
+                license.clone().unwrap_or_default(),
+                details
+                    .get(&pkg.name)
+                    .map(|d| d.downloads.to_string())
+                    .unwrap_or_default(),
+                from.unwrap_or_default(),
+            ]);
+            continue;
+        }
+        // Details are printed as comments so that the output remains valid
+        // for the given format.
+        let mut comment = details.get(&pkg.name).map(|details| {
+            let mut comment = format!(
+                "{} downloads, updated {}",
+                details.downloads,
+                details.updated_at.get(..10).unwrap_or(&details.updated_at)
             );
+            for extra in license.iter().chain(size.iter()) {
+                comment.push_str(", ");
+                comment.push_str(extra);
+            }
+            if let Some(description) = &details.description {
+                comment.push_str(": ");
+                comment.push_str(&description.split_whitespace().collect::<Vec<_>>().join(" "));
+            }
+            comment
+        });
+        if let Some(from) = from {
+            comment = Some(match comment {
+                Some(comment) => format!("{} ({})", comment, from),
+                None => from,
+            });
+            if let Some(checksum) = &pkg.checksum {
+                comment = comment.map(|comment| format!("{}, sha256 {}", comment, checksum));
+            }
+        }
+        match list_options.format {
+            ListFormat::Toml => match comment {
+                Some(comment) => println!("{} = \"{}\" # {}", pkg.name, pkg.version, comment),
+                None => println!("{} = \"{}\"", pkg.name, pkg.version),
+            },
+            ListFormat::Pins => {
+                if let Some(comment) = comment {
+                    println!("# {}", comment);
+                }
+                println!("{}@={}", pkg.name, pkg.version);
+            }
+            ListFormat::Urls => {
+                if let Some(comment) = comment {
+                    println!("# {}", comment);
+                }
+                let cksum = pkg.checksum.as_deref().unwrap_or_default();
+                println!(
+                    "{}",
+                    download::download_url(http, &pkg.name, &pkg.version, cksum)?
+                );
+            }
+            ListFormat::Json | ListFormat::Markdown | ListFormat::Html => unreachable!(),
+        }
+    }
+    match list_options.format {
+        ListFormat::Json => println!("{}", serde_json::to_string_pretty(&entries)?),
+        ListFormat::Markdown | ListFormat::Html => print_table(list_options.format, &rows),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Print the rows of `--format markdown` or `html`.
+fn print_table(format: ListFormat, rows: &[[String; 6]]) {
+    const HEADER: [&str; 6] = [
+        "Crate",
+        "Version",
+        "Size",
+        "License",
+        "Downloads",
+        "Included",
+    ];
+    if let ListFormat::Html = format {
+        let cells = |row: &[&str], tag| {
+            row.iter()
+                .map(|cell| {
+                    let cell = cell
+                        .replace('&', "&amp;")
+                        .replace('<', "&lt;")
+                        .replace('>', "&gt;");
+                    format!("<{}>{}</{}>", tag, cell, tag)
+                })
+                .collect::<String>()
+        };
+        println!("<table>");
+        println!("<tr>{}</tr>", cells(&HEADER, "th"));
+        for row in rows {
+            let row: Vec<&str> = row.iter().map(String::as_str).collect();
+            println!("<tr>{}</tr>", cells(&row, "td"));
         }
+        println!("</table>");
+        return;
+    }
+    println!("| {} |", HEADER.join(" | "));
+    println!("|{}", "---|".repeat(HEADER.len()));
+    for row in rows {
+        let row: Vec<String> = row.iter().map(|cell| cell.replace('|', "\\|")).collect();
+        println!("| {} |", row.join(" | "));
+    }
+}
+
+/// A hash of the requested crates and the packages they resolved to, which
+/// only changes when a fetch would fetch something different.
+fn cache_key(requested: &[String], pkgs: &[Package]) -> String {
+    let mut hasher = Sha256::new();
+    for spec in requested {
+        hasher.update(format!("{}\n", spec));
+    }
+    hasher.update("\n");
+    for pkg in pkgs {
+        hasher.update(format!(
+            "{} {} {}\n",
+            pkg.name,
+            pkg.version,
+            pkg.checksum.as_deref().unwrap_or("")
+        ));
+    }
+    format!("{:x}", hasher.finalize())
+}
 
-        let json: CratesQuery = response.json()?;
-        for c in json.crates.into_iter() {
-            result.push(c.name);
+/// Print the totals of `--summary`.
+fn summary(http: &Http, pkgs: &[Package], provenance: &Provenance) -> Fallible<()> {
+    let cache_dir = cache::crates_io_cache_dir()?;
+    let mut names: Vec<&str> = pkgs.iter().map(|pkg| pkg.name.as_str()).collect();
+    names.dedup();
+    let mut size = 0;
+    let mut unknown = 0;
+    let mut cached = 0;
+    let mut use_api = true;
+    for pkg in pkgs {
+        let path = cache_dir.join(format!("{}-{}.crate", pkg.name, pkg.version));
+        let is_cached = match &pkg.checksum {
+            Some(checksum) => matches!(
+                download::check_cached(&pkg.name, &pkg.version, checksum)?,
+                download::Cached::Valid
+            ),
+            None => path.exists(),
+        };
+        let pkg_size = if is_cached {
+            cached += 1;
+            fs::metadata(&path).ok().map(|m| m.len())
+        } else if use_api {
+            match crates_io::crate_size(http, &pkg.name, &pkg.version) {
+                Ok(size) => size,
+                Err(e) => {
+                    // Don't wait for every request to fail.
+                    warn!("failed to look up crate sizes: {}", e);
+                    use_api = false;
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        match pkg_size {
+            Some(pkg_size) => size += pkg_size,
+            None => unknown += 1,
         }
-        page += 1;
-        count -= n;
     }
-    Ok(result)
+    println!("crates:   {}", names.len());
+    println!("versions: {} ({} cached)", pkgs.len(), cached);
+    if unknown > 0 {
+        println!(
+            "size:     {} ({} versions of unknown size)",
+            cache::human_size(size),
+            unknown
+        );
+    } else {
+        println!("size:     {}", cache::human_size(size));
+    }
+    let mut by_source: BTreeMap<&str, usize> = BTreeMap::new();
+    for name in &names {
+        let sources = provenance.sources(name);
+        if sources.is_empty() {
+            *by_source.entry("dependencies").or_default() += 1;
+        }
+        for source in sources {
+            *by_source.entry(source).or_default() += 1;
+        }
+    }
+    println!("sources:");
+    for (source, count) in by_source {
+        println!("  {}: {} crates", source, count);
+    }
+    Ok(())
+}
+
+/// Returns the requested crates that depend on each package, for explaining
+/// why a dependency is fetched.
+fn dependents<'a>(pkgs: &'a [Package], provenance: &Provenance) -> HashMap<&'a str, Vec<&'a str>> {
+    let mut deps: HashMap<&str, Vec<&str>> = HashMap::new();
+    for pkg in pkgs {
+        deps.entry(&pkg.name[..]).or_default().extend(
+            pkg.dependencies
+                .iter()
+                .filter_map(|dep| dep.split(' ').next()),
+        );
+    }
+    let mut dependents: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut roots: Vec<&str> = deps
+        .keys()
+        .copied()
+        .filter(|name| !provenance.sources(name).is_empty())
+        .collect();
+    roots.sort_unstable();
+    for root in roots {
+        let mut seen = HashSet::new();
+        let mut stack = vec![root];
+        while let Some(name) = stack.pop() {
+            for &dep in deps.get(name).into_iter().flatten() {
+                if dep != root && seen.insert(dep) {
+                    dependents.entry(dep).or_default().push(root);
+                    stack.push(dep);
+                }
+            }
+        }
+    }
+    dependents
 }
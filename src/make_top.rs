@@ -0,0 +1,124 @@
+//! The `make-top` subcommand for generating the list of top dependencies.
+//!
+//! This walks every package in the index, counting how many packages depend
+//! on each crate (using the newest version of each package), and writes the
+//! list in the same form as `src/top.rs`.
+
+use cargo_prefetch::crates_io;
+use cargo_prefetch::exit;
+use cargo_prefetch::http::Http;
+use cargo_prefetch::index;
+use cargo_prefetch::rank;
+use clap::ArgMatches;
+use failure::{format_err, Fallible, ResultExt};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
+use tracing::{info, warn};
+
+/// The number of concurrent requests to the sparse index.
+const JOBS: usize = 16;
+
+pub fn make_top(http: &Http, matches: &ArgMatches<'_>) -> Fallible<()> {
+    let count = match matches.value_of("count") {
+        Some(count) => count
+            .parse::<usize>()
+            .map_err(|e| format_err!("count must be an integer: {}", e))?,
+        None => 1000,
+    };
+    let counts = match matches.value_of("index") {
+        Some(path) => count_local(Path::new(path))?,
+        None => count_sparse(http)?,
+    };
+    let top = rank::top_counts(counts, count);
+
+    // rustfmt would align the counts, which makes every regeneration touch
+    // every line.
+    let mut out = String::from("#[rustfmt::skip]\n");
+    out.push_str(&format!(
+        "pub static TOP_CRATES: [&str; {}] = [\n",
+        top.len()
+    ));
+    for (count, name) in top {
+        out.push_str(&format!("    \"{}\", // {}\n", name, count));
+    }
+    out.push_str("];\n");
+    match matches.value_of("output") {
+        Some(path) => {
+            fs::write(path, out).with_context(|_| format!("Failed to write `{}`.", path))?
+        }
+        None => io::stdout().write_all(out.as_bytes())?,
+    }
+    Ok(())
+}
+
+/// Count dependencies by fetching every package from the sparse index.
+fn count_sparse(http: &Http) -> Fallible<HashMap<String, u32>> {
+    info!("Fetching the list of crates from crates.io...");
+    let names = crates_io::all_crate_names(http)?;
+    let total = names.len();
+    info!("Fetching {} packages from the index...", total);
+    let queue = Mutex::new(names);
+    let counts = Mutex::new(HashMap::new());
+    let errors = Mutex::new(Vec::new());
+    thread::scope(|s| {
+        for _ in 0..JOBS {
+            s.spawn(|| loop {
+                let name = match queue.lock().unwrap().pop() {
+                    Some(name) => name,
+                    None => break,
+                };
+                match index::fetch_sparse(http, &name) {
+                    Ok(Some(contents)) => {
+                        let mut counts = counts.lock().unwrap();
+                        rank::count_deps(&mut counts, &contents);
+                    }
+                    Ok(None) => {}
+                    Err(e) => errors.lock().unwrap().push(format!("{}: {}", name, e)),
+                }
+                let remaining = queue.lock().unwrap().len();
+                if remaining % 1000 == 0 {
+                    info!("{} of {} remaining", remaining, total);
+                }
+            });
+        }
+    });
+    let errors = errors.into_inner().unwrap();
+    if !errors.is_empty() {
+        exit::mark_incomplete();
+        warn!(
+            "failed to fetch {} packages:\n  {}",
+            errors.len(),
+            errors.join("\n  ")
+        );
+    }
+    Ok(counts.into_inner().unwrap())
+}
+
+/// Count dependencies from a local checkout of the index.
+fn count_local(path: &Path) -> Fallible<HashMap<String, u32>> {
+    let mut counts = HashMap::new();
+    walk(path, &mut counts)
+        .with_context(|_| format!("Failed to read index at `{}`.", path.display()))?;
+    Ok(counts)
+}
+
+fn walk(dir: &Path, counts: &mut HashMap<String, u32>) -> Fallible<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name.to_string_lossy().starts_with('.') || name == "config.json" {
+            continue;
+        }
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            walk(&path, counts)?;
+        } else if let Ok(contents) = fs::read_to_string(&path) {
+            rank::count_deps(counts, &contents);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,123 @@
+//! Progress messages for `--message-format json`.
+//!
+//! Like Cargo's `--message-format json`, each message is a JSON object on its
+//! own line on stdout, so that wrappers can show progress and collect
+//! failures. Every package goes through `queued`, and then ends with
+//! `skipped` (already cached), or `downloading` followed by `done` or
+//! `failed`. Crates that are skipped before resolving are `skipped` without
+//! a version.
+//!
+//! The messages can also be recorded for `--report`, and are counted for the
+//! summary at the end of a run.
+
+use serde_derive::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// What happened to a package.
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Event {
+    Queued,
+    Downloading,
+    Done,
+    Skipped,
+    Failed,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Message {
+    pub event: Event,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Why a package was skipped or failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Prints progress messages, if they are enabled, and records them.
+pub struct Messages {
+    print: bool,
+    /// The messages so far, if they are being recorded.
+    history: Option<Mutex<Vec<Message>>>,
+    done: AtomicUsize,
+    skipped: AtomicUsize,
+    failed: AtomicUsize,
+}
+
+impl Messages {
+    pub fn new(print: bool, record: bool) -> Messages {
+        Messages {
+            print,
+            history: if record {
+                Some(Mutex::new(Vec::new()))
+            } else {
+                None
+            },
+            done: AtomicUsize::new(0),
+            skipped: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn emit(&self, event: Event, name: &str, version: &str) {
+        self.emit_with_reason(event, name, version, None);
+    }
+
+    pub fn emit_with_reason(&self, event: Event, name: &str, version: &str, reason: Option<&str>) {
+        self.send(Message {
+            event,
+            name: name.to_string(),
+            version: Some(version.to_string()),
+            reason: reason.map(String::from),
+        });
+    }
+
+    /// Report a crate that was skipped before resolving a version.
+    pub fn skip_crate(&self, name: &str, reason: &str) {
+        self.send(Message {
+            event: Event::Skipped,
+            name: name.to_string(),
+            version: None,
+            reason: Some(reason.to_string()),
+        });
+    }
+
+    /// Returns the recorded messages.
+    pub fn history(&self) -> Vec<Message> {
+        match &self.history {
+            Some(history) => history.lock().unwrap().clone(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the number of packages that ended with the given event, which
+    /// is `done`, `skipped`, or `failed`.
+    pub fn count(&self, event: Event) -> usize {
+        match event {
+            Event::Done => self.done.load(Ordering::Relaxed),
+            Event::Skipped => self.skipped.load(Ordering::Relaxed),
+            Event::Failed => self.failed.load(Ordering::Relaxed),
+            Event::Queued | Event::Downloading => 0,
+        }
+    }
+
+    fn send(&self, message: Message) {
+        let count = match message.event {
+            Event::Done => Some(&self.done),
+            Event::Skipped => Some(&self.skipped),
+            Event::Failed => Some(&self.failed),
+            Event::Queued | Event::Downloading => None,
+        };
+        if let Some(count) = count {
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+        if self.print {
+            println!("{}", serde_json::to_string(&message).unwrap());
+        }
+        if let Some(history) = &self.history {
+            history.lock().unwrap().push(message);
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! Curated lists of platform-specific crates, for `--top-platform`.
+//!
+//! The top dependencies are counted across all of crates.io, so crates that
+//! only matter on one platform rank lower than they deserve for someone
+//! working on it. Each list is roughly in order of how commonly the crates
+//! are needed, so a count takes the most important ones.
+
+/// The platforms and their crates.
+pub static PLATFORMS: &[(&str, &[&str])] = &[
+    (
+        "windows",
+        &[
+            "windows-sys",
+            "windows",
+            "winapi",
+            "windows-targets",
+            "windows-core",
+            "windows-link",
+            "windows-result",
+            "windows-strings",
+            "windows_x86_64_msvc",
+            "windows_x86_64_gnu",
+            "windows_aarch64_msvc",
+            "windows_i686_msvc",
+            "winapi-x86_64-pc-windows-gnu",
+            "winapi-i686-pc-windows-gnu",
+            "winapi-util",
+            "windows-implement",
+            "windows-interface",
+            "windows-registry",
+            "winreg",
+            "schannel",
+            "ntapi",
+            "widestring",
+            "dunce",
+            "junction",
+            "windows-service",
+            "winres",
+            "embed-resource",
+            "wmi",
+        ],
+    ),
+    (
+        "unix",
+        &[
+            "libc",
+            "nix",
+            "rustix",
+            "errno",
+            "signal-hook",
+            "signal-hook-registry",
+            "mio",
+            "socket2",
+            "filetime",
+            "memmap2",
+            "libloading",
+            "xattr",
+            "fs2",
+            "termios",
+            "termion",
+            "daemonize",
+            "fork",
+            "uzers",
+            "pkg-config",
+            "openssl-sys",
+            "openssl",
+            "openssl-probe",
+        ],
+    ),
+    (
+        "linux",
+        &[
+            "libc",
+            "linux-raw-sys",
+            "rustix",
+            "nix",
+            "procfs",
+            "inotify",
+            "io-uring",
+            "memfd",
+            "caps",
+            "landlock",
+            "libseccomp",
+            "seccompiler",
+            "netlink-packet-core",
+            "rtnetlink",
+            "udev",
+            "evdev",
+            "zbus",
+            "dbus",
+            "alsa",
+        ],
+    ),
+    (
+        "macos",
+        &[
+            "core-foundation",
+            "core-foundation-sys",
+            "security-framework",
+            "security-framework-sys",
+            "system-configuration",
+            "objc2",
+            "objc2-foundation",
+            "block2",
+            "objc",
+            "block",
+            "cocoa",
+            "core-graphics",
+            "core-text",
+            "mach2",
+            "fsevent-sys",
+            "io-kit-sys",
+        ],
+    ),
+    (
+        "wasm",
+        &[
+            "wasm-bindgen",
+            "js-sys",
+            "web-sys",
+            "wasm-bindgen-futures",
+            "getrandom",
+            "console_error_panic_hook",
+            "serde-wasm-bindgen",
+            "web-time",
+            "instant",
+            "gloo",
+            "gloo-timers",
+            "wasm-bindgen-test",
+            "wee_alloc",
+        ],
+    ),
+];
+
+/// Returns the crates for a platform.
+pub fn crates(platform: &str) -> Option<&'static [&'static str]> {
+    PLATFORMS
+        .iter()
+        .find(|(name, _)| *name == platform)
+        .map(|(_, crates)| *crates)
+}
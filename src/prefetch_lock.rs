@@ -0,0 +1,63 @@
+//! `prefetch.lock` files, which record the exact set of packages fetched.
+//!
+//! Fetching with `--locked` downloads exactly the packages in the file,
+//! checking each against its recorded checksum, without resolving anything.
+
+use failure::{bail, Fallible, ResultExt};
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// The current version of the file format.
+const VERSION: u32 = 1;
+
+#[derive(Deserialize, Serialize)]
+struct PrefetchLock {
+    version: u32,
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+/// A package in a `prefetch.lock` file.
+#[derive(Clone, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: String,
+    pub checksum: String,
+}
+
+/// Write a `prefetch.lock` file.
+pub fn write(path: &Path, packages: &[LockedPackage]) -> Fallible<()> {
+    let mut package = packages.to_vec();
+    package.sort();
+    package.dedup();
+    let lock = PrefetchLock {
+        version: VERSION,
+        package,
+    };
+    let contents = format!(
+        "# This file is generated by cargo-prefetch.\n\
+         # Use `cargo prefetch --locked {}` to fetch the same packages.\n{}",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        toml::to_string(&lock)?
+    );
+    fs::write(path, contents).with_context(|_| format!("Failed to write `{}`.", path.display()))?;
+    Ok(())
+}
+
+/// Read a `prefetch.lock` file.
+pub fn read(path: &Path) -> Fallible<Vec<LockedPackage>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|_| format!("Failed to read `{}`.", path.display()))?;
+    let lock: PrefetchLock = toml::from_str(&contents)
+        .with_context(|_| format!("Failed to parse `{}`.", path.display()))?;
+    if lock.version != VERSION {
+        bail!(
+            "`{}` has an unsupported version {}, expected {}",
+            path.display(),
+            lock.version,
+            VERSION
+        );
+    }
+    Ok(lock.package)
+}
@@ -0,0 +1,156 @@
+//! Computing the most common dependencies from the registry index.
+
+use crate::cache;
+use crate::exit::NetworkError;
+use crate::http::Http;
+use crate::index;
+use crate::top;
+use failure::{bail, Fallible, ResultExt};
+use flate2::read::GzDecoder;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::Component;
+use std::time::{Duration, SystemTime};
+use tracing::{debug, info, info_span, warn};
+
+/// A snapshot of the crates.io index repository.
+const INDEX_TARBALL: &str =
+    "https://github.com/rust-lang/crates.io-index/archive/refs/heads/master.tar.gz";
+
+/// How long a refreshed ranking is used before computing it again.
+const REFRESH_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// The number of crates in a computed ranking.
+const RANKING_SIZE: usize = 1000;
+
+/// Add the dependencies of the newest version in an index file to the counts.
+pub fn count_deps(counts: &mut HashMap<String, u32>, contents: &str) {
+    let entries = index::parse_index_file(contents);
+    if let Some(entry) = index::newest(&entries) {
+        for dep in &entry.deps {
+            *counts.entry(dep.package_name().to_string()).or_insert(0) += 1;
+        }
+    }
+}
+
+/// Returns the `n` most common dependencies along with their counts.
+pub fn top_counts(counts: HashMap<String, u32>, n: usize) -> Vec<(u32, String)> {
+    let mut all: Vec<(u32, String)> = counts
+        .into_iter()
+        .map(|(name, count)| (count, name))
+        .collect();
+    all.sort_unstable();
+    all.reverse();
+    all.truncate(n);
+    all
+}
+
+/// Merge two rankings into one list of `n` crates.
+///
+/// Each crate scores points based on its position in each list, and the
+/// `weight` (between 0 and 1) is the share given to the first list.
+pub fn combine(first: &[String], second: &[String], weight: f64, n: usize) -> Vec<String> {
+    let mut scores: HashMap<&str, f64> = HashMap::new();
+    for (list, weight) in &[(first, weight), (second, 1.0 - weight)] {
+        let len = list.len() as f64;
+        for (rank, name) in list.iter().enumerate() {
+            *scores.entry(name).or_insert(0.0) += weight * (len - rank as f64);
+        }
+    }
+    let mut all: Vec<(&str, f64)> = scores.into_iter().collect();
+    all.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    all.into_iter()
+        .take(n)
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+/// Returns a current list of the most common dependencies.
+///
+/// The list is cached for a week. If it cannot be computed, this falls back
+/// to a stale cached list, and then to the list built into cargo-prefetch.
+pub fn refreshed_top_deps(http: &Http) -> Fallible<Vec<String>> {
+    let path = cache::prefetch_dir()?.join("top-deps.txt");
+    let cached = fs::read_to_string(&path)
+        .ok()
+        .map(|contents| contents.lines().map(String::from).collect::<Vec<_>>());
+    let fresh = fs::metadata(&path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age < REFRESH_TTL);
+    if let (true, Some(cached)) = (fresh, &cached) {
+        debug!("Using cached top dependencies from `{}`.", path.display());
+        return Ok(cached.clone());
+    }
+
+    match top_deps_from_index(http) {
+        Ok(names) => {
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(&path, names.join("\n"))
+                .with_context(|_| format!("Failed to write `{}`.", path.display()))?;
+            Ok(names)
+        }
+        Err(e) => {
+            warn!("failed to refresh the top dependencies: {}", e);
+            match cached {
+                Some(cached) => {
+                    warn!("using stale list from `{}`", path.display());
+                    Ok(cached)
+                }
+                None => {
+                    warn!("using the built-in list");
+                    Ok(top::TOP_CRATES.iter().map(|s| s.to_string()).collect())
+                }
+            }
+        }
+    }
+}
+
+/// Compute the most common dependencies from a snapshot of the index.
+fn top_deps_from_index(http: &Http) -> Fallible<Vec<String>> {
+    let _span = info_span!("download", url = INDEX_TARBALL).entered();
+    info!("Downloading a snapshot of the crates.io index...");
+    let response = http.get(INDEX_TARBALL)?;
+    if !response.status().is_success() {
+        return Err(NetworkError(format!(
+            "failed to download `{}`: {}",
+            INDEX_TARBALL,
+            response.status()
+        ))
+        .into());
+    }
+    let mut archive = tar::Archive::new(GzDecoder::new(response));
+    let mut counts = HashMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.into_owned();
+        // Skip the top-level directory, and things like `config.json` and
+        // `.github` that aren't packages.
+        let components: Vec<_> = path.components().skip(1).collect();
+        let is_package = components.len() > 1
+            && components.iter().all(|c| match c {
+                Component::Normal(s) => !s.to_string_lossy().starts_with('.'),
+                _ => false,
+            });
+        if !is_package {
+            continue;
+        }
+        let mut contents = String::new();
+        if entry.read_to_string(&mut contents).is_err() {
+            continue;
+        }
+        count_deps(&mut counts, &contents);
+    }
+    if counts.is_empty() {
+        bail!("no packages found in the index snapshot");
+    }
+    Ok(top_counts(counts, RANKING_SIZE)
+        .into_iter()
+        .map(|(_, name)| name)
+        .collect())
+}
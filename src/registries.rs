@@ -0,0 +1,128 @@
+//! Packages in lockfiles that come from registries other than crates.io,
+//! such as a company registry.
+//!
+//! A lockfile only has the index URL of each registry, so it is matched with
+//! the `[registries]` in Cargo's config to find the name Cargo knows it by.
+//! The packages are then fetched with a temp project that depends on them
+//! from that registry, so that Cargo downloads them into its cache for the
+//! registry, with whatever credentials it has for it.
+//!
+//! They are fetched after the crates.io packages, and only into Cargo's
+//! cache: they aren't part of the `--resume` state, the `--incremental`
+//! state, the outputs such as `--write-lock` and `--report`, or the
+//! post-fetch hook.
+
+use crate::config::Config;
+use crate::exit::{self, CargoError};
+use crate::message::Event;
+use crate::{cargo_fetch, mktemp, partition, CrateSet, Options, Package, TEMP_PROJ_NAME};
+use failure::Fallible;
+use std::collections::BTreeMap;
+use std::fs;
+use tracing::{info, warn};
+
+/// Fetch packages from other registries, and return how many were fetched.
+///
+/// Packages from registries that aren't in Cargo's config are skipped with
+/// a warning, since there is no way to know how to access them.
+pub fn fetch(options: &Options, config: &Config, pkgs: &[Package]) -> Fallible<usize> {
+    let mut by_registry: BTreeMap<&str, Vec<&Package>> = BTreeMap::new();
+    let mut unknown: BTreeMap<&str, Vec<&Package>> = BTreeMap::new();
+    for pkg in pkgs {
+        let source = pkg.source.as_deref().unwrap_or_default();
+        match config.registry_name(source) {
+            Some(name) => by_registry.entry(name).or_default().push(pkg),
+            None => unknown.entry(source).or_default().push(pkg),
+        }
+    }
+    for (source, pkgs) in &unknown {
+        warn!(
+            "skipping {} packages from `{}`, which isn't in the `[registries]` of \
+             Cargo's config; add it there to fetch them",
+            pkgs.len(),
+            source
+        );
+        for pkg in pkgs {
+            options.messages.emit_with_reason(
+                Event::Skipped,
+                &pkg.name,
+                &pkg.version,
+                Some("its registry isn't configured"),
+            );
+        }
+        exit::mark_incomplete();
+    }
+    let mut fetched = 0;
+    for (registry, pkgs) in by_registry {
+        info!(
+            "Fetching {} packages from registry `{}`",
+            pkgs.len(),
+            registry
+        );
+        let crates: CrateSet = pkgs
+            .iter()
+            .map(|pkg| (pkg.name.clone(), Some(pkg.version.clone())))
+            .collect();
+        for set in partition(&crates) {
+            let pkgs: Vec<&Package> = pkgs
+                .iter()
+                .copied()
+                .filter(|pkg| set.contains(&(pkg.name.clone(), Some(pkg.version.clone()))))
+                .collect();
+            fetch_set(options, registry, &pkgs)?;
+            fetched += pkgs.len();
+        }
+    }
+    Ok(fetched)
+}
+
+/// Fetch packages from a registry with one temp project, which can only have
+/// one version of each package.
+fn fetch_set(options: &Options, registry: &str, pkgs: &[&Package]) -> Fallible<()> {
+    let dir = mktemp(options)?;
+    let deps: String = pkgs
+        .iter()
+        .map(|pkg| {
+            format!(
+                "\"{}\" = {{ version = \"={}\", registry = \"{}\" }}\n",
+                pkg.name, pkg.version, registry
+            )
+        })
+        .collect();
+    fs::write(
+        dir.path().join("Cargo.toml"),
+        format!(
+            "[package]\nname = \"{}\"\nversion = \"0.0.0\"\nedition = \"2018\"\n\n\
+             [dependencies]\n{}",
+            TEMP_PROJ_NAME, deps
+        ),
+    )?;
+    fs::create_dir_all(dir.path().join("src"))?;
+    fs::write(dir.path().join("src").join("lib.rs"), "")?;
+    for pkg in pkgs {
+        options
+            .messages
+            .emit(Event::Downloading, &pkg.name, &pkg.version);
+    }
+    let status = cargo_fetch(options, dir.path())?;
+    if !status.success() {
+        let reason = format!("`cargo fetch` failed: {}", status);
+        for pkg in pkgs {
+            options.messages.emit_with_reason(
+                Event::Failed,
+                &pkg.name,
+                &pkg.version,
+                Some(&reason),
+            );
+        }
+        return Err(CargoError(format!(
+            "`cargo` failed to fetch from registry `{}`: {}",
+            registry, status
+        ))
+        .into());
+    }
+    for pkg in pkgs {
+        options.messages.emit(Event::Done, &pkg.name, &pkg.version);
+    }
+    Ok(())
+}
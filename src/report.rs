@@ -0,0 +1,106 @@
+//! `--report` files, with a machine-readable summary of a run.
+//!
+//! The report is built from the progress messages recorded during the run,
+//! so it covers the same packages as `--message-format json`.
+
+use crate::cache;
+use crate::message::{Event, Message};
+use crate::prefetch_lock::LockedPackage;
+use crate::sources::Provenance;
+use failure::{Fallible, ResultExt};
+use serde_derive::Serialize;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize)]
+struct Report<'a> {
+    /// When the run started, in seconds since the Unix epoch.
+    started: u64,
+    /// How long the run took, in seconds.
+    duration: f64,
+    /// The crates that were asked for, as `name` or `name@requirement`.
+    requested: &'a [String],
+    /// The packages that were resolved.
+    packages: Vec<Package<'a>>,
+    /// The total size of the packages that were downloaded.
+    bytes_downloaded: u64,
+    skipped: Vec<Skipped<'a>>,
+}
+
+#[derive(Serialize)]
+struct Package<'a> {
+    name: &'a str,
+    version: &'a str,
+    checksum: &'a str,
+    /// Whether it was downloaded by this run, instead of already cached.
+    downloaded: bool,
+    /// The size of the `.crate` file.
+    size: Option<u64>,
+    /// The sources that asked for it, such as `top-deps`, which is empty if
+    /// it is only a dependency.
+    sources: Vec<&'a str>,
+}
+
+#[derive(Serialize)]
+struct Skipped<'a> {
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<&'a str>,
+    reason: &'a str,
+}
+
+/// Write a report of a run.
+pub fn write(
+    path: &Path,
+    started: SystemTime,
+    requested: &[String],
+    fetched: &[LockedPackage],
+    messages: &[Message],
+    provenance: &Provenance,
+) -> Fallible<()> {
+    let cache_dir = cache::crates_io_cache_dir()?;
+    let packages: Vec<Package<'_>> = fetched
+        .iter()
+        .map(|pkg| Package {
+            name: &pkg.name,
+            version: &pkg.version,
+            checksum: &pkg.checksum,
+            downloaded: messages.iter().any(|m| {
+                m.event == Event::Done
+                    && m.name == pkg.name
+                    && m.version.as_deref() == Some(&pkg.version[..])
+            }),
+            size: fs::metadata(cache_dir.join(format!("{}-{}.crate", pkg.name, pkg.version)))
+                .ok()
+                .map(|m| m.len()),
+            sources: provenance.sources(&pkg.name),
+        })
+        .collect();
+    let bytes_downloaded = packages
+        .iter()
+        .filter(|pkg| pkg.downloaded)
+        .filter_map(|pkg| pkg.size)
+        .sum();
+    // Packages skipped because they are cached are listed as packages.
+    let skipped = messages
+        .iter()
+        .filter(|m| m.event == Event::Failed || (m.event == Event::Skipped && m.version.is_none()))
+        .map(|m| Skipped {
+            name: &m.name,
+            version: m.version.as_deref(),
+            reason: m.reason.as_deref().unwrap_or(""),
+        })
+        .collect();
+    let report = Report {
+        started: started.duration_since(UNIX_EPOCH)?.as_secs(),
+        duration: started.elapsed().unwrap_or_default().as_secs_f64(),
+        requested,
+        packages,
+        bytes_downloaded,
+        skipped,
+    };
+    fs::write(path, serde_json::to_string_pretty(&report)? + "\n")
+        .with_context(|_| format!("Failed to write `{}`.", path.display()))?;
+    Ok(())
+}
@@ -0,0 +1,349 @@
+//! Resolution without Cargo, for `--no-cargo`.
+//!
+//! Each requirement selects the newest non-yanked version in the sparse
+//! index that matches it, and then the dependencies of that version are
+//! selected the same way. With a Rust version, versions that support it are
+//! preferred, like Cargo's `fallback` setting. Features are followed so that
+//! optional dependencies are included when something enables them.
+//! Dev-dependencies are skipped, and dependencies for every target are
+//! included, like `cargo fetch` does.
+//!
+//! Unlike Cargo, this doesn't backtrack, and doesn't unify semver-compatible
+//! requirements, so it may select a newer version than Cargo would, or
+//! several versions where Cargo would select one.
+
+use crate::http::Http;
+use crate::index::{self, IndexDep, IndexEntry, CRATES_IO_SPARSE};
+use crate::timings;
+use crate::{CrateSet, Package};
+use failure::{format_err, Fallible};
+use semver::{Version, VersionReq};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use tracing::{debug, info_span};
+
+/// A request to enable features on a version of a package.
+struct Request {
+    name: String,
+    req: VersionReq,
+    features: Vec<String>,
+    default_features: bool,
+    /// The package that asked for this, for errors.
+    parent: Option<String>,
+}
+
+#[derive(Default)]
+struct Node {
+    features: BTreeSet<String>,
+    /// The optional dependencies that are enabled, by the name used in the
+    /// manifest.
+    enabled_deps: BTreeSet<String>,
+    /// Features to enable on dependencies, by the name used in the manifest.
+    dep_features: BTreeMap<String, BTreeSet<String>>,
+    dependencies: BTreeSet<String>,
+}
+
+/// Resolve the crates using the index, and return every selected package.
+pub fn resolve(
+    http: &Http,
+    crates: &CrateSet,
+    rust_version: Option<&str>,
+) -> Fallible<Vec<Package>> {
+    let _span = info_span!("resolve").entered();
+    let _timer = timings::start("resolve", format!("{} crates without Cargo", crates.len()));
+    resolve_with(crates, rust_version, |name| index::entries(http, name))
+}
+
+/// Resolve the crates with the index entries returned by `lookup`.
+fn resolve_with(
+    crates: &CrateSet,
+    rust_version: Option<&str>,
+    mut lookup: impl FnMut(&str) -> Fallible<Vec<IndexEntry>>,
+) -> Fallible<Vec<Package>> {
+    let mut entries: HashMap<String, Vec<IndexEntry>> = HashMap::new();
+    let mut nodes: BTreeMap<(String, Version), Node> = BTreeMap::new();
+    let mut queue: Vec<Request> = Vec::new();
+    let rust_version = rust_version.and_then(parse_rust_version);
+    let supported = |entry: &IndexEntry| match (&rust_version, &entry.rust_version) {
+        (Some(rust_version), Some(required)) => {
+            parse_rust_version(required).is_none_or(|required| required <= *rust_version)
+        }
+        _ => true,
+    };
+    for (name, version) in crates {
+        let req = match version {
+            Some(version) => VersionReq::parse(version).map_err(|e| {
+                format_err!("invalid requirement `{}` for {}: {}", version, name, e)
+            })?,
+            None => VersionReq::STAR,
+        };
+        queue.push(Request {
+            name: name.clone(),
+            req,
+            features: Vec::new(),
+            default_features: true,
+            parent: None,
+        });
+    }
+    while let Some(request) = queue.pop() {
+        if !entries.contains_key(&request.name) {
+            debug!("Fetching index entries for {}", request.name);
+            entries.insert(request.name.clone(), lookup(&request.name)?);
+        }
+        let entry = entries[&request.name]
+            .iter()
+            .filter(|entry| !entry.yanked)
+            .filter_map(|entry| Version::parse(&entry.vers).ok().map(|v| (v, entry)))
+            .filter(|(v, _)| request.req.matches(v))
+            .max_by_key(|(v, entry)| (supported(entry), v.clone()))
+            .map(|(_, entry)| entry)
+            .ok_or_else(|| match &request.parent {
+                Some(parent) => format_err!(
+                    "no version of `{}` matches `{}`, required by {}",
+                    request.name,
+                    request.req,
+                    parent
+                ),
+                None => format_err!("no version of `{}` matches `{}`", request.name, request.req),
+            })?;
+        let version = Version::parse(&entry.vers)?;
+        let node = nodes
+            .entry((request.name.clone(), version.clone()))
+            .or_default();
+        let is_new = node.dependencies.is_empty() && node.features.is_empty();
+        let mut features = request.features.clone();
+        if request.default_features {
+            features.push("default".to_string());
+        }
+        let mut changed = is_new;
+        for feature in features {
+            changed |= activate(entry, node, &feature);
+        }
+        if !changed {
+            continue;
+        }
+        let parent = format!("{}@{}", request.name, version);
+        for dep in &entry.deps {
+            if dep.kind.as_deref() == Some("dev") || dep.registry.is_some() {
+                continue;
+            }
+            if dep.optional && !node.enabled_deps.contains(&dep.name) {
+                continue;
+            }
+            node.dependencies.insert(dep.package_name().to_string());
+            queue.push(dep_request(dep, node, &parent)?);
+        }
+    }
+
+    debug!("Resolved {} packages without Cargo", nodes.len());
+    Ok(nodes
+        .into_iter()
+        .map(|((name, version), node)| {
+            let version = version.to_string();
+            let checksum = entries[&name]
+                .iter()
+                .find(|entry| entry.vers == version)
+                .map(|entry| entry.cksum.clone());
+            Package {
+                name,
+                version,
+                source: Some(format!("sparse+{}", CRATES_IO_SPARSE)),
+                checksum,
+                dependencies: node.dependencies.into_iter().collect(),
+            }
+        })
+        .collect())
+}
+
+/// Parse a Rust version such as `1.70` or `1.70.0`.
+fn parse_rust_version(value: &str) -> Option<Version> {
+    match value.matches('.').count() {
+        1 => Version::parse(&format!("{}.0", value)).ok(),
+        _ => Version::parse(value).ok(),
+    }
+}
+
+fn dep_request(dep: &IndexDep, node: &Node, parent: &str) -> Fallible<Request> {
+    let req = VersionReq::parse(&dep.req).map_err(|e| {
+        format_err!(
+            "invalid requirement `{}` for {} in {}: {}",
+            dep.req,
+            dep.name,
+            parent,
+            e
+        )
+    })?;
+    let mut features = dep.features.clone();
+    features.extend(
+        node.dep_features
+            .get(&dep.name)
+            .into_iter()
+            .flatten()
+            .cloned(),
+    );
+    Ok(Request {
+        name: dep.package_name().to_string(),
+        req,
+        features,
+        default_features: dep.default_features,
+        parent: Some(parent.to_string()),
+    })
+}
+
+/// Enable a feature, and everything it enables. Returns whether anything
+/// changed.
+fn activate(entry: &IndexEntry, node: &mut Node, feature: &str) -> bool {
+    if !node.features.insert(feature.to_string()) {
+        return false;
+    }
+    let mut values = entry
+        .features
+        .get(feature)
+        .into_iter()
+        .chain(entry.features2.get(feature))
+        .flatten()
+        .peekable();
+    if values.peek().is_none() {
+        // Optional dependencies have an implicit feature of the same name,
+        // unless something refers to them with `dep:`.
+        let explicit = format!("dep:{}", feature);
+        let implicit = entry
+            .deps
+            .iter()
+            .any(|dep| dep.optional && dep.name == feature)
+            && !entry
+                .features
+                .values()
+                .chain(entry.features2.values())
+                .flatten()
+                .any(|value| *value == explicit);
+        if implicit {
+            node.enabled_deps.insert(feature.to_string());
+        }
+        return true;
+    }
+    for value in values {
+        if let Some(dep) = value.strip_prefix("dep:") {
+            node.enabled_deps.insert(dep.to_string());
+        } else if let Some((dep, dep_feature)) = value.split_once('/') {
+            // Cargo's resolver also includes dependencies that are only
+            // enabled weakly with `dep?/feature` in the lockfile.
+            let dep = dep.trim_end_matches('?');
+            if entry.deps.iter().any(|d| d.optional && d.name == dep) {
+                node.enabled_deps.insert(dep.to_string());
+            }
+            node.dep_features
+                .entry(dep.to_string())
+                .or_default()
+                .insert(dep_feature.to_string());
+        } else {
+            activate(entry, node, value);
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// The index entries of each package, as JSON.
+    fn index() -> HashMap<&'static str, serde_json::Value> {
+        let mut index = HashMap::new();
+        index.insert(
+            "app",
+            json!([{
+                "vers": "1.0.0",
+                "deps": [
+                    {"name": "log", "req": "^0.4"},
+                    {"name": "json", "package": "serde_json", "req": "^1", "optional": true},
+                    {"name": "rand", "req": "^0.8", "optional": true},
+                    {"name": "hidden", "req": "^1", "optional": true},
+                    {"name": "tempfile", "req": "^3", "kind": "dev"},
+                ],
+                "features": {"default": ["json"], "random": ["rand/std"]},
+                "features2": {"quiet": ["dep:hidden"]},
+            }]),
+        );
+        index.insert(
+            "log",
+            json!([
+                {"vers": "0.4.20"},
+                {"vers": "0.4.21", "yanked": true},
+                {"vers": "0.5.0", "rust_version": "1.80"},
+            ]),
+        );
+        index.insert("serde_json", json!([{"vers": "1.0.100"}]));
+        index.insert("rand", json!([{"vers": "0.8.5"}]));
+        index.insert("hidden", json!([{"vers": "1.0.0"}]));
+        index
+    }
+
+    fn resolved(
+        crates: &[(&str, Option<&str>)],
+        rust_version: Option<&str>,
+    ) -> Fallible<Vec<String>> {
+        let index = index();
+        let crates: CrateSet = crates
+            .iter()
+            .map(|(name, version)| (name.to_string(), version.map(String::from)))
+            .collect();
+        let pkgs = resolve_with(&crates, rust_version, |name| {
+            let entries = index
+                .get(name)
+                .ok_or_else(|| format_err!("no crate `{}`", name))?;
+            Ok(serde_json::from_value(entries.clone())?)
+        })?;
+        Ok(pkgs
+            .into_iter()
+            .map(|pkg| format!("{}@{}", pkg.name, pkg.version))
+            .collect())
+    }
+
+    #[test]
+    fn resolve_features() {
+        // The newest non-yanked compatible `log`, the renamed dependency
+        // enabled by default, and no dev-dependencies or unused optional
+        // dependencies.
+        assert_eq!(
+            resolved(&[("app", None)], None).unwrap(),
+            ["app@1.0.0", "log@0.4.20", "serde_json@1.0.100"]
+        );
+        assert_eq!(resolved(&[("log", None)], None).unwrap(), ["log@0.5.0"]);
+        // Versions that support the Rust version are preferred, if there
+        // are any.
+        assert_eq!(
+            resolved(&[("log", None)], Some("1.70")).unwrap(),
+            ["log@0.4.20"]
+        );
+        assert_eq!(
+            resolved(&[("log", Some("^0.5"))], Some("1.70")).unwrap(),
+            ["log@0.5.0"]
+        );
+        assert_eq!(
+            resolved(&[("log", Some("=0.4.20"))], None).unwrap(),
+            ["log@0.4.20"]
+        );
+        assert!(resolved(&[("log", Some("^0.3"))], None).is_err());
+    }
+
+    #[test]
+    fn activate_features() {
+        let entries: Vec<IndexEntry> = serde_json::from_value(index()["app"].clone()).unwrap();
+        let entry = &entries[0];
+        let enabled = |features: &[&str]| {
+            let mut node = Node::default();
+            for feature in features {
+                activate(entry, &mut node, feature);
+            }
+            node.enabled_deps.into_iter().collect::<Vec<_>>()
+        };
+        assert_eq!(enabled(&["default"]), ["json"]);
+        // `rand/std` enables the optional `rand`.
+        assert_eq!(enabled(&["random"]), ["rand"]);
+        assert_eq!(enabled(&["quiet"]), ["hidden"]);
+        // `dep:hidden` removes the implicit `hidden` feature.
+        assert!(enabled(&["hidden"]).is_empty());
+        assert_eq!(enabled(&["rand"]), ["rand"]);
+    }
+}
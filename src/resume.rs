@@ -0,0 +1,106 @@
+//! Progress of the last fetch that didn't finish, for `--resume`.
+//!
+//! The crates of a fetch are saved when it starts, and each temp project's
+//! crates are marked as completed once they are downloaded. The file is
+//! removed when the fetch finishes, so it only exists after a fetch was
+//! interrupted or failed. Packages that were downloaded before then are in
+//! the cache already, so resuming mostly saves resolving the completed temp
+//! projects again, and keeps the same crates even if a ranking changed.
+
+use crate::cache;
+use crate::CrateSet;
+use failure::{Fallible, ResultExt};
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use tracing::{debug, warn};
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct State {
+    /// Every crate of the fetch.
+    pub crates: CrateSet,
+    /// The crates that were downloaded.
+    pub completed: CrateSet,
+}
+
+impl State {
+    /// The crates that are left to fetch.
+    pub fn remaining(&self) -> CrateSet {
+        self.crates.difference(&self.completed).cloned().collect()
+    }
+}
+
+fn path() -> Fallible<PathBuf> {
+    Ok(cache::prefetch_dir()?.join("resume.json"))
+}
+
+/// Load the state of the last fetch, if it didn't finish.
+pub fn load() -> Fallible<Option<State>> {
+    let path = path()?;
+    if !path.exists() {
+        debug!("no resume state at `{}`", path.display());
+        return Ok(None);
+    }
+    let contents = fs::read_to_string(&path)
+        .with_context(|_| format!("Failed to read `{}`.", path.display()))?;
+    match serde_json::from_str(&contents) {
+        Ok(state) => Ok(Some(state)),
+        Err(e) => {
+            warn!("ignoring invalid resume state `{}`: {}", path.display(), e);
+            Ok(None)
+        }
+    }
+}
+
+pub fn save(state: &State) -> Fallible<()> {
+    let path = path()?;
+    fs::create_dir_all(path.parent().unwrap())?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string(state)?)
+        .with_context(|_| format!("Failed to write `{}`.", tmp.display()))?;
+    fs::rename(&tmp, &path).with_context(|_| format!("Failed to write `{}`.", path.display()))?;
+    Ok(())
+}
+
+/// Remove the state, once a fetch finishes.
+pub fn remove() -> Fallible<()> {
+    let path = path()?;
+    if path.exists() {
+        fs::remove_file(&path)
+            .with_context(|_| format!("Failed to remove `{}`.", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crates(names: &[&str]) -> CrateSet {
+        names.iter().map(|name| (name.to_string(), None)).collect()
+    }
+
+    #[test]
+    fn resume() {
+        cache::test_home();
+        remove().unwrap();
+        assert!(load().unwrap().is_none());
+        let mut state = State {
+            crates: crates(&["a", "b", "c"]),
+            completed: CrateSet::new(),
+        };
+        save(&state).unwrap();
+        state.completed.extend(crates(&["a", "c"]));
+        save(&state).unwrap();
+        assert!(!path().unwrap().with_extension("json.tmp").exists());
+        let loaded = load().unwrap().unwrap();
+        assert_eq!(loaded.crates, crates(&["a", "b", "c"]));
+        assert_eq!(loaded.remaining(), crates(&["b"]));
+        // A fetch that finished leaves nothing to resume.
+        remove().unwrap();
+        assert!(load().unwrap().is_none());
+        fs::write(path().unwrap(), "not json").unwrap();
+        assert!(load().unwrap().is_none());
+        remove().unwrap();
+    }
+}
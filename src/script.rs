@@ -0,0 +1,153 @@
+//! `--emit-script`, a shell script that downloads the selected packages on
+//! another machine.
+//!
+//! The script puts each `.crate` file where Cargo looks for it, under
+//! `$CARGO_HOME/registry/cache`, and checks it against the checksum from the
+//! index before moving it into place. Files that are already there are
+//! skipped, so the script can be run again after a failure.
+//!
+//! Cargo also needs the index entries of the packages to use them offline.
+//! Those are copied from this machine's index cache into the script, encoded
+//! with base64 since they are binary.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use cargo_prefetch::cache;
+use cargo_prefetch::download;
+use cargo_prefetch::http::Http;
+use cargo_prefetch::index;
+use cargo_prefetch::Package;
+use failure::{format_err, Fallible, ResultExt};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use tracing::warn;
+
+/// Returns a script that downloads `pkgs` with `tool`, which is `curl`,
+/// `wget`, or `aria2`.
+pub fn emit(http: &Http, tool: &str, pkgs: &[Package]) -> Fallible<String> {
+    let cache_dir = cache::crates_io_cache_dir()?;
+    let registry = cache_dir.file_name().unwrap().to_string_lossy();
+    let mut out = String::new();
+    out.push_str("#!/bin/sh\n");
+    let _ = writeln!(
+        out,
+        "# Downloads the packages selected by cargo-prefetch into Cargo's cache ({} files).",
+        pkgs.len()
+    );
+    out.push_str("set -eu\n");
+    let _ = writeln!(
+        out,
+        "dir=\"${{CARGO_HOME:-$HOME/.cargo}}/registry/cache/{}\"",
+        registry
+    );
+    out.push_str("mkdir -p \"$dir\"\n");
+    write_index(&mut out, &registry, pkgs)?;
+    let mut files = Vec::new();
+    for pkg in pkgs {
+        let cksum = pkg
+            .checksum
+            .as_deref()
+            .ok_or_else(|| format_err!("package `{}@{}` has no checksum", pkg.name, pkg.version))?;
+        let url = download::download_url(http, &pkg.name, &pkg.version, cksum)?;
+        let file = format!("{}-{}.crate", pkg.name, pkg.version);
+        files.push((url, file, cksum));
+    }
+    if tool == "aria2" {
+        // aria2 checks the checksums itself, and downloads several files at
+        // once.
+        out.push_str("aria2c --dir \"$dir\" --auto-file-renaming=false \\\n");
+        out.push_str("    --check-integrity=true --input-file - <<'EOF'\n");
+        for (url, file, cksum) in &files {
+            let _ = writeln!(out, "{}\n  out={}\n  checksum=sha-256={}", url, file, cksum);
+        }
+        out.push_str("EOF\n");
+        return Ok(out);
+    }
+    let get = match tool {
+        "wget" => "wget --quiet --tries=3 -O \"$tmp\" \"$1\"",
+        _ => "curl --fail --silent --show-error --location --retry 3 -o \"$tmp\" \"$1\"",
+    };
+    // macOS has `shasum` but not `sha256sum`.
+    out.push_str("if command -v sha256sum >/dev/null 2>&1; then\n");
+    out.push_str("    sha256() { sha256sum \"$1\" | cut -d ' ' -f 1; }\n");
+    out.push_str("else\n");
+    out.push_str("    sha256() { shasum -a 256 \"$1\" | cut -d ' ' -f 1; }\n");
+    out.push_str("fi\n");
+    out.push_str("fetch() {\n");
+    out.push_str("    [ -f \"$dir/$2\" ] && return\n");
+    out.push_str("    tmp=\"$dir/$2.part\"\n");
+    let _ = writeln!(out, "    {}", get);
+    out.push_str("    if [ \"$(sha256 \"$tmp\")\" != \"$3\" ]; then\n");
+    out.push_str("        echo \"checksum mismatch for $1\" >&2\n");
+    out.push_str("        rm -f \"$tmp\"\n");
+    out.push_str("        exit 1\n");
+    out.push_str("    fi\n");
+    out.push_str("    mv \"$tmp\" \"$dir/$2\"\n");
+    out.push_str("}\n");
+    for (url, file, cksum) in &files {
+        let _ = writeln!(out, "fetch '{}' {} {}", url, file, cksum);
+    }
+    Ok(out)
+}
+
+/// Add commands that write the index's `config.json` and the index cache
+/// file of each package.
+fn write_index(out: &mut String, registry: &str, pkgs: &[Package]) -> Fallible<()> {
+    let index_dir = cache::cargo_home()?
+        .join("registry")
+        .join("index")
+        .join(registry);
+    let _ = writeln!(
+        out,
+        "index=\"${{CARGO_HOME:-$HOME/.cargo}}/registry/index/{}\"",
+        registry
+    );
+    // Older versions of macOS only have `base64 -D`.
+    out.push_str("if base64 -d </dev/null >/dev/null 2>&1; then\n");
+    out.push_str("    decode() { base64 -d; }\n");
+    out.push_str("else\n");
+    out.push_str("    decode() { base64 -D; }\n");
+    out.push_str("fi\n");
+    out.push_str("index_file() {\n");
+    out.push_str("    mkdir -p \"$(dirname \"$index/$1\")\"\n");
+    out.push_str("    decode > \"$index/$1\"\n");
+    out.push_str("}\n");
+    let config = index_dir.join("config.json");
+    if config.exists() {
+        write_index_file(out, &index_dir, Path::new("config.json"))?;
+    }
+    let names: BTreeSet<&str> = pkgs.iter().map(|pkg| &pkg.name[..]).collect();
+    let mut missing = 0;
+    for name in names {
+        let path = Path::new(".cache").join(index::index_path(name));
+        if index_dir.join(&path).exists() {
+            write_index_file(out, &index_dir, &path)?;
+        } else {
+            missing += 1;
+        }
+    }
+    if missing > 0 {
+        warn!(
+            "{} packages have no index entry in Cargo's cache, so the script \
+             doesn't include them, and Cargo can only use them after updating \
+             the index",
+            missing
+        );
+    }
+    Ok(())
+}
+
+fn write_index_file(out: &mut String, index_dir: &Path, path: &Path) -> Fallible<()> {
+    let contents = fs::read(index_dir.join(path))
+        .with_context(|_| format!("Failed to read `{}`.", index_dir.join(path).display()))?;
+    let _ = writeln!(out, "index_file '{}' <<'EOF'", path.display());
+    let encoded = BASE64.encode(contents);
+    for line in encoded.as_bytes().chunks(76) {
+        out.push_str(std::str::from_utf8(line).unwrap());
+        out.push('\n');
+    }
+    out.push_str("EOF\n");
+    Ok(())
+}
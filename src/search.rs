@@ -0,0 +1,95 @@
+//! `--search`, for finding crates on crates.io and adding them to the fetch.
+//!
+//! Each query is sent to the crates.io search, which matches names and
+//! descriptions loosely. Results are numbered so they can be added by
+//! typing their numbers.
+
+use cargo_prefetch::crates_io::{self, CrateDetails};
+use cargo_prefetch::http::Http;
+use failure::Fallible;
+use std::io::{self, BufRead, Write};
+use tracing::{error, warn};
+
+/// The number of results shown for each query.
+const RESULTS: usize = 10;
+
+/// Prompt for searches until an empty query, and return the crates that
+/// were added.
+pub fn prompt(http: &Http) -> Fallible<Vec<String>> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut added: Vec<String> = Vec::new();
+    eprintln!("Search crates.io, and type the numbers of results to add them.");
+    eprintln!("Enter an empty search when done.");
+    loop {
+        let query = match read_line(&mut lines, "search> ")? {
+            Some(query) if !query.is_empty() => query,
+            _ => break,
+        };
+        let results = match crates_io::search(http, &query, RESULTS) {
+            Ok(results) => results,
+            Err(e) => {
+                error!("{}", e);
+                continue;
+            }
+        };
+        if results.is_empty() {
+            eprintln!("No crates found.");
+            continue;
+        }
+        for (i, krate) in results.iter().enumerate() {
+            print_result(i + 1, krate, added.contains(&krate.name));
+        }
+        let choice = read_line(&mut lines, "add> ")?.unwrap_or_default();
+        for n in choice.split(|c: char| c == ',' || c.is_whitespace()) {
+            if n.is_empty() {
+                continue;
+            }
+            match n
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| results.get(n.wrapping_sub(1)))
+            {
+                Some(krate) if added.contains(&krate.name) => {}
+                Some(krate) => {
+                    eprintln!("Added {}", krate.name);
+                    added.push(krate.name.clone());
+                }
+                None => warn!("`{}` is not one of the results", n),
+            }
+        }
+    }
+    Ok(added)
+}
+
+fn print_result(n: usize, krate: &CrateDetails, added: bool) {
+    let description = krate
+        .description
+        .as_deref()
+        .map(|d| d.split_whitespace().collect::<Vec<_>>().join(" "))
+        .unwrap_or_default();
+    eprintln!(
+        "{:>3}. {}{} {} ({} downloads)",
+        n,
+        if added { "* " } else { "" },
+        krate.name,
+        krate.max_version.as_deref().unwrap_or(""),
+        krate.downloads
+    );
+    if !description.is_empty() {
+        eprintln!("     {}", description);
+    }
+}
+
+/// Print a prompt and read a line, returning `None` at the end of input.
+fn read_line(
+    lines: &mut impl Iterator<Item = io::Result<String>>,
+    prompt: &str,
+) -> Fallible<Option<String>> {
+    eprint!("{}", prompt);
+    io::stderr().flush()?;
+    match lines.next() {
+        Some(line) => Ok(Some(line?.trim().to_string())),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,206 @@
+//! Resolving against a fixed state of the crates.io index, for
+//! `--index-snapshot`.
+//!
+//! The sparse index only serves its current state, but the git index keeps
+//! its history, so a mirror of it is kept in the cargo-prefetch data
+//! directory. For each snapshot, a small repository is made whose `HEAD` is
+//! the snapshot's commit, sharing the mirror's objects, and Cargo is pointed
+//! at it by replacing the crates.io source. Lockfiles still refer to
+//! crates.io, so the selected packages are downloaded into the usual
+//! crates.io cache.
+//!
+//! The crates.io git index is squashed every so often, so the mirror only
+//! goes back to the last squash. Older history is in the
+//! `rust-lang/crates.io-index-archive` repository.
+
+use crate::cache;
+use failure::{bail, format_err, Fallible, ResultExt};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{debug, info, warn};
+
+/// The git index of crates.io.
+pub const CRATES_IO_GIT: &str = "https://github.com/rust-lang/crates.io-index";
+
+/// The name of the replacement source given to Cargo.
+const SOURCE_NAME: &str = "prefetch-snapshot";
+
+/// Prepare a snapshot of the index at `rev`, which is a commit or a date such
+/// as `2024-01-31`, and return the `--config` arguments that make Cargo
+/// resolve with it.
+pub fn cargo_args(rev: &str) -> Fallible<Vec<String>> {
+    let dir = cache::prefetch_dir()?.join("index-snapshot");
+    let mirror = mirror_path()?;
+    if !mirror.exists() {
+        info!(
+            "Cloning `{}` for --index-snapshot, this may take a while...",
+            CRATES_IO_GIT
+        );
+        fs::create_dir_all(&dir)?;
+        git(Command::new("git")
+            .args(["clone", "--bare", "--quiet", CRATES_IO_GIT])
+            .arg(&mirror))?;
+    }
+    let is_date = rev.len() == 10 && rev.as_bytes()[4] == b'-' && rev.as_bytes()[7] == b'-';
+    if is_date {
+        // Commits may have been added since the mirror was last updated, but
+        // the mirror is still useful for older dates without them.
+        if let Err(e) = fetch(&mirror) {
+            warn!(
+                "failed to update the index mirror, using it as it is: {}",
+                e
+            );
+        }
+    } else if resolve(&mirror, &format!("{}^{{commit}}", rev)).is_err() {
+        fetch(&mirror)?;
+    }
+    let commit = if is_date {
+        // The end of the day, so the snapshot includes everything published
+        // on that date.
+        let before = format!("--before={} 23:59:59 +0000", rev);
+        let output = git_output(
+            Command::new("git")
+                .arg("-C")
+                .arg(&mirror)
+                .args(["rev-list", "-1", &before, "HEAD"]),
+        )?;
+        if output.is_empty() {
+            bail!(
+                "the index history in `{}` doesn't go back to {}; older snapshots \
+                 are in https://github.com/rust-lang/crates.io-index-archive",
+                CRATES_IO_GIT,
+                rev
+            );
+        }
+        output
+    } else {
+        resolve(&mirror, &format!("{}^{{commit}}", rev)).map_err(|_| {
+            format_err!(
+                "commit `{}` is not in the index at `{}`",
+                rev,
+                CRATES_IO_GIT
+            )
+        })?
+    };
+    info!("Resolving with the index at commit {}", commit);
+    let snapshot = dir.join(&commit);
+    if !snapshot.exists() {
+        make_snapshot(&mirror, &snapshot, &commit)?;
+    }
+    let url = format!("file://{}", snapshot.display());
+    Ok(vec![
+        "--config".to_string(),
+        format!("source.crates-io.replace-with=\"{}\"", SOURCE_NAME),
+        "--config".to_string(),
+        format!("source.{}.registry=\"{}\"", SOURCE_NAME, url),
+    ])
+}
+
+/// Make a bare repository whose `HEAD` is `commit`, using the objects of the
+/// mirror.
+fn make_snapshot(mirror: &Path, snapshot: &Path, commit: &str) -> Fallible<()> {
+    debug!("Making snapshot `{}`", snapshot.display());
+    let tmp = snapshot.with_extension("tmp");
+    if tmp.exists() {
+        fs::remove_dir_all(&tmp)?;
+    }
+    git(Command::new("git")
+        .args(["init", "--bare", "--quiet"])
+        .arg(&tmp))?;
+    let objects = fs::canonicalize(mirror.join("objects"))?;
+    fs::write(
+        tmp.join("objects").join("info").join("alternates"),
+        format!("{}\n", objects.display()),
+    )?;
+    git(Command::new("git")
+        .arg("-C")
+        .arg(&tmp)
+        .args(["update-ref", "refs/heads/master", commit]))?;
+    git(Command::new("git").arg("-C").arg(&tmp).args([
+        "symbolic-ref",
+        "HEAD",
+        "refs/heads/master",
+    ]))?;
+    fs::rename(&tmp, snapshot)
+        .with_context(|_| format!("Failed to create `{}`.", snapshot.display()))?;
+    Ok(())
+}
+
+/// The path of the mirror of the git index.
+fn mirror_path() -> Fallible<PathBuf> {
+    Ok(cache::prefetch_dir()?
+        .join("index-snapshot")
+        .join("crates.io-index.git"))
+}
+
+/// Update the mirror, if `--index-snapshot` has made one, and return its
+/// path and newest commit.
+pub fn update_mirror() -> Fallible<Option<(PathBuf, String)>> {
+    let mirror = mirror_path()?;
+    if !mirror.exists() {
+        return Ok(None);
+    }
+    fetch(&mirror)?;
+    let commit = resolve(&mirror, "HEAD^{commit}")?;
+    Ok(Some((mirror, commit)))
+}
+
+/// Returns the names of the packages whose index files changed between two
+/// commits of the mirror, in lowercase.
+pub fn changed_packages(mirror: &Path, from: &str, to: &str) -> Fallible<HashSet<String>> {
+    let output = git_output(Command::new("git").arg("-C").arg(mirror).args([
+        "diff",
+        "--name-only",
+        "--no-renames",
+        from,
+        to,
+    ]))?;
+    Ok(output
+        .lines()
+        .filter(|path| path.contains('/'))
+        .filter_map(|path| path.rsplit('/').next())
+        .map(String::from)
+        .collect())
+}
+
+fn fetch(mirror: &Path) -> Fallible<()> {
+    info!("Updating the mirror of the git index");
+    git(Command::new("git").arg("-C").arg(mirror).args([
+        "fetch",
+        "--quiet",
+        "origin",
+        "+refs/heads/*:refs/heads/*",
+    ]))
+}
+
+fn resolve(mirror: &Path, rev: &str) -> Fallible<String> {
+    git_output(Command::new("git").arg("-C").arg(mirror).args([
+        "rev-parse",
+        "--verify",
+        "--quiet",
+        rev,
+    ]))
+}
+
+fn git(cmd: &mut Command) -> Fallible<()> {
+    git_output(cmd).map(|_| ())
+}
+
+/// Run git, and return its trimmed output.
+fn git_output(cmd: &mut Command) -> Fallible<String> {
+    debug!("Running: {:?}", cmd);
+    let output = cmd
+        .output()
+        .with_context(|_| "Failed to run `git`, is it installed?")?;
+    if !output.status.success() {
+        bail!(
+            "{:?} failed: {}\n{}",
+            cmd,
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
@@ -0,0 +1,198 @@
+//! Builders for common sets of crates to fetch.
+
+use crate::crates_io::{self, Sort};
+use crate::github::Repo;
+use crate::http::{self, Http};
+use crate::{is_crates_io, is_registry, load_lockfile, parse_lockfile, CrateSet, Package};
+use failure::{bail, Fallible, ResultExt};
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use tracing::{info, warn};
+
+/// Which sources added each crate to a set, by crate name, such as
+/// `top-deps` or `lockfile Cargo.lock`.
+#[derive(Default)]
+pub struct Provenance {
+    sources: HashMap<String, BTreeSet<String>>,
+}
+
+impl Provenance {
+    /// Add crates to the set, recording that they came from `source`.
+    pub fn add(
+        &mut self,
+        crates: &mut CrateSet,
+        source: &str,
+        new: impl IntoIterator<Item = (String, Option<String>)>,
+    ) {
+        for krate in new {
+            self.sources
+                .entry(krate.0.clone())
+                .or_default()
+                .insert(source.to_string());
+            crates.insert(krate);
+        }
+    }
+
+    /// The sources that added a crate, which is empty if it is only a
+    /// dependency of other crates.
+    pub fn sources(&self, name: &str) -> Vec<&str> {
+        self.sources
+            .get(name)
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+/// The `count` most common dependencies, from the list built into
+/// cargo-prefetch.
+pub fn top_deps(count: usize) -> CrateSet {
+    names(
+        crate::top::TOP_CRATES
+            .iter()
+            .take(count)
+            .map(|s| s.to_string()),
+    )
+}
+
+/// The `count` most downloaded crates, from the crates.io API.
+pub fn top_downloads(http: &Http, sort: Sort, count: usize) -> Fallible<CrateSet> {
+    Ok(names(crates_io::top_downloads(http, sort, count)?))
+}
+
+/// The crates.io packages in a `Cargo.lock` file, pinned to their exact
+/// versions.
+pub fn lockfile(path: &Path) -> Fallible<CrateSet> {
+    Ok(lockfile_packages(path)?
+        .into_iter()
+        .filter(|pkg| pkg.source.as_deref().is_some_and(is_crates_io))
+        .map(|pkg| (pkg.name, Some(format!("={}", pkg.version))))
+        .collect())
+}
+
+/// The packages from crates.io and other registries in a `Cargo.lock` file.
+/// Git and path dependencies are left out.
+pub fn lockfile_packages(path: &Path) -> Fallible<Vec<Package>> {
+    let mut pkgs =
+        load_lockfile(path).with_context(|_| format!("Failed to load `{}`.", path.display()))?;
+    pkgs.retain(|pkg| pkg.source.as_deref().is_some_and(is_registry));
+    Ok(pkgs)
+}
+
+/// The registry packages in a `Cargo.lock` file downloaded from a URL, like
+/// [`lockfile_packages`].
+pub fn lockfile_url_packages(http: &Http, url: &str) -> Fallible<Vec<Package>> {
+    let mut response = http.get(url)?;
+    http::check_status(url, &mut response)?;
+    let contents = response.text()?;
+    let mut pkgs =
+        parse_lockfile(&contents).with_context(|_| format!("Failed to load `{}`.", url))?;
+    pkgs.retain(|pkg| pkg.source.as_deref().is_some_and(is_registry));
+    Ok(pkgs)
+}
+
+/// The crates.io packages in a GitHub repository's `Cargo.lock`, pinned to
+/// their exact versions, or the dependencies in its `Cargo.toml` if it
+/// doesn't have a lockfile.
+///
+/// `spec` is a repository such as `org/name` or `org/name@ref`.
+pub fn repo(http: &Http, spec: &str) -> Fallible<CrateSet> {
+    let repo = Repo::parse(spec)?;
+    if let Some(contents) = repo.read_file(http, "Cargo.lock")? {
+        let mut pkgs = parse_lockfile(&contents)
+            .with_context(|_| format!("Failed to load `Cargo.lock` from `{}`.", spec))?;
+        pkgs.retain(|pkg| pkg.source.as_deref().is_some_and(is_crates_io));
+        return Ok(pkgs
+            .into_iter()
+            .map(|pkg| (pkg.name, Some(format!("={}", pkg.version))))
+            .collect());
+    }
+    info!("`{}` has no Cargo.lock, resolving its Cargo.toml", spec);
+    let contents = match repo.read_file(http, "Cargo.toml")? {
+        Some(contents) => contents,
+        None => bail!("`{}` does not have a Cargo.lock or Cargo.toml", spec),
+    };
+    let manifest: toml::Value = toml::from_str(&contents)
+        .with_context(|_| format!("Failed to parse `Cargo.toml` from `{}`.", spec))?;
+    let mut crates = manifest_deps(&manifest);
+    let members = manifest
+        .get("workspace")
+        .and_then(|workspace| workspace.get("members"))
+        .and_then(|members| members.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|member| member.as_str());
+    for member in members {
+        if member.contains('*') {
+            warn!("skipping workspace members `{}` in `{}`", member, spec);
+            continue;
+        }
+        let path = format!("{}/Cargo.toml", member.trim_end_matches('/'));
+        if let Some(contents) = repo.read_file(http, &path)? {
+            let manifest: toml::Value = toml::from_str(&contents)
+                .with_context(|_| format!("Failed to parse `{}` from `{}`.", path, spec))?;
+            crates.extend(manifest_deps(&manifest));
+        }
+    }
+    Ok(crates)
+}
+
+/// The crates.io dependencies in a manifest, with their version
+/// requirements.
+///
+/// Dependencies from paths, git, or other registries are skipped.
+pub fn manifest_deps(manifest: &toml::Value) -> CrateSet {
+    const KINDS: &[&str] = &["dependencies", "dev-dependencies", "build-dependencies"];
+    let mut tables: Vec<&toml::Value> = KINDS.iter().filter_map(|k| manifest.get(k)).collect();
+    if let Some(deps) = manifest
+        .get("workspace")
+        .and_then(|workspace| workspace.get("dependencies"))
+    {
+        tables.push(deps);
+    }
+    for target in manifest
+        .get("target")
+        .and_then(|target| target.as_table())
+        .into_iter()
+        .flat_map(|targets| targets.values())
+    {
+        tables.extend(KINDS.iter().filter_map(|k| target.get(k)));
+    }
+    let mut crates = CrateSet::new();
+    for (key, dep) in tables
+        .into_iter()
+        .filter_map(|table| table.as_table())
+        .flatten()
+    {
+        match dep {
+            toml::Value::String(req) => {
+                crates.insert((key.clone(), Some(req.clone())));
+            }
+            toml::Value::Table(dep) => {
+                if ["path", "git", "registry", "workspace"]
+                    .iter()
+                    .any(|k| dep.contains_key(*k))
+                {
+                    continue;
+                }
+                let name = dep.get("package").and_then(|p| p.as_str()).unwrap_or(key);
+                if let Some(req) = dep.get("version").and_then(|v| v.as_str()) {
+                    crates.insert((name.to_string(), Some(req.to_string())));
+                }
+            }
+            _ => {}
+        }
+    }
+    crates
+}
+
+/// Whether a `--lockfile` argument is a URL instead of a path.
+pub fn is_url(path: &str) -> bool {
+    path.starts_with("https://") || path.starts_with("http://")
+}
+
+/// A set of crate names, each using the newest version.
+pub fn names(names: impl IntoIterator<Item = String>) -> CrateSet {
+    names.into_iter().map(|name| (name, None)).collect()
+}
@@ -0,0 +1,50 @@
+//! The `status` subcommand for showing what is in the cache.
+
+use cargo_prefetch::cache::{self, CachedCrate};
+use cargo_prefetch::top;
+use failure::Fallible;
+use std::collections::HashSet;
+
+pub fn status() -> Fallible<()> {
+    let cache_dir = cache::cargo_home()?.join("registry").join("cache");
+    println!("Registry cache: {}", cache_dir.display());
+    let mut all = Vec::new();
+    for dir in cache::registry_cache_dirs()? {
+        let crates = cache::cached_crates(&dir)?;
+        println!(
+            "  {}: {}",
+            dir.file_name().unwrap().to_string_lossy(),
+            summary(&crates)
+        );
+        all.extend(crates);
+    }
+    println!("Total: {}", summary(&all));
+
+    let names: HashSet<&str> = all.iter().map(|c| c.name.as_str()).collect();
+    for n in &[100, 1000] {
+        let n = (*n).min(top::TOP_CRATES.len());
+        let covered = top::TOP_CRATES[..n]
+            .iter()
+            .filter(|name| names.contains(*name))
+            .count();
+        println!(
+            "Top {} dependencies: {} cached ({}%)",
+            n,
+            covered,
+            covered * 100 / n
+        );
+    }
+    Ok(())
+}
+
+/// Returns a description such as `120 versions of 80 crates, 12.0 MiB`.
+fn summary(crates: &[CachedCrate]) -> String {
+    let names: HashSet<&str> = crates.iter().map(|c| c.name.as_str()).collect();
+    let size: u64 = crates.iter().map(|c| c.size).sum();
+    format!(
+        "{} versions of {} crates, {}",
+        crates.len(),
+        names.len(),
+        cache::human_size(size)
+    )
+}
@@ -0,0 +1,71 @@
+//! Suggestions for crate names that don't exist, such as typos.
+
+use crate::crates_io;
+use crate::http::Http;
+use crate::index;
+use crate::top::TOP_CRATES;
+use tracing::debug;
+
+/// The most suggestions to show.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Returns existing crates with names similar to `name`, closest first.
+///
+/// Candidates are the common dependencies built into cargo-prefetch, the
+/// crates.io search (if it is reachable), and the name with `-` and `_`
+/// swapped.
+pub fn similar_names(http: &Http, name: &str) -> Vec<String> {
+    let max = (name.len() / 3).max(1);
+    let mut candidates: Vec<String> = TOP_CRATES.iter().map(|s| s.to_string()).collect();
+    match crates_io::search(http, name, 10) {
+        Ok(results) => candidates.extend(results.into_iter().map(|krate| krate.name)),
+        Err(e) => debug!("failed to search for `{}`: {}", name, e),
+    }
+    let mut found: Vec<(usize, String)> = candidates
+        .into_iter()
+        .filter_map(|candidate| {
+            let d = distance(&normalize(name), &normalize(&candidate));
+            (d <= max && candidate != name).then_some((d, candidate))
+        })
+        .collect();
+    for variant in [name.replace('-', "_"), name.replace('_', "-")] {
+        if variant != name && matches!(index::fetch_sparse(http, &variant), Ok(Some(_))) {
+            found.push((0, variant));
+        }
+    }
+    found.sort();
+    found.dedup_by(|a, b| a.1 == b.1);
+    // A name that only differs in case or separators is almost certainly the
+    // one that was meant.
+    if found.first().is_some_and(|(d, _)| *d == 0) {
+        found.retain(|(d, _)| *d == 0);
+    }
+    found
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, name)| name)
+        .collect()
+}
+
+/// Names that only differ in case or `-` and `_` are close.
+fn normalize(name: &str) -> String {
+    name.to_lowercase().replace('-', "_")
+}
+
+/// The Levenshtein distance between two strings.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let next = (row[j + 1] + 1)
+                .min(row[j] + 1)
+                .min(prev + usize::from(ca != cb));
+            prev = row[j + 1];
+            row[j + 1] = next;
+        }
+    }
+    row[b.len()]
+}
@@ -0,0 +1,107 @@
+//! Management of the temporary Cargo projects used for resolution.
+//!
+//! `TempDir` cleans up when dropped, which covers errors and panics (as long
+//! as they unwind). Signals would otherwise kill the process without running
+//! any destructors, so every live project is tracked here and removed by the
+//! signal handler.
+//!
+//! While fetching, the first Ctrl-C only asks the fetch to stop after the
+//! downloads in progress, so that its progress can be saved for `--resume`.
+//! A second one stops immediately.
+//!
+//! With `--keep-temp`, projects are left behind for inspecting how they were
+//! resolved.
+
+use crate::exit;
+use failure::{Fallible, ResultExt};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tempfile::TempDir;
+use tracing::{debug, info, warn};
+
+/// Paths of all temp projects that currently exist.
+static LIVE: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Whether a Ctrl-C only stops the fetch after the downloads in progress.
+static GRACEFUL: AtomicBool = AtomicBool::new(false);
+
+/// Whether Ctrl-C was pressed while fetching.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Install a handler to remove temp projects on Ctrl-C or termination.
+pub fn install_signal_handler() -> Fallible<()> {
+    ctrlc::set_handler(|| {
+        if GRACEFUL.load(Ordering::SeqCst) && !INTERRUPTED.swap(true, Ordering::SeqCst) {
+            warn!("interrupted, stopping after the downloads in progress (press Ctrl-C again to stop now)");
+            return;
+        }
+        if let Ok(live) = LIVE.lock() {
+            for path in live.iter() {
+                let _ = fs::remove_dir_all(path);
+            }
+        }
+        std::process::exit(exit::INTERRUPTED);
+    })
+    .with_context(|_| "Failed to install signal handler.")?;
+    Ok(())
+}
+
+/// Set whether a Ctrl-C lets the downloads in progress finish.
+pub fn set_graceful(graceful: bool) {
+    GRACEFUL.store(graceful, Ordering::SeqCst);
+}
+
+/// Returns whether Ctrl-C was pressed while fetching.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// A temporary directory which is removed when dropped, unless it is kept.
+pub struct TempProject {
+    /// `None` if the directory is kept.
+    dir: Option<TempDir>,
+    path: PathBuf,
+}
+
+impl TempProject {
+    /// Create a new temp directory, inside `parent` if given.
+    pub fn new(parent: Option<&Path>, keep: bool) -> Fallible<TempProject> {
+        let mut builder = tempfile::Builder::new();
+        builder.prefix("cargo-prefetch");
+        let dir = match parent {
+            Some(parent) => builder.tempdir_in(parent),
+            None => builder.tempdir(),
+        }
+        .with_context(|_| "Failed to create temp directory.")?;
+        let path = dir.path().to_path_buf();
+        if keep {
+            info!("Keeping temp project: {}", path.display());
+            return Ok(TempProject {
+                dir: None,
+                path: dir.keep(),
+            });
+        }
+        debug!("Using temp project: {}", path.display());
+        LIVE.lock().unwrap().push(path.clone());
+        Ok(TempProject {
+            dir: Some(dir),
+            path,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempProject {
+    fn drop(&mut self) {
+        if self.dir.is_some() {
+            if let Ok(mut live) = LIVE.lock() {
+                live.retain(|path| *path != self.path);
+            }
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! `--timings`, a breakdown of where the time of a run went.
+//!
+//! Steps are recorded whether or not `--timings` is given, since it is
+//! cheap. `cargo fetch` of each temp project is a `fetch` step, and direct
+//! downloads are `download` steps. With Cargo, updating the index happens
+//! while resolving each temp project, so it is part of the `resolve` time.
+//! With `--no-cargo`, the index requests made while resolving are counted in
+//! both.
+
+use failure::{Fallible, ResultExt};
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::warn;
+
+static STEPS: Mutex<Vec<Step>> = Mutex::new(Vec::new());
+
+/// A step of the run that was timed.
+#[derive(Clone, Serialize)]
+pub struct Step {
+    /// The kind of step, such as `resolve` or `download`.
+    pub category: &'static str,
+    /// What the step was for, such as a crate name or temp project.
+    pub label: String,
+    /// When the step started, in seconds since the start of the run.
+    pub start: f64,
+    /// How long the step took, in seconds.
+    pub duration: f64,
+}
+
+/// Records a step when dropped.
+pub struct Timer {
+    category: &'static str,
+    label: String,
+    start: Instant,
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let step = Step {
+            category: self.category,
+            label: std::mem::take(&mut self.label),
+            start: self.start.duration_since(run_start()).as_secs_f64(),
+            duration: self.start.elapsed().as_secs_f64(),
+        };
+        STEPS.lock().unwrap().push(step);
+    }
+}
+
+/// Start the clock for the run, which step start times are relative to.
+pub fn begin() {
+    run_start();
+}
+
+/// Start timing a step, which is recorded when the returned timer is
+/// dropped.
+pub fn start(category: &'static str, label: impl Into<String>) -> Timer {
+    run_start();
+    Timer {
+        category,
+        label: label.into(),
+        start: Instant::now(),
+    }
+}
+
+fn run_start() -> Instant {
+    static START: Mutex<Option<Instant>> = Mutex::new(None);
+    *START.lock().unwrap().get_or_insert_with(Instant::now)
+}
+
+#[derive(Serialize)]
+struct Category {
+    /// The total time of the steps, in seconds.
+    total: f64,
+    count: usize,
+}
+
+#[derive(Serialize)]
+struct Timings {
+    /// How long the run took, in seconds.
+    duration: f64,
+    categories: BTreeMap<&'static str, Category>,
+    steps: Vec<Step>,
+}
+
+fn timings() -> Timings {
+    let steps = STEPS.lock().unwrap().clone();
+    let mut categories: BTreeMap<&'static str, Category> = BTreeMap::new();
+    for step in &steps {
+        let category = categories.entry(step.category).or_insert(Category {
+            total: 0.0,
+            count: 0,
+        });
+        category.total += step.duration;
+        category.count += 1;
+    }
+    Timings {
+        duration: run_start().elapsed().as_secs_f64(),
+        categories,
+        steps,
+    }
+}
+
+/// Print the time spent in each kind of step, and in each temp project.
+pub fn print() {
+    let timings = timings();
+    eprintln!("Timings ({:.1}s total):", timings.duration);
+    for (name, category) in &timings.categories {
+        eprintln!(
+            "  {:<10} {:>8.1}s  ({} steps)",
+            name, category.total, category.count
+        );
+        // There are a few of these, one for each temp project.
+        if (*name == "resolve" || *name == "fetch") && category.count > 1 {
+            for step in timings.steps.iter().filter(|step| step.category == *name) {
+                eprintln!("    {:>17.1}s  {}", step.duration, step.label);
+            }
+        }
+    }
+}
+
+/// Write the timings to a file, as HTML if the name ends with `.html` and
+/// as JSON otherwise.
+pub fn write(path: &Path) -> Fallible<()> {
+    let timings = timings();
+    let contents = if path.extension().is_some_and(|ext| ext == "html") {
+        html(&timings)
+    } else {
+        serde_json::to_string_pretty(&timings)?
+    };
+    fs::write(path, contents).with_context(|_| format!("Failed to write `{}`.", path.display()))?;
+    Ok(())
+}
+
+/// Prints the timings when dropped, and writes them to `path` if given, so
+/// that they are shown however the run ends.
+pub struct Report {
+    pub path: Option<PathBuf>,
+}
+
+impl Drop for Report {
+    fn drop(&mut self) {
+        print();
+        if let Some(path) = &self.path {
+            if let Err(e) = write(path) {
+                warn!("failed to write timings: {}", e);
+            }
+        }
+    }
+}
+
+fn html(timings: &Timings) -> String {
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    out.push_str("<title>cargo-prefetch timings</title>\n");
+    out.push_str(
+        "<style>td { padding: 0 1em; } .bar { background: #4a90d9; height: 1em; }</style>\n",
+    );
+    out.push_str("</head>\n<body>\n");
+    let _ = writeln!(out, "<h1>Timings ({:.1}s total)</h1>", timings.duration);
+    out.push_str("<table>\n<tr><th>Category</th><th>Time</th><th>Steps</th></tr>\n");
+    for (name, category) in &timings.categories {
+        let _ = writeln!(
+            out,
+            "<tr><td>{}</td><td>{:.1}s</td><td>{}</td></tr>",
+            name, category.total, category.count
+        );
+    }
+    out.push_str("</table>\n<h2>Steps</h2>\n<table>\n");
+    out.push_str("<tr><th>Category</th><th>Step</th><th>Start</th><th>Time</th><th></th></tr>\n");
+    let scale = if timings.duration > 0.0 {
+        100.0 / timings.duration
+    } else {
+        0.0
+    };
+    for step in &timings.steps {
+        let _ = writeln!(
+            out,
+            "<tr><td>{}</td><td>{}</td><td>{:.1}s</td><td>{:.1}s</td>\
+             <td style=\"width: 40em\"><div class=\"bar\" \
+             style=\"margin-left: {:.2}%; width: {:.2}%\"></div></td></tr>",
+            step.category,
+            escape(&step.label),
+            step.start,
+            step.duration,
+            step.start * scale,
+            (step.duration * scale).max(0.1)
+        );
+    }
+    out.push_str("</table>\n</body>\n</html>\n");
+    out
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
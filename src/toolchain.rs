@@ -0,0 +1,107 @@
+//! Detection of the Rust version that a project is built with, from its
+//! `rust-toolchain.toml` or `rust-toolchain` file.
+
+use failure::{Fallible, ResultExt};
+use serde_derive::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize)]
+struct ToolchainFile {
+    toolchain: Toolchain,
+}
+
+#[derive(Deserialize)]
+struct Toolchain {
+    channel: Option<String>,
+}
+
+/// Find the toolchain file that applies to `dir` the same way rustup does,
+/// and return its path and the Rust version that it names.
+///
+/// Returns `None` if there is no toolchain file, or if its channel isn't a
+/// version (such as `stable` or `nightly`).
+pub fn find_rust_version(dir: &Path) -> Fallible<Option<(PathBuf, String)>> {
+    for dir in dir.ancestors() {
+        // rustup uses `rust-toolchain` if both are present.
+        for name in &["rust-toolchain", "rust-toolchain.toml"] {
+            let path = dir.join(name);
+            if !path.is_file() {
+                continue;
+            }
+            let contents = fs::read_to_string(&path)
+                .with_context(|_| format!("Failed to read `{}`.", path.display()))?;
+            let channel = parse_channel(&contents)
+                .with_context(|_| format!("Failed to parse `{}`.", path.display()))?;
+            return Ok(channel
+                .filter(|channel| is_version(channel))
+                .map(|version| (path, version)));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the channel from the contents of a toolchain file.
+fn parse_channel(contents: &str) -> Fallible<Option<String>> {
+    let trimmed = contents.trim();
+    // The legacy format is just the name of the channel.
+    if trimmed.lines().count() == 1 && !trimmed.starts_with('[') && !trimmed.contains('=') {
+        return Ok(Some(trimmed.to_string()));
+    }
+    let file: ToolchainFile = toml::from_str(contents)?;
+    Ok(file.toolchain.channel)
+}
+
+/// Returns whether the value is a Rust version such as `1.70` or `1.70.0`.
+pub fn is_version(value: &str) -> bool {
+    let parts: Vec<&str> = value.split('.').collect();
+    (parts.len() == 2 || parts.len() == 3)
+        && parts
+            .iter()
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channels() {
+        let channel = |contents| parse_channel(contents).unwrap();
+        assert_eq!(channel("1.70.0\n").as_deref(), Some("1.70.0"));
+        assert_eq!(
+            channel("[toolchain]\nchannel = \"1.70\"\ncomponents = [\"clippy\"]\n").as_deref(),
+            Some("1.70")
+        );
+        assert_eq!(channel("[toolchain]\nprofile = \"minimal\"\n"), None);
+        assert!(parse_channel("[toolchain\n").is_err());
+        assert!(is_version("1.70"));
+        assert!(is_version("1.70.0"));
+        assert!(!is_version("1"));
+        assert!(!is_version("stable"));
+        assert!(!is_version("nightly-2024-01-01"));
+        assert!(!is_version("1.70.0-x86_64-unknown-linux-gnu"));
+    }
+
+    #[test]
+    fn find() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("a").join("b");
+        fs::create_dir_all(&nested).unwrap();
+        let toml = dir.path().join("rust-toolchain.toml");
+        fs::write(&toml, "[toolchain]\nchannel = \"1.70\"\n").unwrap();
+        assert_eq!(
+            find_rust_version(&nested).unwrap(),
+            Some((toml, "1.70".to_string()))
+        );
+        // The closest file is used, even if it doesn't name a version.
+        fs::write(nested.join("rust-toolchain"), "stable\n").unwrap();
+        assert_eq!(find_rust_version(&nested).unwrap(), None);
+        let legacy = dir.path().join("a").join("rust-toolchain");
+        fs::write(&legacy, "1.65.0\n").unwrap();
+        assert_eq!(
+            find_rust_version(&dir.path().join("a")).unwrap(),
+            Some((legacy, "1.65.0".to_string()))
+        );
+    }
+}
@@ -0,0 +1,181 @@
+//! `--tools`, for prefetching what `cargo install --offline` needs for a
+//! team's pinned tools.
+//!
+//! The file lists the tools in a `[tools]` table:
+//!
+//! ```toml
+//! [tools]
+//! cargo-nextest = "0.9.72"
+//! cargo-deny = { version = "0.14", locked = true }
+//! ```
+//!
+//! A bare version is an exact requirement, as it is for `cargo install
+//! --version`, so `0.9.72` means `=0.9.72`.
+//!
+//! A `Cargo.toml` with a `[package.metadata.bin]` or
+//! `[workspace.metadata.bin]` table, as used by `cargo-run-bin`, works too.
+//!
+//! `cargo install` resolves a tool as the root of its own workspace, which
+//! includes its dev-dependencies, so it can need more than the tool does as
+//! a dependency. Each tool is downloaded and unpacked, and resolved with
+//! `cargo generate-lockfile`, or with its own `Cargo.lock` when it is
+//! `locked`, like `cargo install --locked`. Every package of that is pinned.
+
+use crate::download;
+use crate::http::Http;
+use crate::index;
+use crate::{cache, is_crates_io, load_lockfile, mktemp, CrateSet, Options};
+use failure::{bail, format_err, Fallible, ResultExt};
+use flate2::read::GzDecoder;
+use std::fs::{self, File};
+use std::path::Path;
+use tracing::{debug, info};
+
+/// A tool to install.
+pub struct Tool {
+    pub name: String,
+    /// The version requirement, if one was given.
+    pub version: Option<String>,
+    /// Whether it is installed with `--locked`.
+    pub locked: bool,
+}
+
+/// Read the tools from a file.
+pub fn parse(path: &Path) -> Fallible<Vec<Tool>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|_| format!("Failed to read `{}`.", path.display()))?;
+    let manifest: toml::Value = toml::from_str(&contents)
+        .with_context(|_| format!("Failed to parse `{}`.", path.display()))?;
+    let metadata_bin = |key: &str| {
+        manifest
+            .get(key)
+            .and_then(|t| t.get("metadata"))
+            .and_then(|t| t.get("bin"))
+    };
+    let table = manifest
+        .get("tools")
+        .or_else(|| metadata_bin("package"))
+        .or_else(|| metadata_bin("workspace"))
+        .and_then(|t| t.as_table())
+        .ok_or_else(|| {
+            format_err!(
+                "`{}` has no `[tools]` or `[package.metadata.bin]` table",
+                path.display()
+            )
+        })?;
+    let mut tools = Vec::new();
+    for (name, value) in table {
+        let (version, locked) = match value {
+            toml::Value::String(version) => (Some(version.clone()), false),
+            toml::Value::Table(t) => (
+                t.get("version").and_then(|v| v.as_str()).map(String::from),
+                t.get("locked").and_then(|v| v.as_bool()).unwrap_or(false),
+            ),
+            _ => bail!(
+                "expected a version or a table for `{}` in `{}`",
+                name,
+                path.display()
+            ),
+        };
+        tools.push(Tool {
+            name: name.clone(),
+            version,
+            locked,
+        });
+    }
+    Ok(tools)
+}
+
+/// Returns every package that `cargo install` needs for a tool, pinned to
+/// their versions.
+pub fn crates(options: &Options, http: &Http, tool: &Tool) -> Fallible<CrateSet> {
+    let req = match &tool.version {
+        Some(version) => version_req(version).map_err(|e| {
+            format_err!(
+                "invalid version requirement `{}` for `{}`: {}",
+                version,
+                tool.name,
+                e
+            )
+        })?,
+        None => semver::VersionReq::STAR,
+    };
+    // Like `cargo install`, prereleases are only selected if asked for.
+    let entry = index::entries(http, &tool.name)?
+        .into_iter()
+        .filter(|entry| !entry.yanked)
+        .filter_map(|entry| Some((semver::Version::parse(&entry.vers).ok()?, entry)))
+        .filter(|(version, _)| req.matches(version))
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, entry)| entry)
+        .ok_or_else(|| {
+            format_err!(
+                "no version of `{}` matches `{}`",
+                tool.name,
+                tool.version.as_deref().unwrap_or("*")
+            )
+        })?;
+    info!(
+        "Resolving `{}` v{} as `cargo install` would",
+        tool.name, entry.vers
+    );
+    let path = {
+        let _lock = cache::lock_package_cache(false)?;
+        download::download_crate(http, &tool.name, &entry.vers, &entry.cksum)?
+    };
+    let dir = mktemp(options)?;
+    tar::Archive::new(GzDecoder::new(File::open(&path)?))
+        .unpack(dir.path())
+        .with_context(|_| format!("Failed to unpack `{}`.", path.display()))?;
+    let root = dir.path().join(format!("{}-{}", tool.name, entry.vers));
+    let lockfile = root.join("Cargo.lock");
+    if !(tool.locked && lockfile.exists()) {
+        debug!("Running: cargo generate-lockfile");
+        let output = options
+            .cargo
+            .command("generate-lockfile")
+            .current_dir(&root)
+            .output()
+            .with_context(|_| "Failed to launch `cargo`.")?;
+        if !output.status.success() {
+            bail!(
+                "failed to resolve `{}` v{}:\n{}",
+                tool.name,
+                entry.vers,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+    }
+    let mut crates: CrateSet = load_lockfile(&lockfile)?
+        .into_iter()
+        .filter(|pkg| pkg.source.as_deref().is_some_and(is_crates_io))
+        .map(|pkg| (pkg.name, Some(format!("={}", pkg.version))))
+        .collect();
+    crates.insert((tool.name.clone(), Some(format!("={}", entry.vers))));
+    Ok(crates)
+}
+
+/// Parse a tool's version requirement, where a bare version such as
+/// `0.9.72` means exactly that version, as it does for `cargo install`.
+fn version_req(version: &str) -> Result<semver::VersionReq, semver::Error> {
+    match semver::Version::parse(version.trim()) {
+        Ok(_) => semver::VersionReq::parse(&format!("={}", version.trim())),
+        Err(_) => semver::VersionReq::parse(version),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_reqs() {
+        let req = |v: &str| version_req(v).unwrap().to_string();
+        assert_eq!(req("0.9.72"), "=0.9.72");
+        assert_eq!(req("1.0.0-beta.2"), "=1.0.0-beta.2");
+        assert_eq!(req("0.14"), "^0.14");
+        assert_eq!(req("^0.9.72"), "^0.9.72");
+        assert_eq!(req(">=1.2, <2"), ">=1.2, <2");
+        assert!(version_req("latest").is_err());
+    }
+}
@@ -1,4 +1,5 @@
-pub static TOP_CRATES: [&'static str; 1000] = [
+#[rustfmt::skip]
+pub static TOP_CRATES: [&str; 1000] = [
     "serde", // 4268
     "serde_derive", // 3230
     "serde_json", // 3079
@@ -0,0 +1,69 @@
+//! Cargo's global cache tracker.
+//!
+//! Newer versions of Cargo record when each file in the cache was last used
+//! in `$CARGO_HOME/.global-cache`, and automatically remove files that
+//! haven't been used in a while. Prefetched crates haven't been used yet, so
+//! they are marked as used when they are fetched to keep them from being
+//! removed before they are needed.
+//!
+//! Cargo only changes the database while holding the package cache lock, so
+//! the lock is held while writing to it too.
+
+use crate::cache;
+use crate::prefetch_lock::LockedPackage;
+use failure::Fallible;
+use rusqlite::{params, Connection};
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Mark the `.crate` files of the given crates.io packages in a Cargo home
+/// as used now.
+///
+/// This does nothing if Cargo hasn't created the tracker database there.
+pub fn mark_used(home: &Path, pkgs: &[LockedPackage]) -> Fallible<()> {
+    let path = home.join(".global-cache");
+    if !path.exists() {
+        return Ok(());
+    }
+    // Other homes get the same registry directory when packages are copied
+    // into them.
+    let registry = cache::crates_io_cache_dir()?
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .into_owned();
+    let cache_dir = home.join("registry").join("cache").join(&registry);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+
+    let _lock = cache::lock_package_cache_in(home, false)?;
+
+    let mut conn = Connection::open(&path)?;
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT INTO registry_index (name, timestamp) VALUES (?1, ?2)
+         ON CONFLICT (name) DO UPDATE SET timestamp = excluded.timestamp",
+        params![registry, now],
+    )?;
+    let registry_id: i64 = tx.query_row(
+        "SELECT id FROM registry_index WHERE name = ?1",
+        params![registry],
+        |row| row.get(0),
+    )?;
+    {
+        let mut insert = tx.prepare(
+            "INSERT INTO registry_crate (registry_id, name, size, timestamp)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (registry_id, name) DO UPDATE
+             SET size = excluded.size, timestamp = excluded.timestamp",
+        )?;
+        for pkg in pkgs {
+            let name = format!("{}-{}.crate", pkg.name, pkg.version);
+            if let Ok(metadata) = fs::metadata(cache_dir.join(&name)) {
+                insert.execute(params![registry_id, name, metadata.len() as i64, now])?;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
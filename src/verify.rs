@@ -0,0 +1,145 @@
+//! The `verify` subcommand for checking the integrity of the cache.
+//!
+//! Each `.crate` file is hashed and compared with the checksum in the index.
+//! Checksums come from Cargo's local copy of the index when possible, and
+//! otherwise from the crates.io sparse index.
+//!
+//! With `--repair`, corrupt files are downloaded again, and stray partial
+//! downloads are removed.
+
+use crate::clean;
+use cargo_prefetch::cache::{self, CachedCrate};
+use cargo_prefetch::download;
+use cargo_prefetch::http::Http;
+use cargo_prefetch::index::{self, IndexEntry};
+use clap::ArgMatches;
+use failure::{bail, Fallible};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use tracing::{error, info, warn};
+
+/// A cached crate that doesn't match the index.
+pub struct Corrupt {
+    pub krate: CachedCrate,
+    /// The checksum from the index.
+    pub cksum: String,
+}
+
+pub fn verify(http: &Http, matches: &ArgMatches<'_>) -> Fallible<()> {
+    if matches.is_present("repair") {
+        return repair(http);
+    }
+    let corrupt = find_corrupt(http)?;
+    for c in &corrupt {
+        println!(
+            "corrupt: {} (expected checksum {})",
+            c.krate.path.display(),
+            c.cksum
+        );
+    }
+    if !corrupt.is_empty() {
+        bail!("found {} corrupt files in the cache", corrupt.len());
+    }
+    Ok(())
+}
+
+/// Download corrupt files again, and remove partial downloads.
+fn repair(http: &Http) -> Fallible<()> {
+    let _lock = cache::lock_package_cache(true)?;
+    for dir in cache::registry_cache_dirs()? {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "partial") {
+                println!("removing partial download: {}", path.display());
+                fs::remove_file(&path)?;
+            }
+        }
+    }
+    let mut failed = 0;
+    for c in find_corrupt(http)? {
+        clean::remove_crate(&c.krate)?;
+        let registry = c.krate.path.parent().unwrap().file_name().unwrap();
+        if !registry.to_string_lossy().starts_with("index.crates.io-") {
+            // Only crates.io is known, but Cargo will download it again
+            // when it is needed.
+            println!("removed: {}", c.krate.path.display());
+            continue;
+        }
+        match download::download_crate(http, &c.krate.name, &c.krate.version, &c.cksum) {
+            Ok(path) => println!("repaired: {}", path.display()),
+            Err(e) => {
+                error!(
+                    "failed to download {}-{}: {}",
+                    c.krate.name, c.krate.version, e
+                );
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("failed to repair {} files", failed);
+    }
+    Ok(())
+}
+
+/// Check every cached crate, and return the ones that are corrupt.
+pub fn find_corrupt(http: &Http) -> Fallible<Vec<Corrupt>> {
+    let mut corrupt = Vec::new();
+    let mut ok = 0;
+    let mut unknown = 0;
+    for dir in cache::registry_cache_dirs()? {
+        let registry = dir.file_name().unwrap().to_string_lossy().into_owned();
+        let is_crates_io = registry.starts_with("index.crates.io-");
+        let mut entries: HashMap<String, Vec<IndexEntry>> = HashMap::new();
+        for krate in cache::cached_crates(&dir)? {
+            if !entries.contains_key(&krate.name) {
+                let mut found = index::read_local_cache(&registry, &krate.name)?;
+                let has_version = |entries: &Option<Vec<IndexEntry>>| {
+                    entries
+                        .iter()
+                        .flatten()
+                        .any(|entry| entry.vers == krate.version)
+                };
+                if !has_version(&found) && is_crates_io {
+                    found = match index::entries(http, &krate.name) {
+                        Ok(entries) => Some(entries),
+                        Err(e) => {
+                            warn!("{}", e);
+                            None
+                        }
+                    };
+                }
+                entries.insert(krate.name.clone(), found.unwrap_or_default());
+            }
+            let cksum = entries[&krate.name]
+                .iter()
+                .find(|entry| entry.vers == krate.version)
+                .map(|entry| entry.cksum.clone());
+            let cksum = match cksum {
+                Some(cksum) => cksum,
+                None => {
+                    warn!(
+                        "no checksum found for {}-{} in {}",
+                        krate.name, krate.version, registry
+                    );
+                    unknown += 1;
+                    continue;
+                }
+            };
+            let actual = format!("{:x}", Sha256::digest(fs::read(&krate.path)?));
+            if actual == cksum {
+                ok += 1;
+            } else {
+                corrupt.push(Corrupt { krate, cksum });
+            }
+        }
+    }
+    info!(
+        "{} ok, {} corrupt, {} without a checksum",
+        ok,
+        corrupt.len(),
+        unknown
+    );
+    Ok(corrupt)
+}
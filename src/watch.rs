@@ -0,0 +1,93 @@
+//! `--watch`, for fetching again whenever a lockfile changes.
+//!
+//! Files are checked by polling their modification times, and each fetch runs
+//! `cargo prefetch` again without `--watch`, so a failed fetch doesn't stop
+//! the watch.
+
+use cargo_prefetch::sources;
+use failure::{bail, Fallible, ResultExt};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+/// How often the files are checked.
+const POLL: Duration = Duration::from_secs(1);
+
+/// How long the files must stay unchanged before fetching, since editors and
+/// Cargo may write them several times.
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Fetch, and then fetch again each time one of the lockfiles changes.
+pub fn watch(lockfiles: &[&str]) -> Fallible<()> {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for lockfile in lockfiles {
+        if sources::is_url(lockfile) {
+            warn!("`{}` is a URL, it won't be watched for changes", lockfile);
+            continue;
+        }
+        let path = PathBuf::from(lockfile);
+        // Adding a dependency to the manifest doesn't update the lockfile
+        // until the next build, so watch the manifest too.
+        let manifest = path.with_file_name("Cargo.toml");
+        paths.push(path);
+        if manifest.exists() {
+            paths.push(manifest);
+        }
+    }
+    if paths.is_empty() {
+        bail!("--watch requires a --lockfile that is a local path");
+    }
+    let exe = env::current_exe().with_context(|_| "Failed to find the current executable.")?;
+    let args: Vec<_> = env::args_os()
+        .skip(1)
+        .filter(|arg| arg != "--watch")
+        .collect();
+    let mut last = modified(&paths);
+    loop {
+        match Command::new(&exe).args(&args).status() {
+            Ok(status) if !status.success() => warn!("fetch failed: {}", status),
+            Ok(_) => {}
+            Err(e) => warn!("failed to run `{}`: {}", exe.display(), e),
+        }
+        info!("Watching {} for changes", describe(&paths));
+        loop {
+            thread::sleep(POLL);
+            let current = modified(&paths);
+            if current != last {
+                last = settle(&paths, current);
+                break;
+            }
+        }
+    }
+}
+
+/// Wait until the files stop changing, and return their modification times.
+fn settle(paths: &[PathBuf], mut current: Vec<Option<SystemTime>>) -> Vec<Option<SystemTime>> {
+    loop {
+        thread::sleep(DEBOUNCE);
+        let next = modified(paths);
+        if next == current {
+            return next;
+        }
+        current = next;
+    }
+}
+
+fn modified(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+fn describe(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| format!("`{}`", Path::display(path)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}